@@ -1,22 +1,42 @@
 use crate::compression;
 use crate::str::StrExt;
 
-use byteorder::{BigEndian, ReadBytesExt};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use std::collections::HashMap;
 use std::convert::TryFrom;
 
 use crate::errors::FileSystemError;
+use crate::filesystem::MAX_ENTRY_SIZE;
 use std::io::{Cursor, Read};
 
 pub const ARCHIVE_HEADER_SIZE: usize = 6;
 
+/// How the data of an `Archive` is compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveCompression {
+    /// The entry table and every entry are compressed together with BZIP2.
+    Whole,
+    /// Every entry is compressed with BZIP2 on its own.
+    Entries,
+}
+
 #[derive(Debug)]
 pub struct Archive {
     entries: HashMap<i32, ArchiveEntry>,
-    header: ArchiveHeader,
+    // the order of the entries in the entry table
+    identifiers: Vec<i32>,
+    compression: ArchiveCompression,
 }
 
 impl Archive {
+    pub fn new(compression: ArchiveCompression) -> Self {
+        Archive {
+            entries: HashMap::new(),
+            identifiers: Vec::new(),
+            compression,
+        }
+    }
+
     pub fn entry_name(&self, name: &str) -> Option<&ArchiveEntry> {
         self.entries.get(&name.name_hash())
     }
@@ -26,7 +46,96 @@ impl Archive {
     }
 
     pub fn entry_count(&self) -> usize {
-        self.entries.iter().count()
+        self.entries.len()
+    }
+
+    /// Iterates over the entries in the order of the archive's entry table.
+    pub fn entries(&self) -> impl Iterator<Item = &ArchiveEntry> {
+        self.identifiers
+            .iter()
+            .map(move |identifier| &self.entries[identifier])
+    }
+
+    pub fn compression(&self) -> ArchiveCompression {
+        self.compression
+    }
+
+    pub fn set_compression(&mut self, compression: ArchiveCompression) {
+        self.compression = compression;
+    }
+
+    /// Inserts an entry under the hash of `name`, returning the entry it replaced.
+    ///
+    /// A replaced entry keeps its position in the entry table, new entries are appended to it.
+    pub fn insert(&mut self, name: &str, data: Vec<u8>) -> Option<ArchiveEntry> {
+        self.insert_hash(name.name_hash(), data)
+    }
+
+    pub fn insert_hash(&mut self, identifier: i32, data: Vec<u8>) -> Option<ArchiveEntry> {
+        let size = data.len() as u32;
+        let entry = ArchiveEntry {
+            identifier,
+            uncompressed_size: size,
+            compressed_size: size,
+            uncompressed_data: data,
+        };
+        let replaced = self.entries.insert(identifier, entry);
+        if replaced.is_none() {
+            self.identifiers.push(identifier);
+        }
+        replaced
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<ArchiveEntry> {
+        self.remove_hash(name.name_hash())
+    }
+
+    pub fn remove_hash(&mut self, identifier: i32) -> Option<ArchiveEntry> {
+        let removed = self.entries.remove(&identifier);
+        if removed.is_some() {
+            self.identifiers.retain(|id| *id != identifier);
+        }
+        removed
+    }
+
+    /// Encodes the archive into the format read by `Archive::try_from`, compressing it the way
+    /// its `ArchiveCompression` describes.
+    pub fn encode(&self) -> Result<Vec<u8>, FileSystemError> {
+        let entry_count = u16::try_from(self.identifiers.len())
+            .map_err(|_| FileSystemError::TooManyArchiveEntries(self.identifiers.len()))?;
+        let mut body: Vec<u8> = Vec::new();
+        body.write_u16::<BigEndian>(entry_count)?;
+        let mut entry_data: Vec<u8> = Vec::new();
+        for entry in self.entries() {
+            let data = match self.compression {
+                ArchiveCompression::Whole => entry.uncompressed_data.clone(),
+                ArchiveCompression::Entries => {
+                    compression::compress_bzip2(&entry.uncompressed_data)?
+                }
+            };
+            let uncompressed_size = entry.uncompressed_data.len();
+            if uncompressed_size > MAX_ENTRY_SIZE || data.len() > MAX_ENTRY_SIZE {
+                return Err(FileSystemError::EntryTooLarge(uncompressed_size));
+            }
+            body.write_i32::<BigEndian>(entry.identifier)?;
+            body.write_u24::<BigEndian>(uncompressed_size as u32)?;
+            body.write_u24::<BigEndian>(data.len() as u32)?;
+            entry_data.extend_from_slice(&data);
+        }
+        body.extend_from_slice(&entry_data);
+
+        let decompressed_size = body.len();
+        if self.compression == ArchiveCompression::Whole {
+            body = compression::compress_bzip2(&body)?;
+        }
+        if decompressed_size > MAX_ENTRY_SIZE || body.len() > MAX_ENTRY_SIZE {
+            return Err(FileSystemError::EntryTooLarge(decompressed_size));
+        }
+        let mut buffer = Vec::with_capacity(ARCHIVE_HEADER_SIZE + body.len());
+        buffer.write_u24::<BigEndian>(decompressed_size as u32)?;
+        buffer.write_u24::<BigEndian>(body.len() as u32)?;
+        buffer.extend_from_slice(&body);
+        Ok(buffer)
     }
 }
 
@@ -95,7 +204,16 @@ impl TryFrom<Vec<u8>> for Archive {
                 },
             );
         }
-        Ok(Archive { entries, header })
+        let compression = if extracted {
+            ArchiveCompression::Whole
+        } else {
+            ArchiveCompression::Entries
+        };
+        Ok(Archive {
+            entries,
+            identifiers,
+            compression,
+        })
     }
 }
 
@@ -157,4 +275,78 @@ impl ArchiveEntry {
     pub fn identifier(&self) -> i32 {
         self.identifier
     }
+
+    pub fn uncompressed_size(&self) -> u32 {
+        self.uncompressed_size
+    }
+
+    /// The size of the entry's data as it was stored in the archive it was decoded from.
+    pub fn compressed_size(&self) -> u32 {
+        self.compressed_size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::archive::{Archive, ArchiveCompression};
+    use crate::errors::FileSystemError;
+    use std::convert::TryFrom;
+
+    fn archive(compression: ArchiveCompression) -> Archive {
+        let mut archive = Archive::new(compression);
+        archive.insert("obj.dat", b"item definitions".to_vec());
+        archive.insert("obj.idx", vec![0; 600]);
+        archive.insert("npc.dat", b"npc definitions".to_vec());
+        archive
+    }
+
+    fn assert_round_trip(compression: ArchiveCompression) {
+        let original = archive(compression);
+        let decoded = Archive::try_from(original.encode().unwrap()).unwrap();
+        assert_eq!(decoded.compression(), compression);
+        assert_eq!(decoded.entry_count(), 3);
+        let identifiers: Vec<i32> = decoded.entries().map(|e| e.identifier()).collect();
+        let expected: Vec<i32> = original.entries().map(|e| e.identifier()).collect();
+        assert_eq!(identifiers, expected);
+        for entry in original.entries() {
+            let decoded_entry = decoded.entry_hash(entry.identifier()).unwrap();
+            assert_eq!(decoded_entry.uncompressed_data(), entry.uncompressed_data());
+        }
+    }
+
+    #[test]
+    fn test_whole_archive_round_trip() {
+        assert_round_trip(ArchiveCompression::Whole);
+    }
+
+    #[test]
+    fn test_entries_archive_round_trip() {
+        assert_round_trip(ArchiveCompression::Entries);
+    }
+
+    #[test]
+    fn test_insert_and_remove() {
+        let mut archive = archive(ArchiveCompression::Whole);
+        let replaced = archive.insert("obj.dat", b"edited".to_vec()).unwrap();
+        assert_eq!(replaced.uncompressed_data(), b"item definitions");
+        assert!(archive.remove("obj.idx").is_some());
+        assert!(archive.remove("obj.idx").is_none());
+        let decoded = Archive::try_from(archive.encode().unwrap()).unwrap();
+        let names: Vec<&[u8]> = decoded.entries().map(|e| e.uncompressed_data()).collect();
+        assert_eq!(names, vec![&b"edited"[..], &b"npc definitions"[..]]);
+    }
+
+    #[test]
+    fn test_too_many_entries() {
+        let mut archive = Archive::new(ArchiveCompression::Whole);
+        for identifier in 0..u16::MAX as i32 {
+            archive.insert_hash(identifier, Vec::new());
+        }
+        assert!(archive.encode().is_ok());
+        archive.insert_hash(-1, Vec::new());
+        assert!(matches!(
+            archive.encode(),
+            Err(FileSystemError::TooManyArchiveEntries(65536))
+        ));
+    }
 }
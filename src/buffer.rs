@@ -0,0 +1,96 @@
+use std::io::{self, Read, Write};
+
+/// The byte that ends a string in the cache.
+pub const STRING_TERMINATOR: u8 = 10;
+
+/// Reading the data types of the cache that `byteorder` has no methods for.
+pub trait BufferReadExt: Read {
+    /// Reads a string that ends with a newline. Every byte is one ISO-8859-1 character.
+    fn read_string(&mut self) -> Result<String, io::Error> {
+        let mut string = String::new();
+        loop {
+            let mut byte = [0; 1];
+            self.read_exact(&mut byte)?;
+            if byte[0] == STRING_TERMINATOR {
+                return Ok(string);
+            }
+            string.push(byte[0] as char);
+        }
+    }
+
+    /// Reads a value between -64 and 63 from one byte, or between -16384 and 16383 from two
+    /// bytes with the high bit of the first one set.
+    fn read_signed_smart(&mut self) -> Result<i32, io::Error> {
+        let mut first = [0; 1];
+        self.read_exact(&mut first)?;
+        if first[0] < 128 {
+            return Ok(i32::from(first[0]) - 64);
+        }
+        let mut second = [0; 1];
+        self.read_exact(&mut second)?;
+        Ok(i32::from(u16::from_be_bytes([first[0], second[0]])) - 49152)
+    }
+}
+
+impl<R: Read + ?Sized> BufferReadExt for R {}
+
+/// Writing the data types of the cache that `byteorder` has no methods for.
+pub trait BufferWriteExt: Write {
+    /// Writes a string followed by a newline. Characters outside of ISO-8859-1 become `?`.
+    fn write_string(&mut self, string: &str) -> Result<(), io::Error> {
+        let bytes: Vec<u8> = string
+            .chars()
+            .map(|c| if (c as u32) < 256 { c as u8 } else { b'?' })
+            .chain(std::iter::once(STRING_TERMINATOR))
+            .collect();
+        self.write_all(&bytes)
+    }
+
+    /// Writes a value between -64 and 63 in one byte, or between -16384 and 16383 in two.
+    fn write_signed_smart(&mut self, value: i32) -> Result<(), io::Error> {
+        match value {
+            -64..=63 => self.write_all(&[(value + 64) as u8]),
+            -16384..=16383 => self.write_all(&((value + 49152) as u16).to_be_bytes()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} does not fit in a signed smart", value),
+            )),
+        }
+    }
+}
+
+impl<W: Write + ?Sized> BufferWriteExt for W {}
+
+#[cfg(test)]
+mod tests {
+    use crate::buffer::{BufferReadExt, BufferWriteExt};
+
+    #[test]
+    fn test_string_round_trip() {
+        let mut buffer = Vec::new();
+        buffer.write_string("Abyssal whip").unwrap();
+        buffer.write_string("£5").unwrap();
+        assert_eq!(buffer[..13], b"Abyssal whip\n"[..]);
+        let mut reader = &buffer[..];
+        assert_eq!(reader.read_string().unwrap(), "Abyssal whip");
+        assert_eq!(reader.read_string().unwrap(), "£5");
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn test_signed_smart_round_trip() {
+        let mut reader = &[0x00, 0x7F, 0x80, 0x00, 0xFF, 0xFF][..];
+        assert_eq!(reader.read_signed_smart().unwrap(), -64);
+        assert_eq!(reader.read_signed_smart().unwrap(), 63);
+        assert_eq!(reader.read_signed_smart().unwrap(), -16384);
+        assert_eq!(reader.read_signed_smart().unwrap(), 16383);
+        assert!(reader.read_signed_smart().is_err());
+
+        let mut buffer = Vec::new();
+        for value in [-64, 63, 64, -16384, 16383].iter() {
+            buffer.write_signed_smart(*value).unwrap();
+        }
+        assert_eq!(buffer, [0x00, 0x7F, 0xC0, 0x40, 0x80, 0x00, 0xFF, 0xFF]);
+        assert!(buffer.write_signed_smart(16384).is_err());
+    }
+}
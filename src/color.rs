@@ -0,0 +1,116 @@
+/// The brightness the client starts with. The brightness settings go from 0.9 for the darkest to
+/// 0.6 for the brightest.
+pub const DEFAULT_BRIGHTNESS: f64 = 0.8;
+
+/// Raises every channel of a colour to the power of `exponent`, the way the client adjusts the
+/// brightness of its colours.
+pub fn apply_brightness(color: u32, exponent: f64) -> u32 {
+    let channel = |shift: u32| {
+        let value = f64::from((color >> shift) & 0xFF) / 256.0;
+        (value.powf(exponent) * 256.0) as u32
+    };
+    (channel(16) << 16) + (channel(8) << 8) + channel(0)
+}
+
+/// Converts a 16 bit HSL colour of models and the minimap into `0xRRGGBB` at the given
+/// brightness, the same way the client fills its colour table.
+///
+/// The hue takes the upper 6 bits, the saturation the next 3 and the lightness the lower 7. Like
+/// the client, black comes out as 1 so it stays visible.
+pub fn hsl16_to_rgb(hsl: u16, brightness: f64) -> u32 {
+    let hue = f64::from(hsl >> 10) / 64.0 + 0.0078125;
+    let saturation = f64::from((hsl >> 7) & 7) / 8.0 + 0.0625;
+    let lightness = f64::from(hsl & 0x7F) / 128.0;
+    let upper = if lightness < 0.5 {
+        lightness * (1.0 + saturation)
+    } else {
+        lightness + saturation - lightness * saturation
+    };
+    let lower = 2.0 * lightness - upper;
+    let channel = |mut hue: f64| {
+        if hue > 1.0 {
+            hue -= 1.0;
+        } else if hue < 0.0 {
+            hue += 1.0;
+        }
+        let value = if 6.0 * hue < 1.0 {
+            lower + (upper - lower) * 6.0 * hue
+        } else if 2.0 * hue < 1.0 {
+            upper
+        } else if 3.0 * hue < 2.0 {
+            lower + (upper - lower) * (2.0 / 3.0 - hue) * 6.0
+        } else {
+            lower
+        };
+        (value * 256.0) as u32
+    };
+    let rgb = (channel(hue + 1.0 / 3.0) << 16) + (channel(hue) << 8) + channel(hue - 1.0 / 3.0);
+    apply_brightness(rgb, brightness).max(1)
+}
+
+/// Every colour of the client's colour table at one brightness, to find the 16 bit HSL colour
+/// that comes closest to a colour.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColorTable {
+    colors: Vec<u32>,
+}
+
+impl ColorTable {
+    pub fn new(brightness: f64) -> Self {
+        ColorTable {
+            colors: (0..=u16::MAX)
+                .map(|hsl| hsl16_to_rgb(hsl, brightness))
+                .collect(),
+        }
+    }
+
+    /// The `0xRRGGBB` colour of a 16 bit HSL colour.
+    pub fn rgb(&self, hsl: u16) -> u32 {
+        self.colors[hsl as usize]
+    }
+
+    /// The lowest 16 bit HSL colour that is closest to `rgb`.
+    pub fn nearest_hsl16(&self, rgb: u32) -> u16 {
+        let distance = |other: u32| -> i32 {
+            [16, 8, 0]
+                .iter()
+                .map(|shift| {
+                    let difference =
+                        ((rgb >> shift) & 0xFF) as i32 - ((other >> shift) & 0xFF) as i32;
+                    difference * difference
+                })
+                .sum()
+        };
+        self.colors
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, color)| distance(**color))
+            .map_or(0, |(hsl, _)| hsl as u16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::color::{self, ColorTable, DEFAULT_BRIGHTNESS};
+
+    #[test]
+    fn test_hsl16_to_rgb() {
+        // black stays visible
+        assert_eq!(color::hsl16_to_rgb(0, 1.0), 1);
+        // the lowest saturation still tints slightly red
+        assert_eq!(color::hsl16_to_rgb(127, 1.0), 0xFEFDFD);
+        assert_eq!(color::hsl16_to_rgb(64, DEFAULT_BRIGHTNESS), 0x9A8B8B);
+        // the highest saturation of red at half lightness
+        assert_eq!(color::hsl16_to_rgb(7 << 7 | 64, 1.0), 0xF81308);
+        assert_eq!(color::apply_brightness(0x808080, 1.4), 0x616161);
+    }
+
+    #[test]
+    fn test_nearest_hsl16() {
+        let table = ColorTable::new(DEFAULT_BRIGHTNESS);
+        assert_eq!(table.rgb(933), color::hsl16_to_rgb(933, DEFAULT_BRIGHTNESS));
+        let hsl = table.nearest_hsl16(table.rgb(933));
+        assert_eq!(table.rgb(hsl), table.rgb(933));
+        assert_eq!(table.nearest_hsl16(0), 0);
+    }
+}
@@ -49,7 +49,8 @@ pub fn decompress_gzip(compressed_data: Vec<u8>) -> Result<Vec<u8>, CompressionE
 }
 
 pub fn compress_bzip2(data: &[u8]) -> Result<Vec<u8>, io::Error> {
-    let mut encoder = BzEncoder::new(data, Compression::Default);
+    // the stripped header is always restored as BZh1, so the block size has to match it
+    let mut encoder = BzEncoder::new(data, Compression::Fastest);
     let mut result = Vec::with_capacity(data.len());
     encoder.read_to_end(&mut result)?;
     result.drain(0..BZIP2_HEADER_SIZE);
@@ -77,6 +78,14 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_bzip2_compression_multiple_blocks() {
+        let data: Vec<u8> = (0..250_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let compressed_data = compress_bzip2(&data).unwrap();
+        let decompressed_data = decompress_bzip2(compressed_data, data.len()).unwrap();
+        assert_eq!(decompressed_data, data);
+    }
+
     #[test]
     fn test_bzip2_compression() {
         let data = b"Hello world!";
@@ -0,0 +1,317 @@
+use crate::buffer::BufferReadExt;
+use crate::config::{self, Definition, END_OPCODE};
+use crate::errors::FileSystemError;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+// every opcode in the order new definitions are encoded in
+const OPCODES: &[u8] = &[1, 2, 3, 5, 6, 7];
+
+/// An underlay or overlay floor from `flo.dat`.
+///
+/// Every field holds the value as it is stored, and the opcodes are kept in the order they were
+/// stored in, so a decoded definition encodes back into the same bytes. The colours the client
+/// renders with are derived through `hsl`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FloorDefinition {
+    pub id: u32,
+    /// The opcodes the definition was decoded from, in their stored order. These are encoded
+    /// first, even when they hold the default value, followed by every other field that differs
+    /// from its default.
+    pub opcodes: Vec<u8>,
+    /// The colour of the floor as `0xRRGGBB`.
+    pub rgb: Option<u32>,
+    pub texture: Option<u8>,
+    /// Opcode 3, read but never used by the client.
+    pub unused_flag_3: bool,
+    /// Whether the floor hides the tiles beneath it.
+    pub occludes: bool,
+    pub name: Option<String>,
+    /// A colour as `0xRRGGBB` that only replaces the colour the client draws on the minimap.
+    pub minimap_rgb: Option<u32>,
+}
+
+/// The colour values the client derives from the colour of a floor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FloorHsl {
+    /// The hue between 0 and 255. Like the client it is not clamped, so reddish purples come out
+    /// slightly below 0.
+    pub hue: i32,
+    pub saturation: i32,
+    pub lightness: i32,
+    /// The hue multiplied by `chroma`, which the client uses to blend the hues of underlays.
+    pub blended_hue: i32,
+    /// The weight of the colour when the client blends underlays, at least 1. Floors with a
+    /// minimap colour get their blended hue here instead, see `FloorDefinition::hsl`.
+    pub chroma: i32,
+}
+
+impl FloorHsl {
+    /// Converts a colour the same way the client does when it loads a floor.
+    pub fn from_rgb(rgb: u32) -> Self {
+        let red = f64::from((rgb >> 16) & 0xFF) / 256.0;
+        let green = f64::from((rgb >> 8) & 0xFF) / 256.0;
+        let blue = f64::from(rgb & 0xFF) / 256.0;
+        let min = red.min(green).min(blue);
+        let max = red.max(green).max(blue);
+        let mut hue = 0.0;
+        let mut saturation = 0.0;
+        let lightness = (min + max) / 2.0;
+        if min != max {
+            saturation = if lightness < 0.5 {
+                (max - min) / (max + min)
+            } else {
+                (max - min) / (2.0 - max - min)
+            };
+            hue = if red == max {
+                (green - blue) / (max - min)
+            } else if green == max {
+                2.0 + (blue - red) / (max - min)
+            } else {
+                4.0 + (red - green) / (max - min)
+            };
+        }
+        hue /= 6.0;
+        let chroma = if lightness > 0.5 {
+            (1.0 - lightness) * saturation * 512.0
+        } else {
+            lightness * saturation * 512.0
+        };
+        let chroma = (chroma as i32).max(1);
+        FloorHsl {
+            hue: (hue * 256.0) as i32,
+            saturation: ((saturation * 256.0) as i32).clamp(0, 255),
+            lightness: ((lightness * 256.0) as i32).clamp(0, 255),
+            blended_hue: (hue * f64::from(chroma)) as i32,
+            chroma,
+        }
+    }
+
+    /// Packs the colour into the 16 bit HSL format of models and the minimap.
+    ///
+    /// The client moves the minimap colour of every floor by a small random amount before packing
+    /// it; this packs the colour as it is.
+    pub fn to_hsl16(&self) -> u16 {
+        let hue = self.hue.clamp(0, 255);
+        let mut saturation = self.saturation;
+        // the client desaturates light colours in steps
+        for threshold in [179, 192, 217, 243].iter() {
+            if self.lightness > *threshold {
+                saturation /= 2;
+            }
+        }
+        (((hue / 4) << 10) | ((saturation / 32) << 7) | (self.lightness / 2)) as u16
+    }
+}
+
+impl FloorDefinition {
+    pub fn new(id: u32) -> Self {
+        FloorDefinition {
+            id,
+            opcodes: Vec::new(),
+            rgb: None,
+            texture: None,
+            unused_flag_3: false,
+            occludes: true,
+            name: None,
+            minimap_rgb: None,
+        }
+    }
+
+    /// The colour values of the floor. Like the client, a floor without a colour has all of them
+    /// at 0.
+    ///
+    /// The client converts a minimap colour the same way and then restores every value but the
+    /// chroma, which it sets to the blended hue. A colour stored after the minimap colour
+    /// converts all of them again.
+    pub fn hsl(&self) -> FloorHsl {
+        let hsl = match self.rgb {
+            Some(rgb) => FloorHsl::from_rgb(rgb),
+            None => FloorHsl {
+                hue: 0,
+                saturation: 0,
+                lightness: 0,
+                blended_hue: 0,
+                chroma: 0,
+            },
+        };
+        match self.minimap_rgb {
+            Some(_) if self.minimap_rgb_applies() => FloorHsl {
+                chroma: hsl.blended_hue,
+                ..hsl
+            },
+            _ => hsl,
+        }
+    }
+
+    /// The packed colour the floor is drawn with on the minimap.
+    pub fn minimap_hsl16(&self) -> u16 {
+        match self.minimap_rgb {
+            Some(rgb) if self.minimap_rgb_applies() => FloorHsl::from_rgb(rgb).to_hsl16(),
+            _ => self.hsl().to_hsl16(),
+        }
+    }
+
+    // whether the minimap colour is read after the colour, in the order the floor is encoded in
+    fn minimap_rgb_applies(&self) -> bool {
+        let position = |opcode: u8| match self.opcodes.iter().position(|stored| *stored == opcode) {
+            Some(position) => position,
+            None => self.opcodes.len() + OPCODES.iter().position(|o| *o == opcode).unwrap_or(0),
+        };
+        self.rgb.is_none() || position(7) > position(1)
+    }
+}
+
+impl Definition for FloorDefinition {
+    const FILE_NAME: &'static str = "flo";
+    const INDEXED: bool = false;
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn decode(id: u32, buffer: &mut &[u8]) -> Result<Self, FileSystemError> {
+        let mut floor = FloorDefinition::new(id);
+        loop {
+            let opcode = buffer.read_u8()?;
+            if opcode != END_OPCODE {
+                config::record_opcode(&mut floor.opcodes, opcode);
+            }
+            match opcode {
+                END_OPCODE => return Ok(floor),
+                1 => floor.rgb = Some(buffer.read_u24::<BigEndian>()?),
+                2 => floor.texture = Some(buffer.read_u8()?),
+                3 => floor.unused_flag_3 = true,
+                5 => floor.occludes = false,
+                6 => floor.name = Some(buffer.read_string()?),
+                7 => floor.minimap_rgb = Some(buffer.read_u24::<BigEndian>()?),
+                _ => {
+                    return Err(FileSystemError::InvalidOpcode {
+                        definition: Self::FILE_NAME,
+                        id,
+                        opcode,
+                    })
+                }
+            }
+        }
+    }
+
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), FileSystemError> {
+        config::encode_opcodes(buffer, &self.opcodes, OPCODES, |buffer, opcode, _| {
+            self.encode_opcode(buffer, opcode)
+        })
+    }
+}
+
+impl FloorDefinition {
+    // writes a single opcode if the floor has a value for it
+    fn encode_opcode(&self, buffer: &mut Vec<u8>, opcode: u8) -> Result<(), FileSystemError> {
+        let rgb = match opcode {
+            1 => self.rgb,
+            7 => self.minimap_rgb,
+            _ => None,
+        };
+        match opcode {
+            1 | 7 => {
+                if let Some(rgb) = rgb {
+                    buffer.write_u8(opcode)?;
+                    buffer.write_u24::<BigEndian>(rgb)?;
+                }
+            }
+            2 => {
+                if let Some(texture) = self.texture {
+                    buffer.write_u8(2)?;
+                    buffer.write_u8(texture)?;
+                }
+            }
+            3 if self.unused_flag_3 => buffer.write_u8(3)?,
+            5 if !self.occludes => buffer.write_u8(5)?,
+            6 => {
+                if let Some(name) = &self.name {
+                    config::write_string(buffer, 6, name)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::archive::{Archive, ArchiveCompression};
+    use crate::config::floor::{FloorDefinition, FloorHsl};
+    use crate::config::{self, Definition};
+
+    #[test]
+    fn test_floor_round_trip() {
+        let data = [
+            1, 0xFF, 0x00, 0x00, 2, 4, 5, 6, b'w', b'a', b't', b'e', b'r', 10, 0,
+        ];
+        let floor = FloorDefinition::decode(6, &mut &data[..]).unwrap();
+        assert_eq!(floor.rgb, Some(0xFF0000));
+        assert_eq!(floor.texture, Some(4));
+        assert!(!floor.occludes);
+        assert_eq!(floor.name.as_deref(), Some("water"));
+        let mut encoded = Vec::new();
+        floor.encode(&mut encoded).unwrap();
+        assert_eq!(encoded, data);
+    }
+
+    #[test]
+    fn test_floor_keeps_stored_order() {
+        let data = [2, 1, 1, 0x00, 0x00, 0x00, 0];
+        let floor = FloorDefinition::decode(0, &mut &data[..]).unwrap();
+        let mut encoded = Vec::new();
+        floor.encode(&mut encoded).unwrap();
+        assert_eq!(encoded, data);
+    }
+
+    #[test]
+    fn test_floor_colours() {
+        let red = FloorHsl::from_rgb(0xFF0000);
+        assert_eq!((red.hue, red.saturation, red.lightness), (0, 255, 127));
+        assert_eq!((red.chroma, red.blended_hue), (255, 0));
+        assert_eq!(red.to_hsl16(), 959);
+        let green = FloorHsl::from_rgb(0x00FF00);
+        assert_eq!((green.hue, green.blended_hue), (85, 85));
+        let grey = FloorHsl::from_rgb(0x808080);
+        assert_eq!((grey.saturation, grey.lightness, grey.chroma), (0, 128, 1));
+        assert_eq!(FloorDefinition::new(0).hsl().chroma, 0);
+    }
+
+    #[test]
+    fn test_floor_minimap_colours() {
+        // green with a red minimap colour keeps the values of green but its chroma
+        let data = [1, 0x00, 0xFF, 0x00, 7, 0xFF, 0x00, 0x00, 0];
+        let floor = FloorDefinition::decode(0, &mut &data[..]).unwrap();
+        let hsl = floor.hsl();
+        assert_eq!((hsl.hue, hsl.saturation, hsl.lightness), (85, 255, 127));
+        assert_eq!((hsl.blended_hue, hsl.chroma), (85, 85));
+        assert_eq!(floor.minimap_hsl16(), 959);
+
+        // the colour stored after the minimap colour replaces everything
+        let data = [7, 0xFF, 0x00, 0x00, 1, 0x00, 0xFF, 0x00, 0];
+        let floor = FloorDefinition::decode(0, &mut &data[..]).unwrap();
+        assert_eq!(floor.hsl(), FloorHsl::from_rgb(0x00FF00));
+        assert_eq!(
+            floor.minimap_hsl16(),
+            FloorHsl::from_rgb(0x00FF00).to_hsl16()
+        );
+    }
+
+    #[test]
+    fn test_floors_through_archive() {
+        let mut floors: Vec<FloorDefinition> = (0..2).map(FloorDefinition::new).collect();
+        floors[1].rgb = Some(0x336699);
+        floors[1].minimap_rgb = Some(0x000000);
+        let mut archive = Archive::new(ArchiveCompression::Whole);
+        config::encode_all(&floors, &mut archive).unwrap();
+        let decoded: Vec<FloorDefinition> = config::decode_all(&archive).unwrap();
+        // the decoded floor remembers the opcodes it was stored with
+        assert_eq!(decoded[1].opcodes, vec![1, 7]);
+        floors[1].opcodes = vec![1, 7];
+        assert_eq!(decoded, floors);
+        assert_eq!(decoded[1].minimap_hsl16(), FloorHsl::from_rgb(0).to_hsl16());
+    }
+}
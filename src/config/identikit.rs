@@ -0,0 +1,206 @@
+use crate::config::{self, Definition, END_OPCODE};
+use crate::errors::FileSystemError;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+/// The number of colours an identity kit can replace.
+pub const RECOLOR_COUNT: usize = 6;
+pub const HEAD_MODEL_COUNT: usize = 5;
+/// The body part of the first female kit. Kits below it are male.
+pub const FEMALE_BODY_PART_OFFSET: u8 = 7;
+// every opcode in the order new definitions are encoded in
+#[rustfmt::skip]
+const OPCODES: &[u8] = &[
+    1, 2, 3, 40, 41, 42, 43, 44, 45, 50, 51, 52, 53, 54, 55, 60, 61, 62, 63, 64,
+];
+
+/// A player body part kit from `idk.dat`, used by the character designer.
+///
+/// Every field holds the value as it is stored, and the opcodes are kept in the order they were
+/// stored in, so a decoded definition encodes back into the same bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdentityKitDefinition {
+    pub id: u32,
+    /// The opcodes the definition was decoded from, in their stored order. These are encoded
+    /// first, even when they hold the default value, followed by every other field that differs
+    /// from its default.
+    pub opcodes: Vec<u8>,
+    /// The body part the kit is worn on: head, jaw, torso, arms, hands, legs and feet for the
+    /// male kits, followed by the same parts for the female kits.
+    pub body_part: Option<u8>,
+    pub models: Vec<u16>,
+    /// Whether the kit is left out of the character designer.
+    pub non_selectable: bool,
+    /// The colours of the models that are replaced, set by opcodes 40 and up.
+    pub original_colors: [Option<u16>; RECOLOR_COUNT],
+    /// The colours that replace them, set by opcodes 50 and up.
+    pub replacement_colors: [Option<u16>; RECOLOR_COUNT],
+    /// The models drawn in chat boxes.
+    pub head_models: [Option<u16>; HEAD_MODEL_COUNT],
+}
+
+impl IdentityKitDefinition {
+    pub fn new(id: u32) -> Self {
+        IdentityKitDefinition {
+            id,
+            opcodes: Vec::new(),
+            body_part: None,
+            models: Vec::new(),
+            non_selectable: false,
+            original_colors: [None; RECOLOR_COUNT],
+            replacement_colors: [None; RECOLOR_COUNT],
+            head_models: [None; HEAD_MODEL_COUNT],
+        }
+    }
+
+    /// Whether the kit belongs to the female body parts.
+    pub fn is_female(&self) -> bool {
+        matches!(self.body_part, Some(body_part) if body_part >= FEMALE_BODY_PART_OFFSET)
+    }
+
+    /// Pairs of the colours of the models and the colours that replace them. Like the client,
+    /// a colour without a replacement is replaced by 0.
+    pub fn recolors(&self) -> Vec<(u16, u16)> {
+        self.original_colors
+            .iter()
+            .zip(self.replacement_colors.iter())
+            .filter_map(|(original, replacement)| Some(((*original)?, replacement.unwrap_or(0))))
+            .collect()
+    }
+}
+
+impl Definition for IdentityKitDefinition {
+    const FILE_NAME: &'static str = "idk";
+    const INDEXED: bool = false;
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn decode(id: u32, buffer: &mut &[u8]) -> Result<Self, FileSystemError> {
+        let mut kit = IdentityKitDefinition::new(id);
+        loop {
+            let opcode = buffer.read_u8()?;
+            if opcode != END_OPCODE {
+                config::record_opcode(&mut kit.opcodes, opcode);
+            }
+            match opcode {
+                END_OPCODE => return Ok(kit),
+                1 => kit.body_part = Some(buffer.read_u8()?),
+                2 => {
+                    let count = buffer.read_u8()?;
+                    let mut models = Vec::with_capacity(count as usize);
+                    for _ in 0..count {
+                        models.push(buffer.read_u16::<BigEndian>()?);
+                    }
+                    kit.models = models;
+                }
+                3 => kit.non_selectable = true,
+                40..=45 => {
+                    kit.original_colors[(opcode - 40) as usize] =
+                        Some(buffer.read_u16::<BigEndian>()?)
+                }
+                50..=55 => {
+                    kit.replacement_colors[(opcode - 50) as usize] =
+                        Some(buffer.read_u16::<BigEndian>()?)
+                }
+                60..=64 => {
+                    kit.head_models[(opcode - 60) as usize] = Some(buffer.read_u16::<BigEndian>()?)
+                }
+                _ => {
+                    return Err(FileSystemError::InvalidOpcode {
+                        definition: Self::FILE_NAME,
+                        id,
+                        opcode,
+                    })
+                }
+            }
+        }
+    }
+
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), FileSystemError> {
+        config::encode_opcodes(buffer, &self.opcodes, OPCODES, |buffer, opcode, stored| {
+            self.encode_opcode(buffer, opcode, stored)
+        })
+    }
+}
+
+impl IdentityKitDefinition {
+    // writes a single opcode if the kit has a value for it
+    fn encode_opcode(
+        &self,
+        buffer: &mut Vec<u8>,
+        opcode: u8,
+        stored: bool,
+    ) -> Result<(), FileSystemError> {
+        match opcode {
+            1 => {
+                if let Some(body_part) = self.body_part {
+                    buffer.write_u8(1)?;
+                    buffer.write_u8(body_part)?;
+                }
+            }
+            2 if stored || !self.models.is_empty() => {
+                buffer.write_u8(2)?;
+                buffer.write_u8(self.models.len() as u8)?;
+                for model in &self.models {
+                    buffer.write_u16::<BigEndian>(*model)?;
+                }
+            }
+            3 if self.non_selectable => buffer.write_u8(3)?,
+            40..=45 | 50..=55 | 60..=64 => {
+                let values = match opcode {
+                    40..=45 => &self.original_colors[..],
+                    50..=55 => &self.replacement_colors[..],
+                    _ => &self.head_models[..],
+                };
+                if let Some(value) = values[(opcode % 10) as usize] {
+                    config::write_u16(buffer, opcode, value)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::identikit::IdentityKitDefinition;
+    use crate::config::Definition;
+
+    #[rustfmt::skip]
+    const LONG_HAIR: &[u8] = &[
+        1, 7,
+        2, 2, 0x01, 0x00, 0x01, 0x01,
+        3,
+        40, 0x00, 0x06,
+        50, 0x00, 0x07,
+        60, 0x02, 0x00,
+        0,
+    ];
+
+    #[test]
+    fn test_identity_kit_round_trip() {
+        let kit = IdentityKitDefinition::decode(45, &mut &LONG_HAIR[..]).unwrap();
+        assert_eq!(kit.body_part, Some(7));
+        assert!(kit.is_female());
+        assert_eq!(kit.models, vec![256, 257]);
+        assert!(kit.non_selectable);
+        assert_eq!(kit.recolors(), vec![(6, 7)]);
+        assert_eq!(kit.head_models, [Some(512), None, None, None, None]);
+        let mut encoded = Vec::new();
+        kit.encode(&mut encoded).unwrap();
+        assert_eq!(encoded, LONG_HAIR);
+    }
+
+    #[test]
+    fn test_identity_kit_keeps_stored_order() {
+        let data = [3, 1, 2, 50, 0x00, 0x01, 2, 0, 0];
+        let kit = IdentityKitDefinition::decode(0, &mut &data[..]).unwrap();
+        assert!(kit.models.is_empty());
+        let mut encoded = Vec::new();
+        kit.encode(&mut encoded).unwrap();
+        assert_eq!(encoded, data);
+    }
+}
@@ -0,0 +1,450 @@
+use crate::buffer::BufferReadExt;
+use crate::config::{self, Definition, END_OPCODE};
+use crate::errors::FileSystemError;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+pub const DEFAULT_MODEL_ZOOM: u16 = 2000;
+pub const DEFAULT_VALUE: i32 = 1;
+pub const DEFAULT_SCALE: u16 = 128;
+pub const STACK_VARIANT_COUNT: usize = 10;
+pub const ACTION_COUNT: usize = 5;
+// every opcode in the order new definitions are encoded in
+#[rustfmt::skip]
+const OPCODES: &[u8] = &[
+    1, 2, 3, 4, 5, 6, 7, 8, 10, 11, 12, 16, 23, 24, 25, 26,
+    30, 31, 32, 33, 34, 35, 36, 37, 38, 39, 40, 78, 79, 90, 91, 92, 93, 95, 97, 98,
+    100, 101, 102, 103, 104, 105, 106, 107, 108, 109, 110, 111, 112, 113, 114, 115,
+];
+
+/// An item definition from `obj.dat`, looked up through `obj.idx`.
+///
+/// Every field holds the value as it is stored, and the opcodes are kept in the order they were
+/// stored in, so a decoded definition encodes back into the same bytes. Equipment and head models
+/// are referred to by their ids in `IndexType::MODEL`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ItemDefinition {
+    pub id: u32,
+    /// The opcodes the definition was decoded from, in their stored order. These are encoded
+    /// first, even when they hold the default value, followed by every other field that differs
+    /// from its default.
+    pub opcodes: Vec<u8>,
+    pub model_id: u16,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub model_zoom: u16,
+    pub model_rotation_x: u16,
+    pub model_rotation_y: u16,
+    pub model_rotation_z: u16,
+    pub model_offset_x: i16,
+    pub model_offset_y: i16,
+    /// Opcode 10, read but never used by the client.
+    pub unused_opcode_10: Option<u16>,
+    pub stackable: bool,
+    pub value: i32,
+    pub members: bool,
+    pub male_models: [Option<u16>; 3],
+    pub male_offset_y: i8,
+    pub female_models: [Option<u16>; 3],
+    pub female_offset_y: i8,
+    pub male_head_models: [Option<u16>; 2],
+    pub female_head_models: [Option<u16>; 2],
+    /// The options shown when the item lies on the ground.
+    pub ground_actions: [Option<String>; ACTION_COUNT],
+    /// The options shown when the item is in the inventory.
+    pub actions: [Option<String>; ACTION_COUNT],
+    /// Pairs of the colours of the model and the colours that replace them.
+    pub recolors: Vec<(u16, u16)>,
+    /// The id of the noted version of this item.
+    pub noted_id: Option<u16>,
+    /// The id of the item whose model notes are drawn with.
+    pub noted_template_id: Option<u16>,
+    /// The items that are shown instead of this one once a stack holds at least the given amount.
+    pub stack_variants: [Option<(u16, u16)>; STACK_VARIANT_COUNT],
+    pub scale_x: u16,
+    pub scale_y: u16,
+    pub scale_z: u16,
+    pub ambient: i8,
+    /// The contrast as stored. The client multiplies it by 5.
+    pub contrast: i8,
+    pub team: u8,
+}
+
+impl ItemDefinition {
+    pub fn new(id: u32) -> Self {
+        ItemDefinition {
+            id,
+            opcodes: Vec::new(),
+            model_id: 0,
+            name: None,
+            description: None,
+            model_zoom: DEFAULT_MODEL_ZOOM,
+            model_rotation_x: 0,
+            model_rotation_y: 0,
+            model_rotation_z: 0,
+            model_offset_x: 0,
+            model_offset_y: 0,
+            unused_opcode_10: None,
+            stackable: false,
+            value: DEFAULT_VALUE,
+            members: false,
+            male_models: [None; 3],
+            male_offset_y: 0,
+            female_models: [None; 3],
+            female_offset_y: 0,
+            male_head_models: [None; 2],
+            female_head_models: [None; 2],
+            ground_actions: Default::default(),
+            actions: Default::default(),
+            recolors: Vec::new(),
+            noted_id: None,
+            noted_template_id: None,
+            stack_variants: [None; STACK_VARIANT_COUNT],
+            scale_x: DEFAULT_SCALE,
+            scale_y: DEFAULT_SCALE,
+            scale_z: DEFAULT_SCALE,
+            ambient: 0,
+            contrast: 0,
+            team: 0,
+        }
+    }
+
+    /// Whether this item is the noted version of another item.
+    pub fn is_noted(&self) -> bool {
+        self.noted_template_id.is_some()
+    }
+
+    /// The inventory action in the given slot. Unlike ground actions, these are never hidden.
+    pub fn action(&self, slot: usize) -> Option<&str> {
+        self.actions.get(slot)?.as_deref()
+    }
+
+    /// The ground action in the given slot, leaving out hidden actions.
+    pub fn ground_action(&self, slot: usize) -> Option<&str> {
+        self.ground_actions
+            .get(slot)?
+            .as_deref()
+            .filter(|action| !action.eq_ignore_ascii_case(config::HIDDEN_ACTION))
+    }
+}
+
+impl Definition for ItemDefinition {
+    const FILE_NAME: &'static str = "obj";
+    const INDEXED: bool = true;
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn decode(id: u32, buffer: &mut &[u8]) -> Result<Self, FileSystemError> {
+        let mut item = ItemDefinition::new(id);
+        loop {
+            let opcode = buffer.read_u8()?;
+            if opcode != END_OPCODE {
+                config::record_opcode(&mut item.opcodes, opcode);
+            }
+            match opcode {
+                END_OPCODE => return Ok(item),
+                1 => item.model_id = buffer.read_u16::<BigEndian>()?,
+                2 => item.name = Some(buffer.read_string()?),
+                3 => item.description = Some(buffer.read_string()?),
+                4 => item.model_zoom = buffer.read_u16::<BigEndian>()?,
+                5 => item.model_rotation_x = buffer.read_u16::<BigEndian>()?,
+                6 => item.model_rotation_y = buffer.read_u16::<BigEndian>()?,
+                7 => item.model_offset_x = buffer.read_i16::<BigEndian>()?,
+                8 => item.model_offset_y = buffer.read_i16::<BigEndian>()?,
+                10 => item.unused_opcode_10 = Some(buffer.read_u16::<BigEndian>()?),
+                11 => item.stackable = true,
+                12 => item.value = buffer.read_i32::<BigEndian>()?,
+                16 => item.members = true,
+                23 => {
+                    item.male_models[0] = Some(buffer.read_u16::<BigEndian>()?);
+                    item.male_offset_y = buffer.read_i8()?;
+                }
+                24 => item.male_models[1] = Some(buffer.read_u16::<BigEndian>()?),
+                25 => {
+                    item.female_models[0] = Some(buffer.read_u16::<BigEndian>()?);
+                    item.female_offset_y = buffer.read_i8()?;
+                }
+                26 => item.female_models[1] = Some(buffer.read_u16::<BigEndian>()?),
+                30..=34 => {
+                    item.ground_actions[(opcode - 30) as usize] = Some(buffer.read_string()?)
+                }
+                35..=39 => item.actions[(opcode - 35) as usize] = Some(buffer.read_string()?),
+                40 => item.recolors = config::read_recolors(buffer)?,
+                78 => item.male_models[2] = Some(buffer.read_u16::<BigEndian>()?),
+                79 => item.female_models[2] = Some(buffer.read_u16::<BigEndian>()?),
+                90 => item.male_head_models[0] = Some(buffer.read_u16::<BigEndian>()?),
+                91 => item.female_head_models[0] = Some(buffer.read_u16::<BigEndian>()?),
+                92 => item.male_head_models[1] = Some(buffer.read_u16::<BigEndian>()?),
+                93 => item.female_head_models[1] = Some(buffer.read_u16::<BigEndian>()?),
+                95 => item.model_rotation_z = buffer.read_u16::<BigEndian>()?,
+                97 => item.noted_id = Some(buffer.read_u16::<BigEndian>()?),
+                98 => item.noted_template_id = Some(buffer.read_u16::<BigEndian>()?),
+                100..=109 => {
+                    let variant_id = buffer.read_u16::<BigEndian>()?;
+                    let amount = buffer.read_u16::<BigEndian>()?;
+                    item.stack_variants[(opcode - 100) as usize] = Some((variant_id, amount));
+                }
+                110 => item.scale_x = buffer.read_u16::<BigEndian>()?,
+                111 => item.scale_y = buffer.read_u16::<BigEndian>()?,
+                112 => item.scale_z = buffer.read_u16::<BigEndian>()?,
+                113 => item.ambient = buffer.read_i8()?,
+                114 => item.contrast = buffer.read_i8()?,
+                115 => item.team = buffer.read_u8()?,
+                _ => {
+                    return Err(FileSystemError::InvalidOpcode {
+                        definition: Self::FILE_NAME,
+                        id,
+                        opcode,
+                    })
+                }
+            }
+        }
+    }
+
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), FileSystemError> {
+        config::encode_opcodes(buffer, &self.opcodes, OPCODES, |buffer, opcode, stored| {
+            self.encode_opcode(buffer, opcode, stored)
+        })
+    }
+}
+
+impl ItemDefinition {
+    // writes a single opcode if the item has a value for it
+    fn encode_opcode(
+        &self,
+        buffer: &mut Vec<u8>,
+        opcode: u8,
+        stored: bool,
+    ) -> Result<(), FileSystemError> {
+        let optional_model = |opcode| match opcode {
+            24 => self.male_models[1],
+            26 => self.female_models[1],
+            78 => self.male_models[2],
+            79 => self.female_models[2],
+            90 => self.male_head_models[0],
+            91 => self.female_head_models[0],
+            92 => self.male_head_models[1],
+            _ => self.female_head_models[1],
+        };
+        match opcode {
+            1 if stored || self.model_id != 0 => config::write_u16(buffer, 1, self.model_id)?,
+            2 => {
+                if let Some(name) = &self.name {
+                    config::write_string(buffer, 2, name)?;
+                }
+            }
+            3 => {
+                if let Some(description) = &self.description {
+                    config::write_string(buffer, 3, description)?;
+                }
+            }
+            4 if stored || self.model_zoom != DEFAULT_MODEL_ZOOM => {
+                config::write_u16(buffer, 4, self.model_zoom)?
+            }
+            5 if stored || self.model_rotation_x != 0 => {
+                config::write_u16(buffer, 5, self.model_rotation_x)?
+            }
+            6 if stored || self.model_rotation_y != 0 => {
+                config::write_u16(buffer, 6, self.model_rotation_y)?
+            }
+            7 if stored || self.model_offset_x != 0 => {
+                config::write_u16(buffer, 7, self.model_offset_x as u16)?
+            }
+            8 if stored || self.model_offset_y != 0 => {
+                config::write_u16(buffer, 8, self.model_offset_y as u16)?
+            }
+            10 => {
+                if let Some(value) = self.unused_opcode_10 {
+                    config::write_u16(buffer, 10, value)?;
+                }
+            }
+            11 if self.stackable => buffer.write_u8(11)?,
+            12 if stored || self.value != DEFAULT_VALUE => {
+                buffer.write_u8(12)?;
+                buffer.write_i32::<BigEndian>(self.value)?;
+            }
+            16 if self.members => buffer.write_u8(16)?,
+            23 => {
+                if let Some(model) = self.male_models[0] {
+                    config::write_u16(buffer, 23, model)?;
+                    buffer.write_i8(self.male_offset_y)?;
+                }
+            }
+            25 => {
+                if let Some(model) = self.female_models[0] {
+                    config::write_u16(buffer, 25, model)?;
+                    buffer.write_i8(self.female_offset_y)?;
+                }
+            }
+            24 | 26 | 78 | 79 | 90..=93 => {
+                if let Some(model) = optional_model(opcode) {
+                    config::write_u16(buffer, opcode, model)?;
+                }
+            }
+            30..=34 => {
+                if let Some(action) = &self.ground_actions[(opcode - 30) as usize] {
+                    config::write_string(buffer, opcode, action)?;
+                }
+            }
+            35..=39 => {
+                if let Some(action) = &self.actions[(opcode - 35) as usize] {
+                    config::write_string(buffer, opcode, action)?;
+                }
+            }
+            40 if stored || !self.recolors.is_empty() => {
+                config::write_recolors(buffer, 40, &self.recolors)?
+            }
+            95 if stored || self.model_rotation_z != 0 => {
+                config::write_u16(buffer, 95, self.model_rotation_z)?
+            }
+            97 => {
+                if let Some(noted_id) = self.noted_id {
+                    config::write_u16(buffer, 97, noted_id)?;
+                }
+            }
+            98 => {
+                if let Some(noted_template_id) = self.noted_template_id {
+                    config::write_u16(buffer, 98, noted_template_id)?;
+                }
+            }
+            100..=109 => {
+                if let Some((variant_id, amount)) = self.stack_variants[(opcode - 100) as usize] {
+                    config::write_u16(buffer, opcode, variant_id)?;
+                    buffer.write_u16::<BigEndian>(amount)?;
+                }
+            }
+            110..=112 => {
+                let scale = [self.scale_x, self.scale_y, self.scale_z][(opcode - 110) as usize];
+                if stored || scale != DEFAULT_SCALE {
+                    config::write_u16(buffer, opcode, scale)?;
+                }
+            }
+            113 if stored || self.ambient != 0 => {
+                buffer.write_u8(113)?;
+                buffer.write_i8(self.ambient)?;
+            }
+            114 if stored || self.contrast != 0 => {
+                buffer.write_u8(114)?;
+                buffer.write_i8(self.contrast)?;
+            }
+            115 if stored || self.team != 0 => {
+                buffer.write_u8(115)?;
+                buffer.write_u8(self.team)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::archive::{Archive, ArchiveCompression};
+    use crate::config::item::ItemDefinition;
+    use crate::config::{self, Definition};
+    use crate::errors::FileSystemError;
+
+    #[rustfmt::skip]
+    const WHIP: &[u8] = &[
+        1, 0x03, 0xEB,
+        2, b'W', b'h', b'i', b'p', 10,
+        4, 0x02, 0xF8,
+        7, 0xFF, 0xFE,
+        12, 0x00, 0x01, 0xD4, 0xC0,
+        16,
+        23, 0x03, 0xEC, 0xFB,
+        25, 0x03, 0xED, 0x06,
+        30, b't', b'a', b'k', b'e', 10,
+        31, b'H', b'i', b'd', b'd', b'e', b'n', 10,
+        35, b'h', b'i', b'd', b'd', b'e', b'n', 10,
+        36, b'W', b'i', b'e', b'l', b'd', 10,
+        40, 1, 0x12, 0x34, 0x56, 0x78,
+        97, 0x11, 0x88,
+        100, 0x00, 0x10, 0x00, 0x02,
+        0,
+    ];
+
+    #[test]
+    fn test_item_round_trip() {
+        let item = ItemDefinition::decode(4151, &mut &WHIP[..]).unwrap();
+        assert_eq!(item.model_id, 1003);
+        assert_eq!(item.name.as_deref(), Some("Whip"));
+        assert_eq!(item.model_zoom, 760);
+        assert_eq!(item.model_offset_x, -2);
+        assert_eq!(item.value, 120_000);
+        assert!(item.members);
+        assert_eq!(item.male_models, [Some(1004), None, None]);
+        assert_eq!(item.male_offset_y, -5);
+        assert_eq!(item.action(0), Some("hidden"));
+        assert_eq!(item.action(1), Some("Wield"));
+        assert_eq!(item.ground_action(0), Some("take"));
+        assert_eq!(item.ground_action(1), None);
+        assert_eq!(item.recolors, vec![(0x1234, 0x5678)]);
+        assert_eq!(item.noted_id, Some(4488));
+        assert_eq!(item.stack_variants[0], Some((16, 2)));
+        let mut encoded = Vec::new();
+        item.encode(&mut encoded).unwrap();
+        assert_eq!(encoded, WHIP);
+    }
+
+    #[test]
+    fn test_item_keeps_stored_defaults_and_order() {
+        #[rustfmt::skip]
+        let data: &[u8] = &[
+            12, 0x00, 0x00, 0x00, 0x01,
+            2, b'C', b'o', b'i', b'n', b's', 10,
+            4, 0x07, 0xD0,
+            110, 0x00, 0x80,
+            11,
+            0,
+        ];
+        let item = ItemDefinition::decode(995, &mut &data[..]).unwrap();
+        assert_eq!(item.value, 1);
+        assert_eq!(item.model_zoom, 2000);
+        let mut encoded = Vec::new();
+        item.encode(&mut encoded).unwrap();
+        assert_eq!(encoded, data);
+        // values that were not stored are only written when they differ from the default
+        let mut item = ItemDefinition::new(995);
+        item.name = Some("Coins".to_owned());
+        item.value = 2;
+        let mut encoded = Vec::new();
+        item.encode(&mut encoded).unwrap();
+        assert_eq!(
+            encoded,
+            [2, b'C', b'o', b'i', b'n', b's', 10, 12, 0, 0, 0, 2, 0]
+        );
+    }
+
+    #[test]
+    fn test_items_through_archive() {
+        let mut items: Vec<ItemDefinition> = (0..3).map(ItemDefinition::new).collect();
+        items[1] = ItemDefinition::decode(1, &mut &WHIP[..]).unwrap();
+        let mut archive = Archive::new(ArchiveCompression::Whole);
+        config::encode_all(&items, &mut archive).unwrap();
+        let decoded: Vec<ItemDefinition> = config::decode_all(&archive).unwrap();
+        assert_eq!(decoded, items);
+        let item: ItemDefinition = config::decode(&archive, 2).unwrap();
+        assert_eq!(item, items[2]);
+        assert!(config::decode::<ItemDefinition>(&archive, 3).is_err());
+    }
+
+    #[test]
+    fn test_items_too_large_for_archive() {
+        let mut archive = Archive::new(ArchiveCompression::Whole);
+        let mut items = vec![ItemDefinition::new(0)];
+        items[0].name = Some("a".repeat(u16::MAX as usize));
+        assert!(matches!(
+            config::encode_all(&items, &mut archive),
+            Err(FileSystemError::DefinitionTooLarge { id: 0, .. })
+        ));
+        let items: Vec<ItemDefinition> = (0..=u16::MAX as u32).map(ItemDefinition::new).collect();
+        assert!(matches!(
+            config::encode_all(&items, &mut archive),
+            Err(FileSystemError::TooManyDefinitions { count: 65536, .. })
+        ));
+        assert_eq!(archive.entry_count(), 0);
+    }
+}
@@ -0,0 +1,240 @@
+pub mod floor;
+pub mod identikit;
+pub mod item;
+pub mod npc;
+pub mod object;
+pub mod sequence;
+pub mod spotanim;
+pub mod varbit;
+pub mod varp;
+
+use crate::archive::Archive;
+use crate::buffer::BufferWriteExt;
+use crate::errors::FileSystemError;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::convert::TryFrom;
+
+/// The opcode that ends every definition.
+pub const END_OPCODE: u8 = 0;
+/// The value that stands for a missing id, like the varbit of an NPC that only depends on a varp.
+pub const NO_ID: u16 = u16::MAX;
+/// The action the client leaves out of the menus of NPCs, objects and items on the ground,
+/// ignoring case.
+pub const HIDDEN_ACTION: &str = "hidden";
+
+/// A definition that is stored in the config archive as a list of opcodes and values.
+pub trait Definition: Sized {
+    /// The name of the definition's files in the config archive, without their extension.
+    const FILE_NAME: &'static str;
+    /// Whether the `.dat` file comes with an `.idx` file holding the size of every definition.
+    const INDEXED: bool;
+
+    fn id(&self) -> u32;
+
+    /// Decodes a definition up to and including its `END_OPCODE`.
+    fn decode(id: u32, buffer: &mut &[u8]) -> Result<Self, FileSystemError>;
+
+    /// Encodes a definition including its `END_OPCODE`.
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), FileSystemError>;
+}
+
+/// Decodes every definition of a kind from the config archive.
+pub fn decode_all<D: Definition>(archive: &Archive) -> Result<Vec<D>, FileSystemError> {
+    let data = entry_data(archive, D::FILE_NAME, "dat")?;
+    let mut buffer = data;
+    let count = buffer.read_u16::<BigEndian>()? as u32;
+    let mut definitions = Vec::with_capacity(count as usize);
+    if D::INDEXED {
+        let offsets = offsets::<D>(archive)?;
+        for (id, (offset, size)) in offsets.into_iter().enumerate() {
+            let mut buffer = definition_data(data, offset, size)?;
+            definitions.push(D::decode(id as u32, &mut buffer)?);
+        }
+    } else {
+        for id in 0..count {
+            definitions.push(D::decode(id, &mut buffer)?);
+        }
+    }
+    Ok(definitions)
+}
+
+/// Decodes a single definition from the config archive.
+///
+/// Indexed definitions are looked up through their `.idx` file, the others are decoded one by
+/// one until the requested definition is reached.
+pub fn decode<D: Definition>(archive: &Archive, id: u32) -> Result<D, FileSystemError> {
+    let data = entry_data(archive, D::FILE_NAME, "dat")?;
+    let mut buffer = data;
+    let count = buffer.read_u16::<BigEndian>()? as u32;
+    if id >= count {
+        return Err(FileSystemError::DefinitionNotFound {
+            definition: D::FILE_NAME,
+            id,
+        });
+    }
+    if D::INDEXED {
+        return match offsets::<D>(archive)?.get(id as usize) {
+            Some((offset, size)) => D::decode(id, &mut definition_data(data, *offset, *size)?),
+            None => Err(FileSystemError::DefinitionNotFound {
+                definition: D::FILE_NAME,
+                id,
+            }),
+        };
+    }
+    for skipped_id in 0..id {
+        D::decode(skipped_id, &mut buffer)?;
+    }
+    D::decode(id, &mut buffer)
+}
+
+/// Encodes definitions into the config archive, replacing the files that held them before.
+///
+/// The definitions have to be ordered by id, starting at 0 and without gaps. Both their count and
+/// the size of every definition are stored in two bytes, so neither can exceed 65535.
+pub fn encode_all<D: Definition>(
+    definitions: &[D],
+    archive: &mut Archive,
+) -> Result<(), FileSystemError> {
+    let count =
+        u16::try_from(definitions.len()).map_err(|_| FileSystemError::TooManyDefinitions {
+            definition: D::FILE_NAME,
+            count: definitions.len(),
+        })?;
+    let mut data = Vec::new();
+    let mut index = Vec::new();
+    data.write_u16::<BigEndian>(count)?;
+    index.write_u16::<BigEndian>(count)?;
+    for definition in definitions {
+        let start = data.len();
+        definition.encode(&mut data)?;
+        let size = data.len() - start;
+        let size = u16::try_from(size).map_err(|_| FileSystemError::DefinitionTooLarge {
+            definition: D::FILE_NAME,
+            id: definition.id(),
+            size,
+        })?;
+        index.write_u16::<BigEndian>(size)?;
+    }
+    archive.insert(&format!("{}.dat", D::FILE_NAME), data);
+    if D::INDEXED {
+        archive.insert(&format!("{}.idx", D::FILE_NAME), index);
+    }
+    Ok(())
+}
+
+// remembers the opcodes of a definition in the order they are first stored
+pub(crate) fn record_opcode(opcodes: &mut Vec<u8>, opcode: u8) {
+    if !opcodes.contains(&opcode) {
+        opcodes.push(opcode);
+    }
+}
+
+// writes the opcodes a definition was decoded with in their stored order, then every other one of
+// `opcodes`, and ends the definition. `encode_opcode` is told whether the opcode was stored, in
+// which case it is written even when its value is the default
+pub(crate) fn encode_opcodes<F>(
+    buffer: &mut Vec<u8>,
+    stored: &[u8],
+    opcodes: &[u8],
+    mut encode_opcode: F,
+) -> Result<(), FileSystemError>
+where
+    F: FnMut(&mut Vec<u8>, u8, bool) -> Result<(), FileSystemError>,
+{
+    for opcode in stored {
+        encode_opcode(buffer, *opcode, true)?;
+    }
+    for opcode in opcodes.iter().filter(|opcode| !stored.contains(opcode)) {
+        encode_opcode(buffer, *opcode, false)?;
+    }
+    buffer.write_u8(END_OPCODE)?;
+    Ok(())
+}
+
+// the list of colours and their replacements that several definitions share
+pub(crate) fn read_recolors(buffer: &mut &[u8]) -> Result<Vec<(u16, u16)>, FileSystemError> {
+    let count = buffer.read_u8()?;
+    let mut recolors = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let original = buffer.read_u16::<BigEndian>()?;
+        let replacement = buffer.read_u16::<BigEndian>()?;
+        recolors.push((original, replacement));
+    }
+    Ok(recolors)
+}
+
+pub(crate) fn write_recolors(
+    buffer: &mut Vec<u8>,
+    opcode: u8,
+    recolors: &[(u16, u16)],
+) -> Result<(), FileSystemError> {
+    buffer.write_u8(opcode)?;
+    buffer.write_u8(recolors.len() as u8)?;
+    for (original, replacement) in recolors {
+        buffer.write_u16::<BigEndian>(*original)?;
+        buffer.write_u16::<BigEndian>(*replacement)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn read_id(buffer: &mut &[u8]) -> Result<Option<u16>, FileSystemError> {
+    let id = buffer.read_u16::<BigEndian>()?;
+    Ok(if id == NO_ID { None } else { Some(id) })
+}
+
+pub(crate) fn write_u16(
+    buffer: &mut Vec<u8>,
+    opcode: u8,
+    value: u16,
+) -> Result<(), FileSystemError> {
+    buffer.write_u8(opcode)?;
+    buffer.write_u16::<BigEndian>(value)?;
+    Ok(())
+}
+
+pub(crate) fn write_string(
+    buffer: &mut Vec<u8>,
+    opcode: u8,
+    string: &str,
+) -> Result<(), FileSystemError> {
+    buffer.write_u8(opcode)?;
+    buffer.write_string(string)?;
+    Ok(())
+}
+
+fn entry_data<'a>(
+    archive: &'a Archive,
+    file_name: &str,
+    extension: &str,
+) -> Result<&'a [u8], FileSystemError> {
+    let name = format!("{}.{}", file_name, extension);
+    match archive.entry_name(&name) {
+        Some(entry) => Ok(entry.uncompressed_data()),
+        None => Err(FileSystemError::ArchiveEntryNotFound(name)),
+    }
+}
+
+// the offset into the `.dat` file and the size of every definition, read from the `.idx` file
+fn offsets<D: Definition>(archive: &Archive) -> Result<Vec<(usize, usize)>, FileSystemError> {
+    let mut buffer = entry_data(archive, D::FILE_NAME, "idx")?;
+    let count = buffer.read_u16::<BigEndian>()? as usize;
+    let mut offsets = Vec::with_capacity(count);
+    // the definitions start right after the count at the start of the `.dat` file
+    let mut offset = 2;
+    for _ in 0..count {
+        let size = buffer.read_u16::<BigEndian>()? as usize;
+        offsets.push((offset, size));
+        offset += size;
+    }
+    Ok(offsets)
+}
+
+fn definition_data(data: &[u8], offset: usize, size: usize) -> Result<&[u8], FileSystemError> {
+    data.get(offset..offset + size).ok_or_else(|| {
+        FileSystemError::Io(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            "definition lies past the end of its file",
+        ))
+    })
+}
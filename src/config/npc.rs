@@ -0,0 +1,420 @@
+use crate::buffer::BufferReadExt;
+use crate::config::{self, Definition, END_OPCODE};
+use crate::errors::FileSystemError;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+pub const DEFAULT_SIZE: i8 = 1;
+pub const DEFAULT_SCALE: u16 = 128;
+pub const DEFAULT_DEGREES_TO_TURN: u16 = 32;
+pub const ACTION_COUNT: usize = 10;
+// every opcode in the order new definitions are encoded in
+#[rustfmt::skip]
+const OPCODES: &[u8] = &[
+    1, 2, 3, 12, 13, 14, 17, 30, 31, 32, 33, 34, 35, 36, 37, 38, 39, 40, 60,
+    90, 91, 92, 93, 95, 97, 98, 99, 100, 101, 102, 103, 106, 107,
+];
+
+/// An NPC definition from `npc.dat`, looked up through `npc.idx`.
+///
+/// Every field holds the value as it is stored, and the opcodes are kept in the order they were
+/// stored in, so a decoded definition encodes back into the same bytes. Models are referred to by
+/// their ids in `IndexType::MODEL` and animations by their ids in `seq.dat`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NpcDefinition {
+    pub id: u32,
+    /// The opcodes the definition was decoded from, in their stored order. These are encoded
+    /// first, even when they hold the default value, followed by every other field that differs
+    /// from its default.
+    pub opcodes: Vec<u8>,
+    pub models: Vec<u16>,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    /// The number of tiles the NPC covers in both directions.
+    pub size: i8,
+    pub stand_animation: Option<u16>,
+    pub walk_animation: Option<u16>,
+    pub turn_around_animation: Option<u16>,
+    pub turn_right_animation: Option<u16>,
+    pub turn_left_animation: Option<u16>,
+    /// The options shown when the NPC is right clicked. The client leaves out `hidden` actions.
+    pub actions: [Option<String>; ACTION_COUNT],
+    /// Pairs of the colours of the models and the colours that replace them.
+    pub recolors: Vec<(u16, u16)>,
+    /// The models drawn in chat boxes.
+    pub head_models: Vec<u16>,
+    /// Opcodes 90, 91 and 92, read but never used by the client.
+    pub unused_opcodes: [Option<u16>; 3],
+    pub minimap_visible: bool,
+    pub combat_level: Option<u16>,
+    pub scale_xz: u16,
+    pub scale_y: u16,
+    /// Whether the NPC is drawn on top of the other NPCs that share its tile.
+    pub render_priority: bool,
+    pub ambient: i8,
+    /// The contrast as stored. The client multiplies it by 5.
+    pub contrast: i8,
+    pub head_icon: Option<u16>,
+    pub degrees_to_turn: u16,
+    pub morphisms: Option<NpcMorphisms>,
+    pub clickable: bool,
+}
+
+/// The NPCs an NPC turns into depending on the value of a varbit or varp.
+///
+/// The child at the position of the value is shown. A child of `None`, or a value past the end of
+/// the list, hides the NPC.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NpcMorphisms {
+    pub varbit_id: Option<u16>,
+    pub varp_id: Option<u16>,
+    pub children: Vec<Option<u16>>,
+}
+
+impl NpcMorphisms {
+    /// The id of the NPC that is shown when the varbit or varp holds the given value.
+    pub fn child(&self, value: i32) -> Option<u16> {
+        if value < 0 {
+            return None;
+        }
+        *self.children.get(value as usize)?
+    }
+}
+
+impl NpcDefinition {
+    pub fn new(id: u32) -> Self {
+        NpcDefinition {
+            id,
+            opcodes: Vec::new(),
+            models: Vec::new(),
+            name: None,
+            description: None,
+            size: DEFAULT_SIZE,
+            stand_animation: None,
+            walk_animation: None,
+            turn_around_animation: None,
+            turn_right_animation: None,
+            turn_left_animation: None,
+            actions: Default::default(),
+            recolors: Vec::new(),
+            head_models: Vec::new(),
+            unused_opcodes: [None; 3],
+            minimap_visible: true,
+            combat_level: None,
+            scale_xz: DEFAULT_SCALE,
+            scale_y: DEFAULT_SCALE,
+            render_priority: false,
+            ambient: 0,
+            contrast: 0,
+            head_icon: None,
+            degrees_to_turn: DEFAULT_DEGREES_TO_TURN,
+            morphisms: None,
+            clickable: true,
+        }
+    }
+
+    /// The action in the given slot, leaving out hidden actions.
+    pub fn action(&self, slot: usize) -> Option<&str> {
+        self.actions
+            .get(slot)?
+            .as_deref()
+            .filter(|action| !action.eq_ignore_ascii_case(config::HIDDEN_ACTION))
+    }
+}
+
+fn read_models(buffer: &mut &[u8]) -> Result<Vec<u16>, FileSystemError> {
+    let count = buffer.read_u8()?;
+    let mut models = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        models.push(buffer.read_u16::<BigEndian>()?);
+    }
+    Ok(models)
+}
+
+fn write_models(buffer: &mut Vec<u8>, opcode: u8, models: &[u16]) -> Result<(), FileSystemError> {
+    buffer.write_u8(opcode)?;
+    buffer.write_u8(models.len() as u8)?;
+    for model in models {
+        buffer.write_u16::<BigEndian>(*model)?;
+    }
+    Ok(())
+}
+
+impl Definition for NpcDefinition {
+    const FILE_NAME: &'static str = "npc";
+    const INDEXED: bool = true;
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn decode(id: u32, buffer: &mut &[u8]) -> Result<Self, FileSystemError> {
+        let mut npc = NpcDefinition::new(id);
+        loop {
+            let opcode = buffer.read_u8()?;
+            if opcode != END_OPCODE {
+                config::record_opcode(&mut npc.opcodes, opcode);
+            }
+            match opcode {
+                END_OPCODE => return Ok(npc),
+                1 => npc.models = read_models(buffer)?,
+                2 => npc.name = Some(buffer.read_string()?),
+                3 => npc.description = Some(buffer.read_string()?),
+                12 => npc.size = buffer.read_i8()?,
+                13 => npc.stand_animation = Some(buffer.read_u16::<BigEndian>()?),
+                14 => npc.walk_animation = Some(buffer.read_u16::<BigEndian>()?),
+                17 => {
+                    npc.walk_animation = Some(buffer.read_u16::<BigEndian>()?);
+                    npc.turn_around_animation = Some(buffer.read_u16::<BigEndian>()?);
+                    npc.turn_right_animation = Some(buffer.read_u16::<BigEndian>()?);
+                    npc.turn_left_animation = Some(buffer.read_u16::<BigEndian>()?);
+                }
+                30..=39 => npc.actions[(opcode - 30) as usize] = Some(buffer.read_string()?),
+                40 => npc.recolors = config::read_recolors(buffer)?,
+                60 => npc.head_models = read_models(buffer)?,
+                90..=92 => {
+                    npc.unused_opcodes[(opcode - 90) as usize] =
+                        Some(buffer.read_u16::<BigEndian>()?)
+                }
+                93 => npc.minimap_visible = false,
+                95 => npc.combat_level = Some(buffer.read_u16::<BigEndian>()?),
+                97 => npc.scale_xz = buffer.read_u16::<BigEndian>()?,
+                98 => npc.scale_y = buffer.read_u16::<BigEndian>()?,
+                99 => npc.render_priority = true,
+                100 => npc.ambient = buffer.read_i8()?,
+                101 => npc.contrast = buffer.read_i8()?,
+                102 => npc.head_icon = Some(buffer.read_u16::<BigEndian>()?),
+                103 => npc.degrees_to_turn = buffer.read_u16::<BigEndian>()?,
+                106 => {
+                    let varbit_id = config::read_id(buffer)?;
+                    let varp_id = config::read_id(buffer)?;
+                    let count = buffer.read_u8()? as usize + 1;
+                    let mut children = Vec::with_capacity(count);
+                    for _ in 0..count {
+                        children.push(config::read_id(buffer)?);
+                    }
+                    npc.morphisms = Some(NpcMorphisms {
+                        varbit_id,
+                        varp_id,
+                        children,
+                    });
+                }
+                107 => npc.clickable = false,
+                _ => {
+                    return Err(FileSystemError::InvalidOpcode {
+                        definition: Self::FILE_NAME,
+                        id,
+                        opcode,
+                    })
+                }
+            }
+        }
+    }
+
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), FileSystemError> {
+        config::encode_opcodes(buffer, &self.opcodes, OPCODES, |buffer, opcode, stored| {
+            self.encode_opcode(buffer, opcode, stored)
+        })
+    }
+}
+
+impl NpcDefinition {
+    // writes a single opcode if the NPC has a value for it
+    fn encode_opcode(
+        &self,
+        buffer: &mut Vec<u8>,
+        opcode: u8,
+        stored: bool,
+    ) -> Result<(), FileSystemError> {
+        let turn_animations = [
+            self.turn_around_animation,
+            self.turn_right_animation,
+            self.turn_left_animation,
+        ];
+        let has_turn_animations = turn_animations.iter().any(Option::is_some);
+        match opcode {
+            1 if stored || !self.models.is_empty() => write_models(buffer, 1, &self.models)?,
+            2 => {
+                if let Some(name) = &self.name {
+                    config::write_string(buffer, 2, name)?;
+                }
+            }
+            3 => {
+                if let Some(description) = &self.description {
+                    config::write_string(buffer, 3, description)?;
+                }
+            }
+            12 if stored || self.size != DEFAULT_SIZE => {
+                buffer.write_u8(12)?;
+                buffer.write_i8(self.size)?;
+            }
+            13 => {
+                if let Some(animation) = self.stand_animation {
+                    config::write_u16(buffer, 13, animation)?;
+                }
+            }
+            // opcode 17 sets the walk animation as well
+            14 if stored || !(has_turn_animations || self.opcodes.contains(&17)) => {
+                if let Some(animation) = self.walk_animation {
+                    config::write_u16(buffer, 14, animation)?;
+                }
+            }
+            // opcode 17 always sets the walk and all three turn animations together
+            17 if stored || has_turn_animations => {
+                buffer.write_u8(17)?;
+                let walk_animation = std::iter::once(self.walk_animation);
+                for animation in walk_animation.chain(turn_animations.iter().copied()) {
+                    buffer.write_u16::<BigEndian>(animation.unwrap_or(config::NO_ID))?;
+                }
+            }
+            30..=39 => {
+                if let Some(action) = &self.actions[(opcode - 30) as usize] {
+                    config::write_string(buffer, opcode, action)?;
+                }
+            }
+            40 if stored || !self.recolors.is_empty() => {
+                config::write_recolors(buffer, 40, &self.recolors)?
+            }
+            60 if stored || !self.head_models.is_empty() => {
+                write_models(buffer, 60, &self.head_models)?
+            }
+            90..=92 => {
+                if let Some(value) = self.unused_opcodes[(opcode - 90) as usize] {
+                    config::write_u16(buffer, opcode, value)?;
+                }
+            }
+            93 if !self.minimap_visible => buffer.write_u8(93)?,
+            95 => {
+                if let Some(combat_level) = self.combat_level {
+                    config::write_u16(buffer, 95, combat_level)?;
+                }
+            }
+            97 if stored || self.scale_xz != DEFAULT_SCALE => {
+                config::write_u16(buffer, 97, self.scale_xz)?
+            }
+            98 if stored || self.scale_y != DEFAULT_SCALE => {
+                config::write_u16(buffer, 98, self.scale_y)?
+            }
+            99 if self.render_priority => buffer.write_u8(99)?,
+            100 if stored || self.ambient != 0 => {
+                buffer.write_u8(100)?;
+                buffer.write_i8(self.ambient)?;
+            }
+            101 if stored || self.contrast != 0 => {
+                buffer.write_u8(101)?;
+                buffer.write_i8(self.contrast)?;
+            }
+            102 => {
+                if let Some(head_icon) = self.head_icon {
+                    config::write_u16(buffer, 102, head_icon)?;
+                }
+            }
+            103 if stored || self.degrees_to_turn != DEFAULT_DEGREES_TO_TURN => {
+                config::write_u16(buffer, 103, self.degrees_to_turn)?
+            }
+            106 => {
+                if let Some(morphisms) = &self.morphisms {
+                    buffer.write_u8(106)?;
+                    buffer.write_u16::<BigEndian>(morphisms.varbit_id.unwrap_or(config::NO_ID))?;
+                    buffer.write_u16::<BigEndian>(morphisms.varp_id.unwrap_or(config::NO_ID))?;
+                    // the count is stored minus one, so an empty list is written as a single
+                    // hidden child
+                    let children: &[Option<u16>] = match morphisms.children.is_empty() {
+                        true => &[None],
+                        false => &morphisms.children,
+                    };
+                    buffer.write_u8((children.len() - 1) as u8)?;
+                    for child in children {
+                        buffer.write_u16::<BigEndian>(child.unwrap_or(config::NO_ID))?;
+                    }
+                }
+            }
+            107 if !self.clickable => buffer.write_u8(107)?,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::archive::{Archive, ArchiveCompression};
+    use crate::config::npc::NpcDefinition;
+    use crate::config::{self, Definition};
+
+    #[rustfmt::skip]
+    const GUARD: &[u8] = &[
+        1, 2, 0x00, 0x10, 0x00, 0x11,
+        2, b'G', b'u', b'a', b'r', b'd', 10,
+        12, 2,
+        13, 0x03, 0x32,
+        17, 0x03, 0x33, 0x03, 0x34, 0x03, 0x35, 0x03, 0x36,
+        30, b'H', b'I', b'D', b'D', b'E', b'N', 10,
+        31, b'A', b't', b't', b'a', b'c', b'k', 10,
+        60, 1, 0x00, 0x20,
+        93,
+        95, 0x00, 0x15,
+        101, 0xFE,
+        106, 0xFF, 0xFF, 0x01, 0x02, 1, 0x00, 0x07, 0x00, 0x08,
+        0,
+    ];
+
+    #[test]
+    fn test_npc_round_trip() {
+        let npc = NpcDefinition::decode(9, &mut &GUARD[..]).unwrap();
+        assert_eq!(npc.models, vec![16, 17]);
+        assert_eq!(npc.name.as_deref(), Some("Guard"));
+        assert_eq!(npc.size, 2);
+        assert_eq!(npc.stand_animation, Some(818));
+        assert_eq!(npc.walk_animation, Some(819));
+        assert_eq!(npc.turn_left_animation, Some(822));
+        assert_eq!(npc.action(0), None);
+        assert_eq!(npc.action(1), Some("Attack"));
+        assert_eq!(npc.head_models, vec![32]);
+        assert!(!npc.minimap_visible);
+        assert_eq!(npc.combat_level, Some(21));
+        assert_eq!(npc.contrast, -2);
+        let morphisms = npc.morphisms.as_ref().unwrap();
+        assert_eq!(morphisms.varbit_id, None);
+        assert_eq!(morphisms.varp_id, Some(258));
+        assert_eq!(morphisms.child(0), Some(7));
+        assert_eq!(morphisms.child(1), Some(8));
+        assert_eq!(morphisms.child(2), None);
+        assert_eq!(morphisms.child(-1), None);
+        let mut encoded = Vec::new();
+        npc.encode(&mut encoded).unwrap();
+        assert_eq!(encoded, GUARD);
+    }
+
+    #[test]
+    fn test_npc_keeps_stored_defaults_and_order() {
+        #[rustfmt::skip]
+        let data: &[u8] = &[
+            2, b'M', b'a', b'n', 10,
+            103, 0x00, 0x20,
+            12, 1,
+            97, 0x00, 0x80,
+            98, 0x00, 0x80,
+            14, 0x03, 0x33,
+            0,
+        ];
+        let npc = NpcDefinition::decode(1, &mut &data[..]).unwrap();
+        assert_eq!(npc.size, 1);
+        assert_eq!(npc.degrees_to_turn, 32);
+        let mut encoded = Vec::new();
+        npc.encode(&mut encoded).unwrap();
+        assert_eq!(encoded, data);
+    }
+
+    #[test]
+    fn test_npcs_through_archive() {
+        let mut npcs: Vec<NpcDefinition> = (0..2).map(NpcDefinition::new).collect();
+        npcs[0] = NpcDefinition::decode(0, &mut &GUARD[..]).unwrap();
+        let mut archive = Archive::new(ArchiveCompression::Whole);
+        config::encode_all(&npcs, &mut archive).unwrap();
+        let decoded: Vec<NpcDefinition> = config::decode_all(&archive).unwrap();
+        assert_eq!(decoded, npcs);
+        let npc: NpcDefinition = config::decode(&archive, 1).unwrap();
+        assert_eq!(npc, npcs[1]);
+    }
+}
@@ -0,0 +1,529 @@
+use crate::buffer::BufferReadExt;
+use crate::config::{self, Definition, END_OPCODE};
+use crate::errors::FileSystemError;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+pub const DEFAULT_SIZE: u8 = 1;
+pub const DEFAULT_DECOR_OFFSET: u8 = 16;
+pub const DEFAULT_SCALE: u16 = 128;
+pub const ACTION_COUNT: usize = 5;
+/// The model type of objects that stand in the middle of a tile, like trees and tables.
+pub const CENTREPIECE_MODEL_TYPE: u8 = 10;
+// every opcode in the order new definitions are encoded in
+#[rustfmt::skip]
+const OPCODES: &[u8] = &[
+    1, 2, 3, 5, 14, 15, 17, 18, 19, 21, 22, 23, 24, 28, 29, 30, 31, 32, 33, 34, 39, 40,
+    60, 62, 64, 65, 66, 67, 68, 69, 70, 71, 72, 73, 74, 75, 77,
+];
+
+/// An object definition from `loc.dat`, looked up through `loc.idx`.
+///
+/// Every field holds the value as it is stored, and the opcodes are kept in the order they were
+/// stored in, so a decoded definition encodes back into the same bytes. Flags that the client
+/// derives from other fields when they are missing, like whether the object can be interacted
+/// with, have methods that apply the same rules.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectDefinition {
+    pub id: u32,
+    /// The opcodes the definition was decoded from, in their stored order. These are encoded
+    /// first, even when they hold the default value, followed by every other field that differs
+    /// from its default.
+    pub opcodes: Vec<u8>,
+    pub models: ObjectModels,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub size_x: u8,
+    pub size_y: u8,
+    /// Whether the object blocks movement. Objects that are not solid are not impenetrable either.
+    pub solid: bool,
+    /// Whether the object blocks projectiles.
+    pub impenetrable: bool,
+    pub interact_type: Option<u8>,
+    /// Whether the model follows the height of the ground it is placed on.
+    pub contoured_ground: bool,
+    pub delay_shading: bool,
+    pub occludes: bool,
+    pub animation: Option<u16>,
+    /// How far wall decorations are moved away from the wall.
+    pub decor_offset: u8,
+    pub ambient: i8,
+    /// The contrast as stored. The client multiplies it by 5.
+    pub contrast: i8,
+    /// The options shown when the object is right clicked. The client leaves out `hidden` actions.
+    pub actions: [Option<String>; ACTION_COUNT],
+    /// Pairs of the colours of the models and the colours that replace them.
+    pub recolors: Vec<(u16, u16)>,
+    /// The minimap function icon, like a bank or a shop.
+    pub map_function: Option<u16>,
+    pub inverted: bool,
+    pub casts_shadow: bool,
+    pub scale_x: u16,
+    pub scale_y: u16,
+    pub scale_z: u16,
+    /// The map scene sprite drawn on the minimap.
+    pub map_scene: Option<u16>,
+    /// The sides of the object that can not be walked through to reach it, one bit per side.
+    pub surroundings: u8,
+    pub offset_x: i16,
+    pub offset_y: i16,
+    pub offset_z: i16,
+    pub obstructs_ground: bool,
+    /// Whether the object can be walked through. Hollow objects are neither solid nor impenetrable.
+    pub hollow: bool,
+    pub support_items: Option<u8>,
+    pub morphisms: Option<ObjectMorphisms>,
+}
+
+/// The models of an object.
+///
+/// Opcode 1 gives every model a type that matches the way the object is placed on the map, while
+/// opcode 5 lists models that are used no matter how the object is placed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ObjectModels {
+    Typed(Vec<(u16, u8)>),
+    Untyped(Vec<u16>),
+}
+
+/// The objects an object turns into depending on the value of a varbit or varp.
+///
+/// The child at the position of the value is shown. A child of `None`, or a value past the end of
+/// the list, hides the object.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectMorphisms {
+    pub varbit_id: Option<u16>,
+    pub varp_id: Option<u16>,
+    pub children: Vec<Option<u16>>,
+}
+
+impl ObjectMorphisms {
+    /// The id of the object that is shown when the varbit or varp holds the given value.
+    pub fn child(&self, value: i32) -> Option<u16> {
+        if value < 0 {
+            return None;
+        }
+        *self.children.get(value as usize)?
+    }
+}
+
+impl ObjectDefinition {
+    pub fn new(id: u32) -> Self {
+        ObjectDefinition {
+            id,
+            opcodes: Vec::new(),
+            models: ObjectModels::Untyped(Vec::new()),
+            name: None,
+            description: None,
+            size_x: DEFAULT_SIZE,
+            size_y: DEFAULT_SIZE,
+            solid: true,
+            impenetrable: true,
+            interact_type: None,
+            contoured_ground: false,
+            delay_shading: false,
+            occludes: false,
+            animation: None,
+            decor_offset: DEFAULT_DECOR_OFFSET,
+            ambient: 0,
+            contrast: 0,
+            actions: Default::default(),
+            recolors: Vec::new(),
+            map_function: None,
+            inverted: false,
+            casts_shadow: true,
+            scale_x: DEFAULT_SCALE,
+            scale_y: DEFAULT_SCALE,
+            scale_z: DEFAULT_SCALE,
+            map_scene: None,
+            surroundings: 0,
+            offset_x: 0,
+            offset_y: 0,
+            offset_z: 0,
+            obstructs_ground: false,
+            hollow: false,
+            support_items: None,
+            morphisms: None,
+        }
+    }
+
+    /// The action in the given slot, leaving out hidden actions.
+    pub fn action(&self, slot: usize) -> Option<&str> {
+        self.actions
+            .get(slot)?
+            .as_deref()
+            .filter(|action| !action.eq_ignore_ascii_case(config::HIDDEN_ACTION))
+    }
+
+    /// Whether the object blocks movement, taking hollow objects into account.
+    pub fn is_solid(&self) -> bool {
+        self.solid && !self.hollow
+    }
+
+    /// Whether the object blocks projectiles, taking hollow objects into account.
+    pub fn is_impenetrable(&self) -> bool {
+        self.solid && self.impenetrable && !self.hollow
+    }
+
+    /// Whether the object can be interacted with.
+    ///
+    /// Without an interact type the client makes objects interactive when they have actions or
+    /// when their first model is a centrepiece.
+    pub fn is_interactive(&self) -> bool {
+        match self.interact_type {
+            Some(interact_type) => interact_type == 1,
+            None => {
+                let centrepiece = match &self.models {
+                    ObjectModels::Typed(models) => matches!(
+                        models.first(),
+                        Some((_, model_type)) if *model_type == CENTREPIECE_MODEL_TYPE
+                    ),
+                    ObjectModels::Untyped(models) => !models.is_empty(),
+                };
+                centrepiece || self.actions.iter().any(Option::is_some)
+            }
+        }
+    }
+
+    /// Whether items can be placed on the object. Without a value this follows `is_solid`.
+    pub fn supports_items(&self) -> bool {
+        match self.support_items {
+            Some(support_items) => support_items == 1,
+            None => self.is_solid(),
+        }
+    }
+}
+
+impl Definition for ObjectDefinition {
+    const FILE_NAME: &'static str = "loc";
+    const INDEXED: bool = true;
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn decode(id: u32, buffer: &mut &[u8]) -> Result<Self, FileSystemError> {
+        let mut object = ObjectDefinition::new(id);
+        loop {
+            let opcode = buffer.read_u8()?;
+            if opcode != END_OPCODE {
+                config::record_opcode(&mut object.opcodes, opcode);
+            }
+            match opcode {
+                END_OPCODE => return Ok(object),
+                1 => {
+                    let count = buffer.read_u8()?;
+                    let mut models = Vec::with_capacity(count as usize);
+                    for _ in 0..count {
+                        let model = buffer.read_u16::<BigEndian>()?;
+                        models.push((model, buffer.read_u8()?));
+                    }
+                    object.models = ObjectModels::Typed(models);
+                }
+                2 => object.name = Some(buffer.read_string()?),
+                3 => object.description = Some(buffer.read_string()?),
+                5 => {
+                    let count = buffer.read_u8()?;
+                    let mut models = Vec::with_capacity(count as usize);
+                    for _ in 0..count {
+                        models.push(buffer.read_u16::<BigEndian>()?);
+                    }
+                    object.models = ObjectModels::Untyped(models);
+                }
+                14 => object.size_x = buffer.read_u8()?,
+                15 => object.size_y = buffer.read_u8()?,
+                17 => {
+                    object.solid = false;
+                    object.impenetrable = false;
+                }
+                18 => object.impenetrable = false,
+                19 => object.interact_type = Some(buffer.read_u8()?),
+                21 => object.contoured_ground = true,
+                22 => object.delay_shading = true,
+                23 => object.occludes = true,
+                24 => object.animation = config::read_id(buffer)?,
+                28 => object.decor_offset = buffer.read_u8()?,
+                29 => object.ambient = buffer.read_i8()?,
+                39 => object.contrast = buffer.read_i8()?,
+                30..=34 => object.actions[(opcode - 30) as usize] = Some(buffer.read_string()?),
+                40 => object.recolors = config::read_recolors(buffer)?,
+                60 => object.map_function = Some(buffer.read_u16::<BigEndian>()?),
+                62 => object.inverted = true,
+                64 => object.casts_shadow = false,
+                65 => object.scale_x = buffer.read_u16::<BigEndian>()?,
+                66 => object.scale_y = buffer.read_u16::<BigEndian>()?,
+                67 => object.scale_z = buffer.read_u16::<BigEndian>()?,
+                68 => object.map_scene = Some(buffer.read_u16::<BigEndian>()?),
+                69 => object.surroundings = buffer.read_u8()?,
+                70 => object.offset_x = buffer.read_i16::<BigEndian>()?,
+                71 => object.offset_y = buffer.read_i16::<BigEndian>()?,
+                72 => object.offset_z = buffer.read_i16::<BigEndian>()?,
+                73 => object.obstructs_ground = true,
+                74 => object.hollow = true,
+                75 => object.support_items = Some(buffer.read_u8()?),
+                77 => {
+                    let varbit_id = config::read_id(buffer)?;
+                    let varp_id = config::read_id(buffer)?;
+                    let count = buffer.read_u8()? as usize + 1;
+                    let mut children = Vec::with_capacity(count);
+                    for _ in 0..count {
+                        children.push(config::read_id(buffer)?);
+                    }
+                    object.morphisms = Some(ObjectMorphisms {
+                        varbit_id,
+                        varp_id,
+                        children,
+                    });
+                }
+                _ => {
+                    return Err(FileSystemError::InvalidOpcode {
+                        definition: Self::FILE_NAME,
+                        id,
+                        opcode,
+                    })
+                }
+            }
+        }
+    }
+
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), FileSystemError> {
+        config::encode_opcodes(buffer, &self.opcodes, OPCODES, |buffer, opcode, stored| {
+            self.encode_opcode(buffer, opcode, stored)
+        })
+    }
+}
+
+impl ObjectDefinition {
+    // writes a single opcode if the object has a value for it
+    fn encode_opcode(
+        &self,
+        buffer: &mut Vec<u8>,
+        opcode: u8,
+        stored: bool,
+    ) -> Result<(), FileSystemError> {
+        match (opcode, &self.models) {
+            (1, ObjectModels::Typed(models)) if stored || !models.is_empty() => {
+                buffer.write_u8(1)?;
+                buffer.write_u8(models.len() as u8)?;
+                for (model, model_type) in models {
+                    buffer.write_u16::<BigEndian>(*model)?;
+                    buffer.write_u8(*model_type)?;
+                }
+            }
+            (5, ObjectModels::Untyped(models)) if stored || !models.is_empty() => {
+                buffer.write_u8(5)?;
+                buffer.write_u8(models.len() as u8)?;
+                for model in models {
+                    buffer.write_u16::<BigEndian>(*model)?;
+                }
+            }
+            _ => {}
+        }
+        let flag = match opcode {
+            21 => self.contoured_ground,
+            22 => self.delay_shading,
+            23 => self.occludes,
+            62 => self.inverted,
+            64 => !self.casts_shadow,
+            73 => self.obstructs_ground,
+            _ => self.hollow,
+        };
+        match opcode {
+            2 => {
+                if let Some(name) = &self.name {
+                    config::write_string(buffer, 2, name)?;
+                }
+            }
+            3 => {
+                if let Some(description) = &self.description {
+                    config::write_string(buffer, 3, description)?;
+                }
+            }
+            14 if stored || self.size_x != DEFAULT_SIZE => {
+                buffer.write_u8(14)?;
+                buffer.write_u8(self.size_x)?;
+            }
+            15 if stored || self.size_y != DEFAULT_SIZE => {
+                buffer.write_u8(15)?;
+                buffer.write_u8(self.size_y)?;
+            }
+            17 if !self.solid => buffer.write_u8(17)?,
+            // opcode 17 makes the object penetrable as well
+            18 if !self.impenetrable && (stored || self.solid) => buffer.write_u8(18)?,
+            19 => {
+                if let Some(interact_type) = self.interact_type {
+                    buffer.write_u8(19)?;
+                    buffer.write_u8(interact_type)?;
+                }
+            }
+            21..=23 | 62 | 64 | 73 | 74 if flag => buffer.write_u8(opcode)?,
+            24 => {
+                if let Some(animation) = self.animation {
+                    config::write_u16(buffer, 24, animation)?;
+                }
+            }
+            28 if stored || self.decor_offset != DEFAULT_DECOR_OFFSET => {
+                buffer.write_u8(28)?;
+                buffer.write_u8(self.decor_offset)?;
+            }
+            29 if stored || self.ambient != 0 => {
+                buffer.write_u8(29)?;
+                buffer.write_i8(self.ambient)?;
+            }
+            30..=34 => {
+                if let Some(action) = &self.actions[(opcode - 30) as usize] {
+                    config::write_string(buffer, opcode, action)?;
+                }
+            }
+            39 if stored || self.contrast != 0 => {
+                buffer.write_u8(39)?;
+                buffer.write_i8(self.contrast)?;
+            }
+            40 if stored || !self.recolors.is_empty() => {
+                config::write_recolors(buffer, 40, &self.recolors)?
+            }
+            60 => {
+                if let Some(map_function) = self.map_function {
+                    config::write_u16(buffer, 60, map_function)?;
+                }
+            }
+            65..=67 => {
+                let scale = [self.scale_x, self.scale_y, self.scale_z][(opcode - 65) as usize];
+                if stored || scale != DEFAULT_SCALE {
+                    config::write_u16(buffer, opcode, scale)?;
+                }
+            }
+            68 => {
+                if let Some(map_scene) = self.map_scene {
+                    config::write_u16(buffer, 68, map_scene)?;
+                }
+            }
+            69 if stored || self.surroundings != 0 => {
+                buffer.write_u8(69)?;
+                buffer.write_u8(self.surroundings)?;
+            }
+            70..=72 => {
+                let offset = [self.offset_x, self.offset_y, self.offset_z][(opcode - 70) as usize];
+                if stored || offset != 0 {
+                    config::write_u16(buffer, opcode, offset as u16)?;
+                }
+            }
+            75 => {
+                if let Some(support_items) = self.support_items {
+                    buffer.write_u8(75)?;
+                    buffer.write_u8(support_items)?;
+                }
+            }
+            77 => {
+                if let Some(morphisms) = &self.morphisms {
+                    buffer.write_u8(77)?;
+                    buffer.write_u16::<BigEndian>(morphisms.varbit_id.unwrap_or(config::NO_ID))?;
+                    buffer.write_u16::<BigEndian>(morphisms.varp_id.unwrap_or(config::NO_ID))?;
+                    // the count is stored minus one, so an empty list is written as a single
+                    // hidden child
+                    let children: &[Option<u16>] = match morphisms.children.is_empty() {
+                        true => &[None],
+                        false => &morphisms.children,
+                    };
+                    buffer.write_u8((children.len() - 1) as u8)?;
+                    for child in children {
+                        buffer.write_u16::<BigEndian>(child.unwrap_or(config::NO_ID))?;
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::archive::{Archive, ArchiveCompression};
+    use crate::config::object::{ObjectDefinition, ObjectModels};
+    use crate::config::{self, Definition};
+
+    #[rustfmt::skip]
+    const BANK_BOOTH: &[u8] = &[
+        1, 1, 0x08, 0x00, 10,
+        2, b'B', b'o', b'o', b't', b'h', 10,
+        14, 2,
+        18,
+        30, b'H', b'i', b'd', b'd', b'e', b'n', 10,
+        31, b'U', b's', b'e', 10,
+        39, 0xFF,
+        60, 0x00, 0x0A,
+        69, 0x0E,
+        70, 0xFF, 0xF0,
+        77, 0x00, 0x05, 0xFF, 0xFF, 1, 0x00, 0x03, 0xFF, 0xFF,
+        0,
+    ];
+
+    #[test]
+    fn test_object_round_trip() {
+        let object = ObjectDefinition::decode(2213, &mut &BANK_BOOTH[..]).unwrap();
+        assert_eq!(object.models, ObjectModels::Typed(vec![(2048, 10)]));
+        assert_eq!(object.name.as_deref(), Some("Booth"));
+        assert_eq!((object.size_x, object.size_y), (2, 1));
+        assert!(object.is_solid());
+        assert!(!object.is_impenetrable());
+        assert!(object.is_interactive());
+        assert!(object.supports_items());
+        assert_eq!(object.action(0), None);
+        assert_eq!(object.action(1), Some("Use"));
+        assert_eq!(object.contrast, -1);
+        assert_eq!(object.map_function, Some(10));
+        assert_eq!(object.surroundings, 0b1110);
+        assert_eq!(object.offset_x, -16);
+        let morphisms = object.morphisms.as_ref().unwrap();
+        assert_eq!(morphisms.varbit_id, Some(5));
+        assert_eq!(morphisms.varp_id, None);
+        assert_eq!(morphisms.child(0), Some(3));
+        assert_eq!(morphisms.child(1), None);
+        assert_eq!(morphisms.child(2), None);
+        let mut encoded = Vec::new();
+        object.encode(&mut encoded).unwrap();
+        assert_eq!(encoded, BANK_BOOTH);
+    }
+
+    #[test]
+    fn test_object_keeps_stored_defaults_and_order() {
+        #[rustfmt::skip]
+        let data: &[u8] = &[
+            28, 16,
+            2, b'D', b'o', b'o', b'r', 10,
+            14, 1,
+            15, 1,
+            66, 0x00, 0x80,
+            17,
+            18,
+            0,
+        ];
+        let object = ObjectDefinition::decode(1530, &mut &data[..]).unwrap();
+        assert_eq!((object.size_x, object.size_y), (1, 1));
+        assert_eq!(object.decor_offset, 16);
+        let mut encoded = Vec::new();
+        object.encode(&mut encoded).unwrap();
+        assert_eq!(encoded, data);
+    }
+
+    #[test]
+    fn test_hollow_objects() {
+        let mut object = ObjectDefinition::new(0);
+        object.models = ObjectModels::Typed(vec![(1, 0)]);
+        assert!(!object.is_interactive());
+        object.hollow = true;
+        assert!(!object.is_solid());
+        assert!(!object.is_impenetrable());
+        assert!(!object.supports_items());
+    }
+
+    #[test]
+    fn test_objects_through_archive() {
+        let mut objects: Vec<ObjectDefinition> = (0..2).map(ObjectDefinition::new).collect();
+        objects[1] = ObjectDefinition::decode(1, &mut &BANK_BOOTH[..]).unwrap();
+        let mut archive = Archive::new(ArchiveCompression::Whole);
+        config::encode_all(&objects, &mut archive).unwrap();
+        let decoded: Vec<ObjectDefinition> = config::decode_all(&archive).unwrap();
+        assert_eq!(decoded, objects);
+    }
+}
@@ -0,0 +1,324 @@
+use crate::config::{self, Definition, END_OPCODE};
+use crate::errors::FileSystemError;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io::Read;
+
+pub const DEFAULT_FORCED_PRIORITY: u8 = 5;
+pub const DEFAULT_MAX_LOOPS: u8 = 99;
+pub const DEFAULT_REPLAY_MODE: u8 = 2;
+// every opcode in the order new definitions are encoded in
+const OPCODES: &[u8] = &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+
+/// An animation sequence from `seq.dat`.
+///
+/// Every field holds the value as it is stored, and the opcodes are kept in the order they were
+/// stored in, so a decoded definition encodes back into the same bytes. Frames are referred to by
+/// their ids in the frame archives of `IndexType::ANIMATION`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SequenceDefinition {
+    pub id: u32,
+    /// The opcodes the definition was decoded from, in their stored order. These are encoded
+    /// first, even when they hold the default value, followed by every other field that differs
+    /// from its default.
+    pub opcodes: Vec<u8>,
+    pub frames: Vec<SequenceFrame>,
+    /// The frame the sequence starts again from once it has finished, if it loops.
+    pub loop_offset: Option<u16>,
+    /// The labels of the skeleton that this sequence animates when it is played together with
+    /// a walking animation. The other labels follow the walking animation.
+    pub interleave_order: Option<Vec<u8>>,
+    pub stretches: bool,
+    pub forced_priority: u8,
+    /// The item shown in the shield slot while the sequence plays, offset by 512 like the client.
+    pub shield: Option<u16>,
+    /// The item shown in the weapon slot while the sequence plays, offset by 512 like the client.
+    pub weapon: Option<u16>,
+    pub max_loops: u8,
+    pub animating_precedence: Option<u8>,
+    pub walking_precedence: Option<u8>,
+    pub replay_mode: u8,
+    /// Opcode 12, read but never used by the client.
+    pub unused_opcode_12: Option<u32>,
+}
+
+/// A single frame of a sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SequenceFrame {
+    pub frame_id: u16,
+    /// The frame drawn on top of the primary frame, used for the tweening of some sequences.
+    pub secondary_frame_id: Option<u16>,
+    /// The number of client ticks the frame is shown for. When 0 the client uses the duration
+    /// stored in the frame itself.
+    pub duration: u16,
+}
+
+impl SequenceDefinition {
+    pub fn new(id: u32) -> Self {
+        SequenceDefinition {
+            id,
+            opcodes: Vec::new(),
+            frames: Vec::new(),
+            loop_offset: None,
+            interleave_order: None,
+            stretches: false,
+            forced_priority: DEFAULT_FORCED_PRIORITY,
+            shield: None,
+            weapon: None,
+            max_loops: DEFAULT_MAX_LOOPS,
+            animating_precedence: None,
+            walking_precedence: None,
+            replay_mode: DEFAULT_REPLAY_MODE,
+            unused_opcode_12: None,
+        }
+    }
+
+    /// The precedence of the sequence over other sequences. Without a stored value the client
+    /// uses 2 for interleaved sequences and 0 for the others.
+    pub fn resolved_animating_precedence(&self) -> u8 {
+        self.animating_precedence
+            .unwrap_or_else(|| self.default_precedence())
+    }
+
+    /// The precedence of the sequence over walking. Without a stored value the client uses 2
+    /// for interleaved sequences and 0 for the others.
+    pub fn resolved_walking_precedence(&self) -> u8 {
+        self.walking_precedence
+            .unwrap_or_else(|| self.default_precedence())
+    }
+
+    fn default_precedence(&self) -> u8 {
+        if self.interleave_order.is_some() {
+            2
+        } else {
+            0
+        }
+    }
+}
+
+impl Definition for SequenceDefinition {
+    const FILE_NAME: &'static str = "seq";
+    const INDEXED: bool = false;
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn decode(id: u32, buffer: &mut &[u8]) -> Result<Self, FileSystemError> {
+        let mut sequence = SequenceDefinition::new(id);
+        loop {
+            let opcode = buffer.read_u8()?;
+            if opcode != END_OPCODE {
+                config::record_opcode(&mut sequence.opcodes, opcode);
+            }
+            match opcode {
+                END_OPCODE => return Ok(sequence),
+                1 => {
+                    let count = buffer.read_u8()?;
+                    let mut frames = Vec::with_capacity(count as usize);
+                    for _ in 0..count {
+                        frames.push(SequenceFrame {
+                            frame_id: buffer.read_u16::<BigEndian>()?,
+                            secondary_frame_id: config::read_id(buffer)?,
+                            duration: buffer.read_u16::<BigEndian>()?,
+                        });
+                    }
+                    sequence.frames = frames;
+                }
+                2 => sequence.loop_offset = Some(buffer.read_u16::<BigEndian>()?),
+                3 => {
+                    let count = buffer.read_u8()?;
+                    let mut labels = vec![0; count as usize];
+                    buffer.read_exact(&mut labels)?;
+                    sequence.interleave_order = Some(labels);
+                }
+                4 => sequence.stretches = true,
+                5 => sequence.forced_priority = buffer.read_u8()?,
+                6 => sequence.shield = Some(buffer.read_u16::<BigEndian>()?),
+                7 => sequence.weapon = Some(buffer.read_u16::<BigEndian>()?),
+                8 => sequence.max_loops = buffer.read_u8()?,
+                9 => sequence.animating_precedence = Some(buffer.read_u8()?),
+                10 => sequence.walking_precedence = Some(buffer.read_u8()?),
+                11 => sequence.replay_mode = buffer.read_u8()?,
+                12 => sequence.unused_opcode_12 = Some(buffer.read_u32::<BigEndian>()?),
+                _ => {
+                    return Err(FileSystemError::InvalidOpcode {
+                        definition: Self::FILE_NAME,
+                        id,
+                        opcode,
+                    })
+                }
+            }
+        }
+    }
+
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), FileSystemError> {
+        config::encode_opcodes(buffer, &self.opcodes, OPCODES, |buffer, opcode, stored| {
+            self.encode_opcode(buffer, opcode, stored)
+        })
+    }
+}
+
+impl SequenceDefinition {
+    // writes a single opcode if the sequence has a value for it
+    fn encode_opcode(
+        &self,
+        buffer: &mut Vec<u8>,
+        opcode: u8,
+        stored: bool,
+    ) -> Result<(), FileSystemError> {
+        let optional_u8 = match opcode {
+            9 => self.animating_precedence,
+            10 => self.walking_precedence,
+            _ => None,
+        };
+        match opcode {
+            1 if stored || !self.frames.is_empty() => {
+                buffer.write_u8(1)?;
+                buffer.write_u8(self.frames.len() as u8)?;
+                for frame in &self.frames {
+                    buffer.write_u16::<BigEndian>(frame.frame_id)?;
+                    buffer.write_u16::<BigEndian>(
+                        frame.secondary_frame_id.unwrap_or(config::NO_ID),
+                    )?;
+                    buffer.write_u16::<BigEndian>(frame.duration)?;
+                }
+            }
+            2 => {
+                if let Some(loop_offset) = self.loop_offset {
+                    config::write_u16(buffer, 2, loop_offset)?;
+                }
+            }
+            3 => {
+                if let Some(labels) = &self.interleave_order {
+                    buffer.write_u8(3)?;
+                    buffer.write_u8(labels.len() as u8)?;
+                    buffer.extend_from_slice(labels);
+                }
+            }
+            4 if self.stretches => buffer.write_u8(4)?,
+            5 if stored || self.forced_priority != DEFAULT_FORCED_PRIORITY => {
+                buffer.write_u8(5)?;
+                buffer.write_u8(self.forced_priority)?;
+            }
+            6 => {
+                if let Some(shield) = self.shield {
+                    config::write_u16(buffer, 6, shield)?;
+                }
+            }
+            7 => {
+                if let Some(weapon) = self.weapon {
+                    config::write_u16(buffer, 7, weapon)?;
+                }
+            }
+            8 if stored || self.max_loops != DEFAULT_MAX_LOOPS => {
+                buffer.write_u8(8)?;
+                buffer.write_u8(self.max_loops)?;
+            }
+            9 | 10 => {
+                if let Some(precedence) = optional_u8 {
+                    buffer.write_u8(opcode)?;
+                    buffer.write_u8(precedence)?;
+                }
+            }
+            11 if stored || self.replay_mode != DEFAULT_REPLAY_MODE => {
+                buffer.write_u8(11)?;
+                buffer.write_u8(self.replay_mode)?;
+            }
+            12 => {
+                if let Some(value) = self.unused_opcode_12 {
+                    buffer.write_u8(12)?;
+                    buffer.write_u32::<BigEndian>(value)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::archive::{Archive, ArchiveCompression};
+    use crate::config::sequence::{SequenceDefinition, SequenceFrame};
+    use crate::config::{self, Definition};
+
+    #[rustfmt::skip]
+    const SLASH: &[u8] = &[
+        1, 2, 0x00, 0x01, 0xFF, 0xFF, 0x00, 0x04, 0x00, 0x02, 0x00, 0x03, 0x00, 0x00,
+        2, 0x00, 0x01,
+        3, 2, 5, 7,
+        5, 8,
+        7, 0x05, 0x00,
+        10, 1,
+        0,
+    ];
+
+    #[test]
+    fn test_sequence_round_trip() {
+        let sequence = SequenceDefinition::decode(390, &mut &SLASH[..]).unwrap();
+        assert_eq!(
+            sequence.frames,
+            vec![
+                SequenceFrame {
+                    frame_id: 1,
+                    secondary_frame_id: None,
+                    duration: 4
+                },
+                SequenceFrame {
+                    frame_id: 2,
+                    secondary_frame_id: Some(3),
+                    duration: 0
+                },
+            ]
+        );
+        assert_eq!(sequence.loop_offset, Some(1));
+        assert_eq!(sequence.interleave_order, Some(vec![5, 7]));
+        assert_eq!(sequence.forced_priority, 8);
+        assert_eq!(sequence.weapon, Some(1280));
+        assert_eq!(sequence.resolved_animating_precedence(), 2);
+        assert_eq!(sequence.resolved_walking_precedence(), 1);
+        let mut encoded = Vec::new();
+        sequence.encode(&mut encoded).unwrap();
+        assert_eq!(encoded, SLASH);
+    }
+
+    #[test]
+    fn test_sequence_keeps_stored_defaults_and_order() {
+        #[rustfmt::skip]
+        let data: &[u8] = &[
+            2, 0x00, 0x00,
+            1, 1, 0x00, 0x01, 0xFF, 0xFF, 0x00, 0x04,
+            5, 5,
+            8, 99,
+            11, 2,
+            0,
+        ];
+        let sequence = SequenceDefinition::decode(808, &mut &data[..]).unwrap();
+        assert_eq!(sequence.forced_priority, 5);
+        assert_eq!(sequence.max_loops, 99);
+        let mut encoded = Vec::new();
+        sequence.encode(&mut encoded).unwrap();
+        assert_eq!(encoded, data);
+
+        let mut sequence = SequenceDefinition::new(0);
+        sequence.stretches = true;
+        sequence.loop_offset = Some(1);
+        let mut encoded = Vec::new();
+        sequence.encode(&mut encoded).unwrap();
+        assert_eq!(encoded, [2, 0x00, 0x01, 4, 0]);
+    }
+
+    #[test]
+    fn test_sequences_through_archive() {
+        let mut sequences: Vec<SequenceDefinition> = (0..3).map(SequenceDefinition::new).collect();
+        sequences[1] = SequenceDefinition::decode(1, &mut &SLASH[..]).unwrap();
+        let mut archive = Archive::new(ArchiveCompression::Whole);
+        config::encode_all(&sequences, &mut archive).unwrap();
+        assert!(archive.entry_name("seq.idx").is_none());
+        let decoded: Vec<SequenceDefinition> = config::decode_all(&archive).unwrap();
+        assert_eq!(decoded, sequences);
+        let sequence: SequenceDefinition = config::decode(&archive, 2).unwrap();
+        assert_eq!(sequence, sequences[2]);
+    }
+}
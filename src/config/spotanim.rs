@@ -0,0 +1,211 @@
+use crate::config::{self, Definition, END_OPCODE};
+use crate::errors::FileSystemError;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+pub const DEFAULT_SCALE: u16 = 128;
+/// The number of colours a spot animation can replace.
+pub const RECOLOR_COUNT: usize = 6;
+// every opcode in the order new definitions are encoded in
+#[rustfmt::skip]
+const OPCODES: &[u8] = &[
+    1, 2, 4, 5, 6, 7, 8, 40, 41, 42, 43, 44, 45, 50, 51, 52, 53, 54, 55,
+];
+
+/// A spot animation from `spotanim.dat`, the graphics drawn on top of players and NPCs like
+/// spell impacts.
+///
+/// Every field holds the value as it is stored, and the opcodes are kept in the order they were
+/// stored in, so a decoded definition encodes back into the same bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpotAnimDefinition {
+    pub id: u32,
+    /// The opcodes the definition was decoded from, in their stored order. These are encoded
+    /// first, even when they hold the default value, followed by every other field that differs
+    /// from its default.
+    pub opcodes: Vec<u8>,
+    pub model_id: u16,
+    /// The id of the sequence in `seq.dat` that animates the model.
+    pub sequence_id: Option<u16>,
+    pub scale_xy: u16,
+    pub scale_z: u16,
+    pub rotation: u16,
+    pub ambient: u8,
+    pub contrast: u8,
+    /// The colours of the model that are replaced, set by opcodes 40 and up.
+    pub original_colors: [Option<u16>; RECOLOR_COUNT],
+    /// The colours that replace them, set by opcodes 50 and up.
+    pub replacement_colors: [Option<u16>; RECOLOR_COUNT],
+}
+
+impl SpotAnimDefinition {
+    pub fn new(id: u32) -> Self {
+        SpotAnimDefinition {
+            id,
+            opcodes: Vec::new(),
+            model_id: 0,
+            sequence_id: None,
+            scale_xy: DEFAULT_SCALE,
+            scale_z: DEFAULT_SCALE,
+            rotation: 0,
+            ambient: 0,
+            contrast: 0,
+            original_colors: [None; RECOLOR_COUNT],
+            replacement_colors: [None; RECOLOR_COUNT],
+        }
+    }
+
+    /// Pairs of the colours of the model and the colours that replace them. Like the client,
+    /// a colour without a replacement is replaced by 0.
+    pub fn recolors(&self) -> Vec<(u16, u16)> {
+        self.original_colors
+            .iter()
+            .zip(self.replacement_colors.iter())
+            .filter_map(|(original, replacement)| Some(((*original)?, replacement.unwrap_or(0))))
+            .collect()
+    }
+}
+
+impl Definition for SpotAnimDefinition {
+    const FILE_NAME: &'static str = "spotanim";
+    const INDEXED: bool = false;
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn decode(id: u32, buffer: &mut &[u8]) -> Result<Self, FileSystemError> {
+        let mut spotanim = SpotAnimDefinition::new(id);
+        loop {
+            let opcode = buffer.read_u8()?;
+            if opcode != END_OPCODE {
+                config::record_opcode(&mut spotanim.opcodes, opcode);
+            }
+            match opcode {
+                END_OPCODE => return Ok(spotanim),
+                1 => spotanim.model_id = buffer.read_u16::<BigEndian>()?,
+                2 => spotanim.sequence_id = Some(buffer.read_u16::<BigEndian>()?),
+                4 => spotanim.scale_xy = buffer.read_u16::<BigEndian>()?,
+                5 => spotanim.scale_z = buffer.read_u16::<BigEndian>()?,
+                6 => spotanim.rotation = buffer.read_u16::<BigEndian>()?,
+                7 => spotanim.ambient = buffer.read_u8()?,
+                8 => spotanim.contrast = buffer.read_u8()?,
+                40..=45 => {
+                    spotanim.original_colors[(opcode - 40) as usize] =
+                        Some(buffer.read_u16::<BigEndian>()?)
+                }
+                50..=55 => {
+                    spotanim.replacement_colors[(opcode - 50) as usize] =
+                        Some(buffer.read_u16::<BigEndian>()?)
+                }
+                _ => {
+                    return Err(FileSystemError::InvalidOpcode {
+                        definition: Self::FILE_NAME,
+                        id,
+                        opcode,
+                    })
+                }
+            }
+        }
+    }
+
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), FileSystemError> {
+        config::encode_opcodes(buffer, &self.opcodes, OPCODES, |buffer, opcode, stored| {
+            self.encode_opcode(buffer, opcode, stored)
+        })
+    }
+}
+
+impl SpotAnimDefinition {
+    // writes a single opcode if the spot animation has a value for it
+    fn encode_opcode(
+        &self,
+        buffer: &mut Vec<u8>,
+        opcode: u8,
+        stored: bool,
+    ) -> Result<(), FileSystemError> {
+        match opcode {
+            1 if stored || self.model_id != 0 => config::write_u16(buffer, 1, self.model_id)?,
+            2 => {
+                if let Some(sequence_id) = self.sequence_id {
+                    config::write_u16(buffer, 2, sequence_id)?;
+                }
+            }
+            4 if stored || self.scale_xy != DEFAULT_SCALE => {
+                config::write_u16(buffer, 4, self.scale_xy)?
+            }
+            5 if stored || self.scale_z != DEFAULT_SCALE => {
+                config::write_u16(buffer, 5, self.scale_z)?
+            }
+            6 if stored || self.rotation != 0 => config::write_u16(buffer, 6, self.rotation)?,
+            7 if stored || self.ambient != 0 => {
+                buffer.write_u8(7)?;
+                buffer.write_u8(self.ambient)?;
+            }
+            8 if stored || self.contrast != 0 => {
+                buffer.write_u8(8)?;
+                buffer.write_u8(self.contrast)?;
+            }
+            40..=45 | 50..=55 => {
+                let colors = match opcode {
+                    40..=45 => &self.original_colors,
+                    _ => &self.replacement_colors,
+                };
+                if let Some(color) = colors[(opcode % 10) as usize] {
+                    config::write_u16(buffer, opcode, color)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::spotanim::SpotAnimDefinition;
+    use crate::config::Definition;
+
+    #[rustfmt::skip]
+    const SPLASH: &[u8] = &[
+        1, 0x0C, 0x80,
+        2, 0x02, 0x9B,
+        4, 0x00, 0x64,
+        7, 0x0A,
+        40, 0x11, 0x11,
+        41, 0x22, 0x22,
+        50, 0x33, 0x33,
+        0,
+    ];
+
+    #[test]
+    fn test_spotanim_round_trip() {
+        let spotanim = SpotAnimDefinition::decode(85, &mut &SPLASH[..]).unwrap();
+        assert_eq!(spotanim.model_id, 3200);
+        assert_eq!(spotanim.sequence_id, Some(667));
+        assert_eq!(spotanim.scale_xy, 100);
+        assert_eq!(spotanim.scale_z, 128);
+        assert_eq!(spotanim.ambient, 10);
+        assert_eq!(spotanim.recolors(), vec![(0x1111, 0x3333), (0x2222, 0)]);
+        let mut encoded = Vec::new();
+        spotanim.encode(&mut encoded).unwrap();
+        assert_eq!(encoded, SPLASH);
+    }
+
+    #[test]
+    fn test_spotanim_keeps_stored_defaults_and_order() {
+        #[rustfmt::skip]
+        let data: &[u8] = &[
+            2, 0x00, 0x01,
+            1, 0x00, 0x05,
+            4, 0x00, 0x80,
+            7, 0,
+            0,
+        ];
+        let spotanim = SpotAnimDefinition::decode(90, &mut &data[..]).unwrap();
+        assert_eq!(spotanim.scale_xy, 128);
+        let mut encoded = Vec::new();
+        spotanim.encode(&mut encoded).unwrap();
+        assert_eq!(encoded, data);
+    }
+}
@@ -0,0 +1,225 @@
+use crate::buffer::BufferReadExt;
+use crate::config::{self, Definition, END_OPCODE};
+use crate::errors::FileSystemError;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+// every opcode in the order new definitions are encoded in
+const OPCODES: &[u8] = &[1, 2, 3, 4, 10];
+
+/// A variable from `varbit.dat` that is stored in a range of bits of a varp.
+///
+/// Every field holds the value as it is stored, and the opcodes are kept in the order they were
+/// stored in, so a decoded definition encodes back into the same bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VarbitDefinition {
+    pub id: u32,
+    /// The opcodes the definition was decoded from, in their stored order. These are encoded
+    /// first, even when they hold the default value, followed by every other field that differs
+    /// from its default.
+    pub opcodes: Vec<u8>,
+    /// The id of the varp that holds the bits.
+    pub varp_id: u16,
+    /// The lowest bit of the range, counting from 0.
+    pub least_significant_bit: u8,
+    /// The highest bit of the range, including the bit itself.
+    pub most_significant_bit: u8,
+    /// Opcode 2, read but never used by the client.
+    pub unused_flag_2: bool,
+    pub unused_opcode_3: Option<i32>,
+    pub unused_opcode_4: Option<i32>,
+    pub unused_opcode_10: Option<String>,
+}
+
+impl VarbitDefinition {
+    pub fn new(id: u32) -> Self {
+        VarbitDefinition {
+            id,
+            opcodes: Vec::new(),
+            varp_id: 0,
+            least_significant_bit: 0,
+            most_significant_bit: 0,
+            unused_flag_2: false,
+            unused_opcode_3: None,
+            unused_opcode_4: None,
+            unused_opcode_10: None,
+        }
+    }
+
+    /// The mask of the bit range, shifted down to bit 0.
+    pub fn mask(&self) -> u32 {
+        let bits = self
+            .most_significant_bit
+            .saturating_sub(self.least_significant_bit)
+            .min(31);
+        u32::MAX >> (31 - bits)
+    }
+
+    /// The value of the varbit in the given varp values, indexed by varp id, the same way the
+    /// client reads it. Returns `None` when the varp lies outside of the values.
+    pub fn evaluate(&self, varps: &[i32]) -> Option<i32> {
+        let varp = *varps.get(self.varp_id as usize)? as u32;
+        let shift = u32::from(self.least_significant_bit.min(31));
+        Some(((varp >> shift) & self.mask()) as i32)
+    }
+
+    /// Stores a value in the bits of the varbit, leaving the other bits of the varp untouched.
+    /// Bits of the value that do not fit in the range are dropped. Returns `false` when the varp
+    /// lies outside of the values.
+    pub fn assign(&self, varps: &mut [i32], value: i32) -> bool {
+        let varp = match varps.get_mut(self.varp_id as usize) {
+            Some(varp) => varp,
+            None => return false,
+        };
+        let shift = u32::from(self.least_significant_bit.min(31));
+        let mask = self.mask() << shift;
+        *varp = ((*varp as u32 & !mask) | ((value as u32) << shift & mask)) as i32;
+        true
+    }
+}
+
+impl Definition for VarbitDefinition {
+    const FILE_NAME: &'static str = "varbit";
+    const INDEXED: bool = false;
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn decode(id: u32, buffer: &mut &[u8]) -> Result<Self, FileSystemError> {
+        let mut varbit = VarbitDefinition::new(id);
+        loop {
+            let opcode = buffer.read_u8()?;
+            if opcode != END_OPCODE {
+                config::record_opcode(&mut varbit.opcodes, opcode);
+            }
+            match opcode {
+                END_OPCODE => return Ok(varbit),
+                1 => {
+                    varbit.varp_id = buffer.read_u16::<BigEndian>()?;
+                    varbit.least_significant_bit = buffer.read_u8()?;
+                    varbit.most_significant_bit = buffer.read_u8()?;
+                }
+                2 => varbit.unused_flag_2 = true,
+                3 => varbit.unused_opcode_3 = Some(buffer.read_i32::<BigEndian>()?),
+                4 => varbit.unused_opcode_4 = Some(buffer.read_i32::<BigEndian>()?),
+                10 => varbit.unused_opcode_10 = Some(buffer.read_string()?),
+                _ => {
+                    return Err(FileSystemError::InvalidOpcode {
+                        definition: Self::FILE_NAME,
+                        id,
+                        opcode,
+                    })
+                }
+            }
+        }
+    }
+
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), FileSystemError> {
+        config::encode_opcodes(buffer, &self.opcodes, OPCODES, |buffer, opcode, stored| {
+            self.encode_opcode(buffer, opcode, stored)
+        })
+    }
+}
+
+impl VarbitDefinition {
+    // writes a single opcode if the varbit has a value for it
+    fn encode_opcode(
+        &self,
+        buffer: &mut Vec<u8>,
+        opcode: u8,
+        stored: bool,
+    ) -> Result<(), FileSystemError> {
+        let optional_i32 = match opcode {
+            3 => self.unused_opcode_3,
+            4 => self.unused_opcode_4,
+            _ => None,
+        };
+        match opcode {
+            1 if stored
+                || self.varp_id != 0
+                || self.least_significant_bit != 0
+                || self.most_significant_bit != 0 =>
+            {
+                config::write_u16(buffer, 1, self.varp_id)?;
+                buffer.write_u8(self.least_significant_bit)?;
+                buffer.write_u8(self.most_significant_bit)?;
+            }
+            2 if self.unused_flag_2 => buffer.write_u8(2)?,
+            3 | 4 => {
+                if let Some(value) = optional_i32 {
+                    buffer.write_u8(opcode)?;
+                    buffer.write_i32::<BigEndian>(value)?;
+                }
+            }
+            10 => {
+                if let Some(value) = &self.unused_opcode_10 {
+                    config::write_string(buffer, 10, value)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::archive::{Archive, ArchiveCompression};
+    use crate::config::varbit::VarbitDefinition;
+    use crate::config::{self, Definition};
+
+    #[test]
+    fn test_varbit_round_trip() {
+        let data = [1, 0x00, 0x2B, 4, 6, 0];
+        let varbit = VarbitDefinition::decode(0, &mut &data[..]).unwrap();
+        assert_eq!(varbit.varp_id, 43);
+        assert_eq!(varbit.least_significant_bit, 4);
+        assert_eq!(varbit.most_significant_bit, 6);
+        let mut encoded = Vec::new();
+        varbit.encode(&mut encoded).unwrap();
+        assert_eq!(encoded, data);
+    }
+
+    #[test]
+    fn test_varbit_evaluation() {
+        let mut varbit = VarbitDefinition::new(0);
+        varbit.varp_id = 1;
+        varbit.least_significant_bit = 4;
+        varbit.most_significant_bit = 6;
+        assert_eq!(varbit.mask(), 0b111);
+        let mut varps = [0, 0b1101_0110];
+        assert_eq!(varbit.evaluate(&varps), Some(0b101));
+        assert!(varbit.assign(&mut varps, 0b1010));
+        assert_eq!(varps[1], 0b1010_0110);
+        assert_eq!(varbit.evaluate(&varps), Some(0b010));
+
+        varbit.least_significant_bit = 0;
+        varbit.most_significant_bit = 31;
+        assert_eq!(varbit.mask(), u32::MAX);
+        varps[1] = -5;
+        assert_eq!(varbit.evaluate(&varps), Some(-5));
+        varbit.varp_id = 2;
+        assert_eq!(varbit.evaluate(&varps), None);
+        assert!(!varbit.assign(&mut varps, 1));
+    }
+
+    #[test]
+    fn test_varbit_keeps_stored_defaults_and_order() {
+        let data = [2, 1, 0x00, 0x00, 0, 0, 0];
+        let varbit = VarbitDefinition::decode(0, &mut &data[..]).unwrap();
+        assert_eq!(varbit.varp_id, 0);
+        let mut encoded = Vec::new();
+        varbit.encode(&mut encoded).unwrap();
+        assert_eq!(encoded, data);
+    }
+
+    #[test]
+    fn test_varbits_through_archive() {
+        let varbits: Vec<VarbitDefinition> = (0..4).map(VarbitDefinition::new).collect();
+        let mut archive = Archive::new(ArchiveCompression::Whole);
+        config::encode_all(&varbits, &mut archive).unwrap();
+        let decoded: Vec<VarbitDefinition> = config::decode_all(&archive).unwrap();
+        assert_eq!(decoded, varbits);
+    }
+}
@@ -0,0 +1,169 @@
+use crate::buffer::BufferReadExt;
+use crate::config::{self, Definition, END_OPCODE};
+use crate::errors::FileSystemError;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+/// The opcodes without a value that the client reads without using them.
+pub const FLAG_OPCODES: [u8; 6] = [3, 4, 6, 8, 11, 13];
+// every opcode in the order new definitions are encoded in
+const OPCODES: &[u8] = &[1, 2, 3, 4, 5, 6, 7, 8, 10, 11, 12, 13];
+
+/// A player variable from `varp.dat`.
+///
+/// Every field holds the value as it is stored, and the opcodes are kept in the order they were
+/// stored in, so a decoded definition encodes back into the same bytes. Apart from the client
+/// code, every opcode is read by the client without being used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VarpDefinition {
+    pub id: u32,
+    /// The opcodes the definition was decoded from, in their stored order. These are encoded
+    /// first, even when they hold the default value, followed by every other field that differs
+    /// from its default.
+    pub opcodes: Vec<u8>,
+    /// The code the client runs when the value of the varp changes, like the brightness or
+    /// music volume settings.
+    pub client_code: u16,
+    pub unused_opcode_1: Option<u8>,
+    pub unused_opcode_2: Option<u8>,
+    pub unused_opcode_7: Option<i32>,
+    pub unused_opcode_10: Option<String>,
+    pub unused_opcode_12: Option<i32>,
+    /// The `FLAG_OPCODES` the definition contains.
+    pub unused_flags: Vec<u8>,
+}
+
+impl VarpDefinition {
+    pub fn new(id: u32) -> Self {
+        VarpDefinition {
+            id,
+            opcodes: Vec::new(),
+            client_code: 0,
+            unused_opcode_1: None,
+            unused_opcode_2: None,
+            unused_opcode_7: None,
+            unused_opcode_10: None,
+            unused_opcode_12: None,
+            unused_flags: Vec::new(),
+        }
+    }
+}
+
+impl Definition for VarpDefinition {
+    const FILE_NAME: &'static str = "varp";
+    const INDEXED: bool = false;
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn decode(id: u32, buffer: &mut &[u8]) -> Result<Self, FileSystemError> {
+        let mut varp = VarpDefinition::new(id);
+        loop {
+            let opcode = buffer.read_u8()?;
+            if opcode != END_OPCODE {
+                config::record_opcode(&mut varp.opcodes, opcode);
+            }
+            match opcode {
+                END_OPCODE => return Ok(varp),
+                1 => varp.unused_opcode_1 = Some(buffer.read_u8()?),
+                2 => varp.unused_opcode_2 = Some(buffer.read_u8()?),
+                5 => varp.client_code = buffer.read_u16::<BigEndian>()?,
+                7 => varp.unused_opcode_7 = Some(buffer.read_i32::<BigEndian>()?),
+                10 => varp.unused_opcode_10 = Some(buffer.read_string()?),
+                12 => varp.unused_opcode_12 = Some(buffer.read_i32::<BigEndian>()?),
+                _ if FLAG_OPCODES.contains(&opcode) => {
+                    if !varp.unused_flags.contains(&opcode) {
+                        varp.unused_flags.push(opcode);
+                    }
+                }
+                _ => {
+                    return Err(FileSystemError::InvalidOpcode {
+                        definition: Self::FILE_NAME,
+                        id,
+                        opcode,
+                    })
+                }
+            }
+        }
+    }
+
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), FileSystemError> {
+        config::encode_opcodes(buffer, &self.opcodes, OPCODES, |buffer, opcode, stored| {
+            self.encode_opcode(buffer, opcode, stored)
+        })
+    }
+}
+
+impl VarpDefinition {
+    // writes a single opcode if the varp has a value for it
+    fn encode_opcode(
+        &self,
+        buffer: &mut Vec<u8>,
+        opcode: u8,
+        stored: bool,
+    ) -> Result<(), FileSystemError> {
+        let optional_u8 = match opcode {
+            1 => self.unused_opcode_1,
+            2 => self.unused_opcode_2,
+            _ => None,
+        };
+        let optional_i32 = match opcode {
+            7 => self.unused_opcode_7,
+            12 => self.unused_opcode_12,
+            _ => None,
+        };
+        match opcode {
+            1 | 2 => {
+                if let Some(value) = optional_u8 {
+                    buffer.write_u8(opcode)?;
+                    buffer.write_u8(value)?;
+                }
+            }
+            5 if stored || self.client_code != 0 => config::write_u16(buffer, 5, self.client_code)?,
+            7 | 12 => {
+                if let Some(value) = optional_i32 {
+                    buffer.write_u8(opcode)?;
+                    buffer.write_i32::<BigEndian>(value)?;
+                }
+            }
+            10 => {
+                if let Some(value) = &self.unused_opcode_10 {
+                    config::write_string(buffer, 10, value)?;
+                }
+            }
+            _ if self.unused_flags.contains(&opcode) => buffer.write_u8(opcode)?,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::varp::VarpDefinition;
+    use crate::config::Definition;
+
+    #[test]
+    fn test_varp_round_trip() {
+        let data = [1, 7, 3, 5, 0x00, 0x06, 10, b'x', 10, 13, 0];
+        let varp = VarpDefinition::decode(166, &mut &data[..]).unwrap();
+        assert_eq!(varp.client_code, 6);
+        assert_eq!(varp.unused_opcode_1, Some(7));
+        assert_eq!(varp.unused_opcode_10.as_deref(), Some("x"));
+        assert_eq!(varp.unused_flags, vec![3, 13]);
+        let mut encoded = Vec::new();
+        varp.encode(&mut encoded).unwrap();
+        assert_eq!(encoded, data);
+    }
+
+    #[test]
+    fn test_varp_keeps_stored_defaults_and_order() {
+        let data = [13, 5, 0x00, 0x00, 2, 0, 3, 0];
+        let varp = VarpDefinition::decode(0, &mut &data[..]).unwrap();
+        assert_eq!(varp.client_code, 0);
+        let mut encoded = Vec::new();
+        varp.encode(&mut encoded).unwrap();
+        assert_eq!(encoded, data);
+    }
+}
@@ -1,5 +1,6 @@
 use crate::filesystem::{BLOCK_HEADER_EXTENDED_SIZE, BLOCK_HEADER_SIZE};
 use crate::index::IndexType;
+use std::fmt;
 use std::io;
 use thiserror::Error;
 
@@ -17,6 +18,88 @@ pub enum FileSystemError {
     IndexNotFound { index_type: IndexType },
     #[error("Could not find index entry {0} in cache.")]
     IndexEntryNotFound(u32),
+    #[error("Cannot store an entry of {0} bytes. Entries are limited to 16777215 bytes.")]
+    EntryTooLarge(usize),
+    #[error("Cannot store {0} entries in an archive. Archives hold at most 65535 entries.")]
+    TooManyArchiveEntries(usize),
+    #[error("The cache was opened for reading only. Use FileSystem::open_writable to change it.")]
+    ReadOnly,
+    #[error("The data file has no room for more sectors.")]
+    DataFileFull,
+    #[error("Could not find archive entry {0}.")]
+    ArchiveEntryNotFound(String),
+    #[error("Could not find {definition} definition {id}.")]
+    DefinitionNotFound { definition: &'static str, id: u32 },
+    #[error(
+        "Cannot store {count} {definition} definitions. At most 65535 fit in the config archive."
+    )]
+    TooManyDefinitions {
+        definition: &'static str,
+        count: usize,
+    },
+    #[error(
+        "{definition} definition {id} takes {size} bytes. Definitions are limited to 65535 bytes."
+    )]
+    DefinitionTooLarge {
+        definition: &'static str,
+        id: u32,
+        size: usize,
+    },
+    #[error("Unknown opcode {opcode} in {definition} definition {id}.")]
+    InvalidOpcode {
+        definition: &'static str,
+        id: u32,
+        opcode: u8,
+    },
+    #[error("Widget {widget} has {count} {element}, more than the {max} an interface can hold.")]
+    WidgetTooLarge {
+        widget: u16,
+        element: &'static str,
+        count: usize,
+        max: usize,
+    },
+    #[error(
+        "Invalid sprite reference \"{0}\". It should be a name and a frame separated by a comma."
+    )]
+    InvalidSpriteReference(String),
+    #[error("Could not find frame {frame} of sprite {name}.")]
+    SpriteFrameNotFound { name: String, frame: u32 },
+    #[error("Unknown pixel order {0}. It should be 0 for rows or 1 for columns.")]
+    InvalidPixelOrder(u8),
+    #[error("Cannot write sprite {0} without any frames.")]
+    EmptySprite(String),
+    #[error("A palette of {0} colours does not fit in a sprite. Sprites hold at most 255 colours, including transparency.")]
+    SpritePaletteTooLarge(usize),
+    #[error("A sprite frame of {width}x{height} pixels has {actual} pixels.")]
+    SpriteFrameSizeMismatch {
+        width: u16,
+        height: u16,
+        actual: usize,
+    },
+    #[error("An image of {width}x{height} pixels is too large for a sprite.")]
+    ImageTooLarge { width: u32, height: u32 },
+    #[error("The sprite index is full. A sprite header cannot start at offset {0}.")]
+    SpriteIndexFull(usize),
+    #[error("A model of {0} bytes is too short for its 18 byte footer.")]
+    ModelTooShort(usize),
+    #[error("A model cannot hold {count} {element}.")]
+    ModelTooLarge { element: &'static str, count: usize },
+    #[error("A model has {actual} {attribute} where it should have {expected}.")]
+    ModelAttributeMismatch {
+        attribute: &'static str,
+        expected: usize,
+        actual: usize,
+    },
+    #[error("Cannot export a model without faces as binary glTF.")]
+    EmptyModelExport,
+    #[error("Could not import model: {0}")]
+    InvalidModelImport(String),
+    #[error("PNG decoding error.")]
+    PngDecoding(#[from] png::DecodingError),
+    #[error("PNG encoding error.")]
+    PngEncoding(#[from] png::EncodingError),
+    #[error("Index {0} has no version list.")]
+    VersionListNotFound(u8),
     #[error("Could not find archive {0} in cache.")]
     ArchiveNotFound(u32),
     #[error(
@@ -26,16 +109,41 @@ pub enum FileSystemError {
     )]
     InvalidBlockHeaderLength(usize),
     #[error(
-        "Sector {} mismatch. Expected: {}, actual: {}.",
+        "Sector {} {} mismatch. Expected: {}, actual: {}.",
+        sector,
         data_type,
-        actual,
-        expected
+        expected,
+        actual
     )]
     SectorReadingDataMismatch {
-        data_type: String,
+        sector: u64,
+        data_type: SectorDataType,
         expected: usize,
         actual: usize,
     },
+    #[error("Sector {0} lies outside of the data file.")]
+    SectorOutOfBounds(u64),
+    #[error("Sector {0} appears twice in the same sector chain.")]
+    SectorCycle(u64),
+}
+
+/// The part of a sector header that did not hold the expected value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectorDataType {
+    IndexId,
+    Sequence,
+    EntryId,
+}
+
+impl fmt::Display for SectorDataType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            SectorDataType::IndexId => "index id",
+            SectorDataType::Sequence => "sequence block",
+            SectorDataType::EntryId => "file entry id",
+        };
+        f.write_str(name)
+    }
 }
 
 #[derive(Error, Debug)]
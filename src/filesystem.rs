@@ -1,12 +1,16 @@
 use crate::archive::{Archive, ArchiveType};
-use crate::index::{Index, IndexType};
+use crate::index::{Index, IndexEntry, IndexType};
 
 use std::collections::HashMap;
 use std::convert::TryFrom;
 
-use crate::errors::FileSystemError;
+use crate::errors::{FileSystemError, SectorDataType};
+use crate::versionlist::{
+    self, AnimIndex, CrcList, MapIndex, MidiIndex, ModelIndex, VersionList, ANIM_INDEX_ENTRY_NAME,
+    MAP_INDEX_ENTRY_NAME, MIDI_INDEX_ENTRY_NAME, MODEL_INDEX_ENTRY_NAME, VERSION_TRAILER_SIZE,
+};
 
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 
@@ -19,18 +23,34 @@ pub const BLOCK_CHUNK_SIZE: u32 = 512;
 pub const BLOCK_CHUNK_EXTENDED_SIZE: u32 = 510;
 pub const BLOCK_HEADER_SIZE: usize = 8;
 pub const BLOCK_HEADER_EXTENDED_SIZE: usize = 10;
+pub const MAX_ENTRY_SIZE: usize = 0xFF_FFFF;
+pub const MAX_BLOCK_COUNT: u64 = 0xFF_FFFF;
+const DEFRAGMENT_DIRECTORY_NAME: &str = ".defragment";
+const DEFRAGMENT_BACKUP_DIRECTORY_NAME: &str = "original";
 
 #[derive(Debug)]
 pub struct FileSystem {
+    path: PathBuf,
+    writable: bool,
     main_data_file: File,
     indices: HashMap<u8, Index>,
 }
 
 impl FileSystem {
+    /// Opens the cache at `base` for reading only.
     pub fn new<P: AsRef<Path>>(base: P) -> Result<Self, FileSystemError> {
-        let path = base.as_ref();
+        FileSystem::open(base.as_ref(), false)
+    }
+
+    /// Opens the cache at `base` for both reading and writing, which every method that changes
+    /// the cache needs.
+    pub fn open_writable<P: AsRef<Path>>(base: P) -> Result<Self, FileSystemError> {
+        FileSystem::open(base.as_ref(), true)
+    }
+
+    fn open(path: &Path, writable: bool) -> Result<Self, FileSystemError> {
         let main_data_file_path = &path.join(DEFAULT_DATA_FILE_NAME);
-        let main_data_file = File::open(main_data_file_path).map_err(|e| {
+        let main_data_file = open_file(main_data_file_path, writable).map_err(|e| {
             FileSystemError::DataFileNotFound(format!(
                 "Problem loading {}. {}",
                 DEFAULT_DATA_FILE_NAME, e
@@ -46,16 +66,56 @@ impl FileSystem {
                 .map(|index_id: u8| {
                     (
                         index_id,
-                        Index::new(index_id, File::open(index_file_path(&index_id)).unwrap()),
+                        Index::new(
+                            index_id,
+                            open_file(index_file_path(&index_id), writable).unwrap(),
+                        ),
                     )
                 }),
         );
         Ok(FileSystem {
+            path: path.to_path_buf(),
+            writable,
             main_data_file,
             indices,
         })
     }
 
+    /// Creates an empty cache at `base` containing an empty data file and one empty index file for
+    /// each of the given index types, and opens it for writing.
+    ///
+    /// Fails if a data file or any of the index files already exists, in which case none of the
+    /// files created so far are left behind.
+    pub fn create<P: AsRef<Path>>(
+        base: P,
+        index_types: &[IndexType],
+    ) -> Result<Self, FileSystemError> {
+        let path = base.as_ref();
+        std::fs::create_dir_all(path)?;
+        let mut file_paths = vec![path.join(DEFAULT_DATA_FILE_NAME)];
+        file_paths.extend(index_types.iter().map(|index_type| {
+            path.join(format!("{}{}", DEFAULT_INDEX_FILE_PREFIX, index_type.id()))
+        }));
+        let mut created_paths = Vec::new();
+        let mut create_files = || -> Result<Self, FileSystemError> {
+            for file_path in &file_paths {
+                OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(file_path)?;
+                created_paths.push(file_path);
+            }
+            FileSystem::open_writable(path)
+        };
+        let result = create_files();
+        if result.is_err() {
+            for created_path in created_paths {
+                let _ = std::fs::remove_file(created_path);
+            }
+        }
+        result
+    }
+
     pub fn index(&self, index_type: IndexType) -> Result<&Index, FileSystemError> {
         let index_id = index_type.id();
         match self.indices.get(&index_id) {
@@ -73,6 +133,75 @@ impl FileSystem {
         self.indices.len() as u8
     }
 
+    /// The ids of the indexes in the cache, in ascending order.
+    pub fn index_ids(&self) -> Vec<u8> {
+        let mut index_ids: Vec<u8> = self.indices.keys().copied().collect();
+        index_ids.sort_unstable();
+        index_ids
+    }
+
+    /// Writes a defragmented copy of the cache to `output`.
+    ///
+    /// Every entry of every index is rewritten so that its sectors are contiguous, and sectors that
+    /// no entry refers to are left behind. The output directory must not contain a cache yet.
+    pub fn defragment<P: AsRef<Path>>(
+        &self,
+        output: P,
+    ) -> Result<DefragmentReport, FileSystemError> {
+        let index_ids = self.index_ids();
+        let index_types: Vec<IndexType> = index_ids.iter().map(|id| IndexType::new(*id)).collect();
+        let mut defragmented = FileSystem::create(output, &index_types)?;
+        let mut entries_moved = 0;
+        for index_id in index_ids {
+            let index = &self.indices[&index_id];
+            for entry_id in 0..index.file_count() as u32 {
+                let index_entry = index.entry(entry_id)?;
+                let data = self.read_sectors(index_id, &index_entry, &mut Vec::new())?;
+                defragmented.write(IndexType::new(index_id), entry_id, &data)?;
+                let offset = defragmented.indices[&index_id].entry(entry_id)?.offset();
+                if !data.is_empty() && offset != index_entry.offset() {
+                    entries_moved += 1;
+                }
+            }
+        }
+        let old_size = self.main_data_file.metadata()?.len();
+        let new_size = defragmented.main_data_file.metadata()?.len();
+        Ok(DefragmentReport {
+            bytes_reclaimed: old_size.saturating_sub(new_size),
+            entries_moved,
+        })
+    }
+
+    /// Defragments the cache in place.
+    ///
+    /// The defragmented cache is first written to a temporary directory inside the cache
+    /// directory, so an error while defragmenting leaves the original cache untouched. Its files
+    /// are then moved into place one at a time, the index files first and the data file last,
+    /// with every original file moved aside first. When a move fails, the original files that
+    /// were already replaced are moved back. Only if moving them back fails as well, which needs
+    /// a second error from the file system, is the cache left with index files that do not match
+    /// its data file; the original files are then kept in `.defragment/original`.
+    pub fn defragment_in_place(&mut self) -> Result<DefragmentReport, FileSystemError> {
+        self.check_writable()?;
+        let temporary_path = self.path.join(DEFRAGMENT_DIRECTORY_NAME);
+        if temporary_path.exists() {
+            std::fs::remove_dir_all(&temporary_path)?;
+        }
+        let report = self.defragment(&temporary_path)?;
+        let mut file_names: Vec<String> = self
+            .index_ids()
+            .iter()
+            .map(|index_id| format!("{}{}", DEFAULT_INDEX_FILE_PREFIX, index_id))
+            .collect();
+        file_names.push(DEFAULT_DATA_FILE_NAME.to_owned());
+        let backup_path = temporary_path.join(DEFRAGMENT_BACKUP_DIRECTORY_NAME);
+        std::fs::create_dir(&backup_path)?;
+        replace_files(&temporary_path, &backup_path, &self.path, &file_names)?;
+        std::fs::remove_dir_all(&temporary_path)?;
+        *self = FileSystem::open_writable(&self.path)?;
+        Ok(report)
+    }
+
     pub fn read_archive(&self, archive_type: ArchiveType) -> Result<Archive, FileSystemError> {
         let file_data = self.read(IndexType::ARCHIVE, archive_type.id());
         let file_data = match file_data {
@@ -82,17 +211,140 @@ impl FileSystem {
         Archive::try_from(file_data)
     }
 
+    /// Encodes the archive and stores it in the archive index, returning the CRC of the encoded
+    /// archive that the client's CRC table should now hold for it.
+    pub fn write_archive(
+        &mut self,
+        archive_type: ArchiveType,
+        archive: &Archive,
+    ) -> Result<u32, FileSystemError> {
+        let data = archive.encode()?;
+        self.write(IndexType::ARCHIVE, archive_type.id(), &data)?;
+        Ok(CrcList::checksum(&data))
+    }
+
+    /// Computes the CRC table the client checks its archives against, indexed by archive id.
+    ///
+    /// Archives that are missing from the cache have a CRC of 0.
+    pub fn archive_crcs(&self) -> Result<Vec<u32>, FileSystemError> {
+        let archive_count = self.file_count(IndexType::ARCHIVE)? as u32;
+        let mut crcs = Vec::with_capacity(archive_count as usize);
+        for archive_id in 0..archive_count {
+            let data = self.read(IndexType::ARCHIVE, archive_id)?;
+            crcs.push(if data.is_empty() {
+                0
+            } else {
+                CrcList::checksum(&data)
+            });
+        }
+        Ok(crcs)
+    }
+
+    /// Writes `data` to an entry of one of the on-demand indexes and refreshes the entry's version
+    /// and CRC in the versions archive, so the client picks up the new file.
+    ///
+    /// The version of the entry is increased by one and appended to `data` as the two byte trailer
+    /// the client expects. The CRC covers `data` without that trailer.
+    pub fn write_versioned(
+        &mut self,
+        index_type: IndexType,
+        entry_id: u32,
+        data: &[u8],
+    ) -> Result<(), FileSystemError> {
+        let index_id = index_type.id();
+        let (version_name, crc_name) = versionlist::entry_names(index_id)
+            .ok_or_else(|| FileSystemError::VersionListNotFound(index_type.id()))?;
+        let mut versions_archive = self.read_archive(ArchiveType::VERSIONS)?;
+        let mut versions = VersionList::from_archive(&versions_archive, version_name)?;
+        let mut crcs = CrcList::from_archive(&versions_archive, crc_name)?;
+        let version = versions.get(entry_id).map_or(1, |version| version + 1);
+        versions.set(entry_id, version);
+        crcs.set(entry_id, CrcList::checksum(data));
+        versions_archive.insert(version_name, versions.encode()?);
+        versions_archive.insert(crc_name, crcs.encode()?);
+        let mut versioned_data = Vec::with_capacity(data.len() + VERSION_TRAILER_SIZE);
+        versioned_data.extend_from_slice(data);
+        versioned_data.extend_from_slice(&(version as u16).to_be_bytes());
+        self.write(index_type, entry_id, &versioned_data)?;
+        self.write_archive(ArchiveType::VERSIONS, &versions_archive)?;
+        Ok(())
+    }
+
+    /// Reads the version list of one of the on-demand indexes from the versions archive.
+    ///
+    /// Like `write_versioned`, a version list that is missing from the archive is empty.
+    pub fn versions(&self, index_type: IndexType) -> Result<VersionList, FileSystemError> {
+        let (version_name, _) = versionlist::entry_names(index_type.id())
+            .ok_or_else(|| FileSystemError::VersionListNotFound(index_type.id()))?;
+        let archive = self.read_archive(ArchiveType::VERSIONS)?;
+        VersionList::from_archive(&archive, version_name)
+    }
+
+    /// Reads the CRC list of one of the on-demand indexes from the versions archive.
+    ///
+    /// Like `write_versioned`, a CRC list that is missing from the archive is empty.
+    pub fn crcs(&self, index_type: IndexType) -> Result<CrcList, FileSystemError> {
+        let (_, crc_name) = versionlist::entry_names(index_type.id())
+            .ok_or_else(|| FileSystemError::VersionListNotFound(index_type.id()))?;
+        let archive = self.read_archive(ArchiveType::VERSIONS)?;
+        CrcList::from_archive(&archive, crc_name)
+    }
+
+    /// Reads the flags of every model from the versions archive.
+    pub fn model_index(&self) -> Result<ModelIndex, FileSystemError> {
+        let data = self.versions_entry(MODEL_INDEX_ENTRY_NAME)?;
+        ModelIndex::decode(&mut &data[..], data.len())
+    }
+
+    /// Reads the `anim_index` table from the versions archive.
+    pub fn anim_index(&self) -> Result<AnimIndex, FileSystemError> {
+        let data = self.versions_entry(ANIM_INDEX_ENTRY_NAME)?;
+        AnimIndex::decode(&mut &data[..], data.len())
+    }
+
+    /// Reads which MIDI files are preloaded from the versions archive.
+    pub fn midi_index(&self) -> Result<MidiIndex, FileSystemError> {
+        let data = self.versions_entry(MIDI_INDEX_ENTRY_NAME)?;
+        MidiIndex::decode(&mut &data[..], data.len())
+    }
+
+    /// Reads the map files of every region from the versions archive.
+    pub fn map_index(&self) -> Result<MapIndex, FileSystemError> {
+        let data = self.versions_entry(MAP_INDEX_ENTRY_NAME)?;
+        MapIndex::decode(&mut &data[..], data.len())
+    }
+
+    fn versions_entry(&self, name: &str) -> Result<Vec<u8>, FileSystemError> {
+        let archive = self.read_archive(ArchiveType::VERSIONS)?;
+        match archive.entry_name(name) {
+            Some(entry) => Ok(entry.uncompressed_data().to_vec()),
+            None => Err(FileSystemError::ArchiveEntryNotFound(name.to_owned())),
+        }
+    }
+
     pub fn read(&self, index_type: IndexType, entry_id: u32) -> Result<Vec<u8>, FileSystemError> {
         let index = self.index(index_type)?;
         let index_entry = index.entry(entry_id)?;
         let index_id = index.index_type().id();
-        let ref mut main_data_file = &self.main_data_file;
+        self.read_sectors(index_id, &index_entry, &mut Vec::new())
+    }
+
+    // follows the sector chain of the entry, recording every sector that belongs to it in `blocks`
+    pub(crate) fn read_sectors(
+        &self,
+        index_id: u8,
+        index_entry: &IndexEntry,
+        blocks: &mut Vec<u64>,
+    ) -> Result<Vec<u8>, FileSystemError> {
+        let entry_id = index_entry.id();
+        let main_data_file = &self.main_data_file;
+        let block_count = block_count(main_data_file)?;
         let mut buffer: Vec<u8> = Vec::with_capacity(index_entry.size() as usize);
         let mut block = index_entry.offset();
         let mut remaining_bytes = index_entry.size();
         let mut current_sequence = 0;
         // if the entry id is larger than a unsigned short integer (65535)
-        let large = entry_id > u16::max_value() as u32;
+        let large = entry_id > u16::MAX as u32;
         let block_header_size = if large {
             BLOCK_HEADER_EXTENDED_SIZE
         } else {
@@ -104,43 +356,283 @@ impl FileSystem {
             BLOCK_CHUNK_SIZE
         };
         while remaining_bytes > 0 {
-            let mut block_data: [u8; TOTAL_BLOCK_SIZE as usize] = [0; TOTAL_BLOCK_SIZE as usize];
-            main_data_file.seek(SeekFrom::Start(block * TOTAL_BLOCK_SIZE))?;
-            main_data_file.read(&mut block_data)?;
-            let sector_header = CacheSectorHeader::try_from(&block_data[0..block_header_size])?;
+            // sector 0 is never handed out, so a chain pointing to it has been cut short
+            if block == 0 || block >= block_count {
+                return Err(FileSystemError::SectorOutOfBounds(block));
+            }
             // the bytes consumed in this iteration minus the header size
             let chunks_consumed = std::cmp::min(remaining_bytes, block_chunk_size);
             // the bytes consumed in this iteration plus the header size
-            let total_consumed: usize = chunks_consumed as usize + block_header_size as usize;
-            if remaining_bytes > 0 {
-                if sector_header.next_index_id != (index_id + 1) {
-                    return Err(FileSystemError::SectorReadingDataMismatch {
-                        data_type: "index id".to_owned(),
-                        expected: (index_id + 1) as usize,
-                        actual: sector_header.next_index_id as usize,
-                    });
+            let total_consumed: usize = chunks_consumed as usize + block_header_size;
+            let mut block_data: Vec<u8> = Vec::with_capacity(TOTAL_BLOCK_SIZE as usize);
+            let mut main_data_file = main_data_file;
+            main_data_file.seek(SeekFrom::Start(block * TOTAL_BLOCK_SIZE))?;
+            main_data_file
+                .take(TOTAL_BLOCK_SIZE)
+                .read_to_end(&mut block_data)?;
+            if block_data.len() < total_consumed {
+                return Err(FileSystemError::SectorOutOfBounds(block));
+            }
+            let sector_header = CacheSectorHeader::try_from(&block_data[0..block_header_size])?;
+            if sector_header.next_index_id != (index_id + 1) {
+                return Err(FileSystemError::SectorReadingDataMismatch {
+                    sector: block,
+                    data_type: SectorDataType::IndexId,
+                    expected: (index_id + 1) as usize,
+                    actual: sector_header.next_index_id as usize,
+                });
+            }
+            if sector_header.next_sequence != current_sequence {
+                // sequence numbers only go up, so a chain that loops back fails this check
+                if blocks.contains(&block) {
+                    return Err(FileSystemError::SectorCycle(block));
+                }
+                return Err(FileSystemError::SectorReadingDataMismatch {
+                    sector: block,
+                    data_type: SectorDataType::Sequence,
+                    expected: current_sequence as usize,
+                    actual: sector_header.next_sequence as usize,
+                });
+            }
+            if sector_header.next_entry_id != entry_id {
+                return Err(FileSystemError::SectorReadingDataMismatch {
+                    sector: block,
+                    data_type: SectorDataType::EntryId,
+                    expected: entry_id as usize,
+                    actual: sector_header.next_entry_id as usize,
+                });
+            }
+            buffer.extend_from_slice(&block_data[block_header_size..total_consumed]);
+            blocks.push(block);
+            remaining_bytes -= chunks_consumed;
+            block = sector_header.next_block;
+            current_sequence += 1;
+        }
+        Ok(buffer)
+    }
+
+    /// Writes `data` to the given entry, replacing whatever was stored there before.
+    ///
+    /// An existing entry reuses its sector chain and only appends new sectors once the chain
+    /// runs out. If the existing chain turns out to be broken, the data is written to a fresh
+    /// chain at the end of the data file instead.
+    pub fn write(
+        &mut self,
+        index_type: IndexType,
+        entry_id: u32,
+        data: &[u8],
+    ) -> Result<(), FileSystemError> {
+        self.check_writable()?;
+        if data.len() > MAX_ENTRY_SIZE {
+            return Err(FileSystemError::EntryTooLarge(data.len()));
+        }
+        let index_id = index_type.id();
+        if !self.indices.contains_key(&index_id) {
+            return Err(FileSystemError::IndexNotFound { index_type });
+        }
+        if !self.write_sectors(index_id, entry_id, data, true)? {
+            self.write_sectors(index_id, entry_id, data, false)?;
+        }
+        Ok(())
+    }
+
+    /// Removes the given entry by clearing its index record.
+    ///
+    /// The sectors that held the entry are left in the data file unless `scrub` is set, in which
+    /// case every sector of the entry's chain is zeroed out.
+    pub fn remove(
+        &mut self,
+        index_type: IndexType,
+        entry_id: u32,
+        scrub: bool,
+    ) -> Result<(), FileSystemError> {
+        self.check_writable()?;
+        let index_id = index_type.id();
+        let index_entry = self.index(index_type)?.entry(entry_id)?;
+        if scrub {
+            let mut blocks = Vec::new();
+            // a broken chain is only scrubbed up to the point where it stops belonging to the entry
+            if let Err(FileSystemError::Io(e)) =
+                self.read_sectors(index_id, &index_entry, &mut blocks)
+            {
+                return Err(FileSystemError::Io(e));
+            }
+            let mut main_data_file = &self.main_data_file;
+            let empty_block: [u8; TOTAL_BLOCK_SIZE as usize] = [0; TOTAL_BLOCK_SIZE as usize];
+            for block in blocks {
+                main_data_file.seek(SeekFrom::Start(block * TOTAL_BLOCK_SIZE))?;
+                main_data_file.write_all(&empty_block)?;
+            }
+        }
+        let index = self.indices.get_mut(&index_id).unwrap();
+        index.write_entry(&IndexEntry::new(entry_id, 0, 0))
+    }
+
+    /// Shrinks the index file of the given index by dropping its trailing empty entries.
+    ///
+    /// Returns the number of entries that were dropped.
+    pub fn truncate(&mut self, index_type: IndexType) -> Result<u64, FileSystemError> {
+        self.check_writable()?;
+        let index_id = index_type.id();
+        match self.indices.get_mut(&index_id) {
+            Some(index) => index.truncate(),
+            None => Err(FileSystemError::IndexNotFound { index_type }),
+        }
+    }
+
+    fn check_writable(&self) -> Result<(), FileSystemError> {
+        if !self.writable {
+            return Err(FileSystemError::ReadOnly);
+        }
+        Ok(())
+    }
+
+    // returns false if `overwrite` was requested but the existing sector chain could not be reused
+    fn write_sectors(
+        &mut self,
+        index_id: u8,
+        entry_id: u32,
+        data: &[u8],
+        overwrite: bool,
+    ) -> Result<bool, FileSystemError> {
+        let index = self.indices.get_mut(&index_id).unwrap();
+        if data.is_empty() {
+            index.write_entry(&IndexEntry::new(entry_id, 0, 0))?;
+            return Ok(true);
+        }
+        let mut main_data_file = &self.main_data_file;
+        let mut overwrite = overwrite;
+        let mut block_count = block_count(main_data_file)?;
+        let mut block = if overwrite {
+            match index.entry(entry_id) {
+                Ok(index_entry)
+                    if index_entry.offset() > 0 && index_entry.offset() <= block_count =>
+                {
+                    index_entry.offset()
                 }
-                if sector_header.next_sequence != current_sequence {
-                    return Err(FileSystemError::SectorReadingDataMismatch {
-                        data_type: "sequence block".to_owned(),
-                        expected: current_sequence as usize,
-                        actual: sector_header.next_sequence as usize,
-                    });
+                _ => return Ok(false),
+            }
+        } else {
+            std::cmp::max(block_count, 1)
+        };
+        let first_block = block;
+        let large = entry_id > u16::MAX as u32;
+        let (block_header_size, block_chunk_size) = if large {
+            (
+                BLOCK_HEADER_EXTENDED_SIZE,
+                BLOCK_CHUNK_EXTENDED_SIZE as usize,
+            )
+        } else {
+            (BLOCK_HEADER_SIZE, BLOCK_CHUNK_SIZE as usize)
+        };
+        for (sequence, chunk) in data.chunks(block_chunk_size).enumerate() {
+            if block > MAX_BLOCK_COUNT {
+                return Err(FileSystemError::DataFileFull);
+            }
+            let mut next_block = 0;
+            if overwrite {
+                let mut header_data = vec![0; block_header_size];
+                main_data_file.seek(SeekFrom::Start(block * TOTAL_BLOCK_SIZE))?;
+                if main_data_file.read_exact(&mut header_data).is_err() {
+                    return Ok(false);
                 }
-                if sector_header.next_entry_id != entry_id {
-                    return Err(FileSystemError::SectorReadingDataMismatch {
-                        data_type: "file entry id".to_owned(),
-                        expected: entry_id as usize,
-                        actual: sector_header.next_entry_id as usize,
-                    });
+                let sector_header = CacheSectorHeader::try_from(&header_data[..])?;
+                if sector_header.next_entry_id != entry_id
+                    || sector_header.next_sequence != sequence as u32
+                    || sector_header.next_index_id != index_id + 1
+                {
+                    return Ok(false);
+                }
+                next_block = sector_header.next_block;
+                if next_block > block_count {
+                    return Ok(false);
                 }
-                buffer.write(&block_data[block_header_size..total_consumed])?;
-                remaining_bytes -= chunks_consumed;
-                block = sector_header.next_block;
-                current_sequence += 1;
             }
+            if next_block == 0 {
+                overwrite = false;
+                next_block = std::cmp::max(block_count, 1);
+                if next_block == block {
+                    next_block += 1;
+                }
+            }
+            // the last sector of a chain points to nothing
+            if (sequence + 1) * block_chunk_size >= data.len() {
+                next_block = 0;
+            }
+            let sector_header = CacheSectorHeader {
+                next_entry_id: entry_id,
+                next_sequence: sequence as u32,
+                next_block,
+                next_index_id: index_id + 1,
+            };
+            let mut block_data: [u8; TOTAL_BLOCK_SIZE as usize] = [0; TOTAL_BLOCK_SIZE as usize];
+            block_data[..block_header_size].copy_from_slice(&sector_header.to_bytes(large));
+            block_data[block_header_size..block_header_size + chunk.len()].copy_from_slice(chunk);
+            main_data_file.seek(SeekFrom::Start(block * TOTAL_BLOCK_SIZE))?;
+            main_data_file.write_all(&block_data)?;
+            block_count = std::cmp::max(block_count, block + 1);
+            block = next_block;
+        }
+        index.write_entry(&IndexEntry::new(entry_id, data.len() as u32, first_block))?;
+        Ok(true)
+    }
+}
+
+fn open_file<P: AsRef<Path>>(path: P, writable: bool) -> Result<File, std::io::Error> {
+    OpenOptions::new().read(true).write(writable).open(path)
+}
+
+// moves every file from `source` into `target`, moving the files it replaces into `backup` first.
+// When a move fails, the replaced files are moved back before the error is returned
+fn replace_files(
+    source: &Path,
+    backup: &Path,
+    target: &Path,
+    file_names: &[String],
+) -> Result<(), std::io::Error> {
+    let mut replaced: Vec<&String> = Vec::with_capacity(file_names.len());
+    let restore = |replaced: &[&String]| -> Result<(), std::io::Error> {
+        for file_name in replaced.iter().rev() {
+            std::fs::rename(backup.join(file_name), target.join(file_name))?;
+        }
+        Ok(())
+    };
+    for file_name in file_names {
+        if let Err(error) = std::fs::rename(target.join(file_name), backup.join(file_name)) {
+            restore(&replaced)?;
+            return Err(error);
+        }
+        replaced.push(file_name);
+        if let Err(error) = std::fs::rename(source.join(file_name), target.join(file_name)) {
+            restore(&replaced)?;
+            return Err(error);
         }
-        Ok(buffer)
+    }
+    Ok(())
+}
+
+// the number of sectors in the data file, counting a trailing partial sector as a whole one
+fn block_count(main_data_file: &File) -> Result<u64, FileSystemError> {
+    let len = main_data_file.metadata()?.len();
+    Ok(len.div_ceil(TOTAL_BLOCK_SIZE))
+}
+
+/// The outcome of defragmenting a cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DefragmentReport {
+    bytes_reclaimed: u64,
+    entries_moved: u64,
+}
+
+impl DefragmentReport {
+    /// How much smaller the data file became.
+    pub fn bytes_reclaimed(&self) -> u64 {
+        self.bytes_reclaimed
+    }
+
+    /// How many entries now start at a different sector.
+    pub fn entries_moved(&self) -> u64 {
+        self.entries_moved
     }
 }
 
@@ -151,6 +643,21 @@ struct CacheSectorHeader {
     next_index_id: u8,
 }
 
+impl CacheSectorHeader {
+    fn to_bytes(&self, large: bool) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(BLOCK_HEADER_EXTENDED_SIZE);
+        if large {
+            bytes.extend_from_slice(&self.next_entry_id.to_be_bytes());
+        } else {
+            bytes.extend_from_slice(&(self.next_entry_id as u16).to_be_bytes());
+        }
+        bytes.extend_from_slice(&(self.next_sequence as u16).to_be_bytes());
+        bytes.extend_from_slice(&self.next_block.to_be_bytes()[5..]);
+        bytes.push(self.next_index_id);
+        bytes
+    }
+}
+
 impl TryFrom<&[u8]> for CacheSectorHeader {
     type Error = FileSystemError;
 
@@ -165,7 +672,7 @@ impl TryFrom<&[u8]> for CacheSectorHeader {
                 ((block_data[6] as u64) << 16)
                     | ((block_data[7] as u64) << 8)
                     | (block_data[8] as u64),
-                block_data[9] as u8,
+                block_data[9],
             ),
             BLOCK_HEADER_SIZE => (
                 ((block_data[0] as u32) << 8) | (block_data[1] as u32),
@@ -173,7 +680,7 @@ impl TryFrom<&[u8]> for CacheSectorHeader {
                 ((block_data[4] as u64) << 16)
                     | ((block_data[5] as u64) << 8)
                     | (block_data[6] as u64),
-                block_data[7] as u8,
+                block_data[7],
             ),
             other => return Err(FileSystemError::InvalidBlockHeaderLength(other)),
         };
@@ -185,3 +692,205 @@ impl TryFrom<&[u8]> for CacheSectorHeader {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::archive::{Archive, ArchiveCompression, ArchiveType};
+    use crate::errors::FileSystemError;
+    use crate::filesystem::{
+        FileSystem, DEFAULT_DATA_FILE_NAME, DEFAULT_INDEX_FILE_PREFIX, TOTAL_BLOCK_SIZE,
+    };
+    use crate::index::IndexType;
+    use crate::testing::TempDir;
+    use crate::versionlist::CrcList;
+
+    fn empty_cache(name: &str) -> TempDir {
+        let path = TempDir::new(name);
+        FileSystem::create(&path, &[IndexType::ARCHIVE, IndexType::MODEL]).unwrap();
+        path
+    }
+
+    fn data(len: usize, seed: u8) -> Vec<u8> {
+        (0..len)
+            .map(|i| (i as u8).wrapping_mul(31).wrapping_add(seed))
+            .collect()
+    }
+
+    #[test]
+    fn test_write_then_read() {
+        let path = empty_cache("write-read");
+        let mut fs = FileSystem::open_writable(&path).unwrap();
+        fs.write(IndexType::MODEL, 3, &data(1500, 1)).unwrap();
+        fs.write(IndexType::MODEL, 0, &data(10, 2)).unwrap();
+        assert_eq!(fs.read(IndexType::MODEL, 3).unwrap(), data(1500, 1));
+        assert_eq!(fs.read(IndexType::MODEL, 0).unwrap(), data(10, 2));
+        assert_eq!(fs.file_count(IndexType::MODEL).unwrap(), 4);
+    }
+
+    #[test]
+    fn test_overwrite_reuses_sector_chain() {
+        let path = empty_cache("overwrite");
+        let mut fs = FileSystem::open_writable(&path).unwrap();
+        fs.write(IndexType::MODEL, 1, &data(1000, 1)).unwrap();
+        fs.write(IndexType::MODEL, 2, &data(100, 2)).unwrap();
+        let offset = fs
+            .index(IndexType::MODEL)
+            .unwrap()
+            .entry(1)
+            .unwrap()
+            .offset();
+        fs.write(IndexType::MODEL, 1, &data(3000, 3)).unwrap();
+        assert_eq!(
+            fs.index(IndexType::MODEL)
+                .unwrap()
+                .entry(1)
+                .unwrap()
+                .offset(),
+            offset
+        );
+        assert_eq!(fs.read(IndexType::MODEL, 1).unwrap(), data(3000, 3));
+        assert_eq!(fs.read(IndexType::MODEL, 2).unwrap(), data(100, 2));
+        fs.write(IndexType::MODEL, 1, &data(20, 4)).unwrap();
+        assert_eq!(fs.read(IndexType::MODEL, 1).unwrap(), data(20, 4));
+    }
+
+    #[test]
+    fn test_write_extended_entry_id() {
+        let path = empty_cache("extended");
+        let mut fs = FileSystem::open_writable(&path).unwrap();
+        fs.write(IndexType::MODEL, 70_000, &data(1200, 5)).unwrap();
+        assert_eq!(fs.read(IndexType::MODEL, 70_000).unwrap(), data(1200, 5));
+    }
+
+    #[test]
+    fn test_remove_and_truncate() {
+        let path = empty_cache("remove");
+        let mut fs = FileSystem::open_writable(&path).unwrap();
+        fs.write(IndexType::MODEL, 0, &data(600, 1)).unwrap();
+        fs.write(IndexType::MODEL, 4, &data(700, 2)).unwrap();
+        let offset = fs
+            .index(IndexType::MODEL)
+            .unwrap()
+            .entry(4)
+            .unwrap()
+            .offset();
+        fs.remove(IndexType::MODEL, 4, true).unwrap();
+        assert!(fs.read(IndexType::MODEL, 4).unwrap().is_empty());
+        let data_file = std::fs::read(path.join(DEFAULT_DATA_FILE_NAME)).unwrap();
+        let start = (offset * TOTAL_BLOCK_SIZE) as usize;
+        assert!(data_file[start..].iter().all(|b| *b == 0));
+        assert_eq!(fs.truncate(IndexType::MODEL).unwrap(), 4);
+        assert_eq!(fs.file_count(IndexType::MODEL).unwrap(), 1);
+        assert_eq!(fs.read(IndexType::MODEL, 0).unwrap(), data(600, 1));
+    }
+
+    #[test]
+    fn test_create() {
+        let path = empty_cache("create");
+        let fs = FileSystem::new(&path).unwrap();
+        assert_eq!(fs.index_count(), 2);
+        assert_eq!(fs.file_count(IndexType::MODEL).unwrap(), 0);
+        assert!(path
+            .join(format!("{}{}", DEFAULT_INDEX_FILE_PREFIX, 0))
+            .exists());
+        assert!(matches!(
+            fs.index(IndexType::MAP),
+            Err(FileSystemError::IndexNotFound { .. })
+        ));
+        let mut read_only = FileSystem::new(&path).unwrap();
+        assert!(matches!(
+            read_only.write(IndexType::MODEL, 0, &[1]),
+            Err(FileSystemError::ReadOnly)
+        ));
+        assert!(FileSystem::create(&path, &[IndexType::MAP]).is_err());
+        // the index files created before the existing one are removed again
+        std::fs::remove_file(path.join(DEFAULT_DATA_FILE_NAME)).unwrap();
+        assert!(FileSystem::create(&path, &[IndexType::MAP, IndexType::MODEL]).is_err());
+        assert!(!path.join(DEFAULT_DATA_FILE_NAME).exists());
+        assert!(!path
+            .join(format!("{}{}", DEFAULT_INDEX_FILE_PREFIX, 4))
+            .exists());
+        assert!(path
+            .join(format!("{}{}", DEFAULT_INDEX_FILE_PREFIX, 1))
+            .exists());
+    }
+
+    #[test]
+    fn test_write_archive_and_versioned_entries() {
+        let path = empty_cache("versioned");
+        let mut fs = FileSystem::open_writable(&path).unwrap();
+        let mut archive = Archive::new(ArchiveCompression::Whole);
+        archive.insert("model_version", vec![0, 7, 0, 3]);
+        let crc = fs.write_archive(ArchiveType::VERSIONS, &archive).unwrap();
+        assert_eq!(fs.archive_crcs().unwrap(), vec![0, 0, 0, 0, 0, crc]);
+
+        fs.write_versioned(IndexType::MODEL, 1, &data(50, 1))
+            .unwrap();
+        fs.write_versioned(IndexType::MODEL, 3, &data(60, 2))
+            .unwrap();
+        assert_eq!(
+            fs.read(IndexType::MODEL, 3).unwrap(),
+            [data(60, 2), vec![0, 1]].concat()
+        );
+        assert_eq!(fs.read(IndexType::MODEL, 1).unwrap()[50..], [0, 4]);
+        let versions = fs.versions(IndexType::MODEL).unwrap();
+        let crcs = fs.crcs(IndexType::MODEL).unwrap();
+        assert_eq!(versions.len(), 4);
+        assert_eq!(versions.get(0), Some(&7));
+        assert_eq!(versions.get(1), Some(&4));
+        assert_eq!(versions.get(3), Some(&1));
+        assert_eq!(crcs.get(3), Some(&CrcList::checksum(&data(60, 2))));
+        assert!(fs.write_versioned(IndexType::ARCHIVE, 1, &[1]).is_err());
+        assert!(fs.versions(IndexType::MAP).unwrap().is_empty());
+        assert!(fs.crcs(IndexType::MAP).unwrap().is_empty());
+        assert!(matches!(
+            fs.versions(IndexType::ARCHIVE),
+            Err(FileSystemError::VersionListNotFound(0))
+        ));
+    }
+
+    #[test]
+    fn test_defragment_in_place() {
+        let path = empty_cache("defragment");
+        let mut fs = FileSystem::open_writable(&path).unwrap();
+        fs.write(IndexType::MODEL, 0, &data(2000, 1)).unwrap();
+        fs.write(IndexType::MODEL, 1, &data(700, 2)).unwrap();
+        fs.write(IndexType::ARCHIVE, 2, &data(300, 3)).unwrap();
+        // leaves the four sectors of entry 0 orphaned
+        fs.remove(IndexType::MODEL, 0, false).unwrap();
+        fs.write(IndexType::MODEL, 3, &data(100, 4)).unwrap();
+
+        let report = fs.defragment_in_place().unwrap();
+        assert_eq!(report.bytes_reclaimed(), 4 * TOTAL_BLOCK_SIZE);
+        assert_eq!(report.entries_moved(), 3);
+        assert!(fs.read(IndexType::MODEL, 0).unwrap().is_empty());
+        assert_eq!(fs.read(IndexType::MODEL, 1).unwrap(), data(700, 2));
+        assert_eq!(fs.read(IndexType::MODEL, 3).unwrap(), data(100, 4));
+        assert_eq!(fs.read(IndexType::ARCHIVE, 2).unwrap(), data(300, 3));
+        assert_eq!(fs.file_count(IndexType::ARCHIVE).unwrap(), 3);
+        assert!(!path.join(".defragment").exists());
+    }
+
+    #[test]
+    fn test_defragment_rolls_back() {
+        let path = TempDir::new("defragment-rollback");
+        let (source, backup) = (path.join("new"), path.join("backup"));
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::create_dir_all(&backup).unwrap();
+        std::fs::write(path.join("a"), "old a").unwrap();
+        std::fs::write(path.join("b"), "old b").unwrap();
+        std::fs::write(source.join("a"), "new a").unwrap();
+        // the new b is missing, so it cannot be moved into place
+        let file_names = ["a".to_string(), "b".to_string()];
+        assert!(super::replace_files(&source, &backup, &path, &file_names).is_err());
+        assert_eq!(std::fs::read_to_string(path.join("a")).unwrap(), "old a");
+        assert_eq!(std::fs::read_to_string(path.join("b")).unwrap(), "old b");
+
+        std::fs::write(source.join("a"), "new a").unwrap();
+        std::fs::write(source.join("b"), "new b").unwrap();
+        super::replace_files(&source, &backup, &path, &file_names).unwrap();
+        assert_eq!(std::fs::read_to_string(path.join("a")).unwrap(), "new a");
+        assert_eq!(std::fs::read_to_string(path.join("b")).unwrap(), "new b");
+        assert_eq!(std::fs::read_to_string(backup.join("b")).unwrap(), "old b");
+    }
+}
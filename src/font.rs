@@ -0,0 +1,329 @@
+use crate::archive::Archive;
+use crate::errors::FileSystemError;
+use crate::sprite::{self, IndexedSprite, PixelOrder, Sprite, TRANSPARENT};
+
+/// The number of glyphs in a font, one for every ISO-8859-1 character.
+pub const GLYPH_COUNT: usize = 256;
+/// The font that takes the width of a space from `I` instead of `i`.
+pub const WIDE_SPACE_FONT_NAME: &str = "q8_full";
+
+/// A font of the title archive, like `p12_full`, with the glyph metrics the client derives when
+/// it loads the font.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BitmapFont {
+    /// The glyphs, indexed by ISO-8859-1 character.
+    pub glyphs: Vec<Glyph>,
+    /// The height of the tallest of the first 128 glyphs, the distance from the top of a line of
+    /// text to its baseline.
+    pub height: u16,
+}
+
+/// A character of a `BitmapFont`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Glyph {
+    /// How far right of the pen the glyph is drawn. The client replaces the stored offset with 1,
+    /// or 0 when the left column of the glyph is mostly empty.
+    pub offset_x: u8,
+    /// How far below the top of the line the glyph is drawn.
+    pub offset_y: u8,
+    pub width: u16,
+    pub height: u16,
+    /// How far the pen moves after the glyph.
+    pub advance: u16,
+    /// The pixels row by row, where any non-zero value is drawn.
+    pub pixels: Vec<u8>,
+}
+
+impl BitmapFont {
+    /// Derives the glyph metrics from the frames of a font sprite, the same way the client does.
+    ///
+    /// `wide_space` takes the width of a space from `I`, as the client does for `q8_full`, and
+    /// from `i` otherwise.
+    pub fn from_frames(frames: &[IndexedSprite], wide_space: bool) -> Self {
+        let mut height = 0;
+        let mut glyphs: Vec<Glyph> = frames
+            .iter()
+            .enumerate()
+            .map(|(character, frame)| {
+                if character < 128 {
+                    height = height.max(frame.height);
+                }
+                Glyph::from_frame(frame)
+            })
+            .collect();
+        let space_source = if wide_space { b'I' } else { b'i' };
+        if let Some(advance) = glyphs.get(space_source as usize).map(|glyph| glyph.advance) {
+            if let Some(space) = glyphs.get_mut(b' ' as usize) {
+                space.advance = advance;
+            }
+        }
+        BitmapFont { glyphs, height }
+    }
+
+    /// The glyph of a character. Characters outside of ISO-8859-1 have none.
+    pub fn glyph(&self, character: char) -> Option<&Glyph> {
+        self.glyphs.get(character as usize)
+    }
+
+    /// The width of a line of text in pixels, with colour codes like `@red@` left out.
+    pub fn text_width(&self, text: &str) -> u32 {
+        parse_text(text)
+            .iter()
+            .filter_map(|part| match part {
+                TextPart::Character(character) => self.glyph(*character),
+                TextPart::Color(_) => None,
+            })
+            .map(|glyph| u32::from(glyph.advance))
+            .sum()
+    }
+
+    /// Draws a line of text like the client's text widgets, starting in `color` and switching at
+    /// every known colour code. A shadow is drawn one pixel right of and below the text in black.
+    ///
+    /// The sprite is as wide as `text_width`, and tall enough for the glyphs that reach below
+    /// the baseline, at `height`.
+    pub fn render(&self, text: &str, color: u32, shadowed: bool) -> Sprite {
+        let parts = parse_text(text);
+        let shadow = u32::from(shadowed);
+        let width = self.text_width(text) + shadow;
+        let height = parts
+            .iter()
+            .filter_map(|part| match part {
+                TextPart::Character(character) => self.glyph(*character),
+                TextPart::Color(_) => None,
+            })
+            .map(|glyph| u32::from(glyph.offset_y) + u32::from(glyph.height))
+            .max()
+            .unwrap_or(0)
+            .max(u32::from(self.height))
+            + shadow;
+        let mut pixels = vec![TRANSPARENT; width as usize * height as usize];
+        let mut draw = |glyph: &Glyph, x: u32, y: u32, color: u32| {
+            for (position, pixel) in glyph.pixels.iter().enumerate() {
+                if *pixel == 0 {
+                    continue;
+                }
+                let pixel_x = x + position as u32 % u32::from(glyph.width);
+                let pixel_y = y + position as u32 / u32::from(glyph.width);
+                if pixel_x >= width {
+                    continue;
+                }
+                if let Some(target) = pixels.get_mut((pixel_x + pixel_y * width) as usize) {
+                    // black is kept visible as 1
+                    *target = color.max(1);
+                }
+            }
+        };
+        let mut pen = 0;
+        let mut color = color;
+        for part in parts {
+            let character = match part {
+                TextPart::Character(character) => character,
+                TextPart::Color(code) => {
+                    color = code.unwrap_or(color);
+                    continue;
+                }
+            };
+            let glyph = match self.glyph(character) {
+                Some(glyph) => glyph,
+                None => continue,
+            };
+            if character != ' ' {
+                let x = pen + u32::from(glyph.offset_x);
+                let y = u32::from(glyph.offset_y);
+                if shadowed {
+                    draw(glyph, x + 1, y + 1, 0);
+                }
+                draw(glyph, x, y, color);
+            }
+            pen += u32::from(glyph.advance);
+        }
+        Sprite {
+            max_width: width as u16,
+            max_height: height as u16,
+            offset_x: 0,
+            offset_y: 0,
+            width: width as u16,
+            height: height as u16,
+            pixel_order: PixelOrder::Horizontal,
+            pixels,
+        }
+    }
+}
+
+impl Glyph {
+    fn from_frame(frame: &IndexedSprite) -> Self {
+        let width = frame.width as usize;
+        let height = frame.height as usize;
+        // the client sums the lower part of the outer columns to find out if they are empty
+        let column_sum = |x: usize| -> i32 {
+            (height / 7..height)
+                .map(|y| {
+                    frame
+                        .pixels
+                        .get(x + y * width)
+                        .map_or(0, |pixel| *pixel as i8 as i32)
+                })
+                .sum()
+        };
+        let mut offset_x = 1;
+        let mut advance = frame.width + 2;
+        if column_sum(0) <= (height / 7) as i32 {
+            offset_x = 0;
+            advance -= 1;
+        }
+        if column_sum(width.saturating_sub(1)) <= (height / 7) as i32 {
+            advance -= 1;
+        }
+        Glyph {
+            offset_x,
+            offset_y: frame.offset_y,
+            width: frame.width,
+            height: frame.height,
+            advance,
+            pixels: frame.pixels.clone(),
+        }
+    }
+}
+
+/// The colour a colour code like `red` in `@red@` switches text to, if the client knows it.
+pub fn color_code(code: &str) -> Option<u32> {
+    let color = match code {
+        "red" => 0xFF0000,
+        "gre" => 0x00FF00,
+        "blu" => 0x0000FF,
+        "yel" => 0xFFFF00,
+        "cya" => 0x00FFFF,
+        "mag" => 0xFF00FF,
+        "whi" => 0xFFFFFF,
+        "bla" => 0x000000,
+        "lre" => 0xFF9040,
+        "dre" => 0x800000,
+        "dbl" => 0x000080,
+        "or1" => 0xFFB000,
+        "or2" => 0xFF7000,
+        "or3" => 0xFF3000,
+        "gr1" => 0xC0FF00,
+        "gr2" => 0x80FF00,
+        "gr3" => 0x40FF00,
+        _ => return None,
+    };
+    Some(color)
+}
+
+enum TextPart {
+    Character(char),
+    // a colour code, which is skipped even when the client does not know it
+    Color(Option<u32>),
+}
+
+fn parse_text(text: &str) -> Vec<TextPart> {
+    let characters: Vec<char> = text.chars().collect();
+    let mut parts = Vec::with_capacity(characters.len());
+    let mut position = 0;
+    while position < characters.len() {
+        if characters[position] == '@' && characters.get(position + 4) == Some(&'@') {
+            let code: String = characters[position + 1..position + 4].iter().collect();
+            parts.push(TextPart::Color(color_code(&code)));
+            position += 5;
+        } else {
+            parts.push(TextPart::Character(characters[position]));
+            position += 1;
+        }
+    }
+    parts
+}
+
+impl Archive {
+    /// Decodes the font stored as `<name>.dat`, e.g. `title.font("p12_full")`.
+    pub fn font(&self, name: &str) -> Result<BitmapFont, FileSystemError> {
+        let (data, index) = self.sprite_entries(name)?;
+        let frames = sprite::decode_frames(data, index, Some(GLYPH_COUNT))?;
+        Ok(BitmapFont::from_frames(
+            &frames,
+            name == WIDE_SPACE_FONT_NAME,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::archive::{Archive, ArchiveCompression};
+    use crate::font::{BitmapFont, GLYPH_COUNT};
+    use crate::sprite::{IndexedSprite, PixelOrder, INDEX_ENTRY_NAME};
+
+    fn glyph(width: u16, height: u16, pixels: Vec<u8>) -> IndexedSprite {
+        IndexedSprite {
+            max_width: 0,
+            max_height: 0,
+            offset_x: 0,
+            offset_y: 2,
+            width,
+            height,
+            pixel_order: PixelOrder::Horizontal,
+            palette: vec![0, 0xFFFFFF],
+            pixels,
+        }
+    }
+
+    fn font() -> Archive {
+        let mut frames = vec![glyph(0, 0, Vec::new()); GLYPH_COUNT];
+        #[rustfmt::skip]
+        let i = glyph(3, 3, vec![
+            0, 1, 0,
+            0, 1, 0,
+            0, 1, 0,
+        ]);
+        #[rustfmt::skip]
+        let upper_i = glyph(3, 3, vec![
+            1, 1, 1,
+            1, 1, 1,
+            1, 1, 1,
+        ]);
+        frames[b'i' as usize] = i;
+        frames[b'I' as usize] = upper_i;
+        let mut archive = Archive::new(ArchiveCompression::Whole);
+        archive.insert(INDEX_ENTRY_NAME, Vec::new());
+        archive.write_indexed_sprites("p12_full", &frames).unwrap();
+        archive.write_indexed_sprites("q8_full", &frames).unwrap();
+        archive
+    }
+
+    #[test]
+    fn test_font_metrics() {
+        let title = font();
+        let font = title.font("p12_full").unwrap();
+        assert_eq!(font.glyphs.len(), GLYPH_COUNT);
+        assert_eq!(font.height, 3);
+        // the empty outer columns of i are trimmed, the full ones of I are not
+        let i = font.glyph('i').unwrap();
+        assert_eq!((i.offset_x, i.advance), (0, 3));
+        let upper_i = font.glyph('I').unwrap();
+        assert_eq!((upper_i.offset_x, upper_i.advance), (1, 5));
+        assert_eq!(font.glyph(' ').unwrap().advance, 3);
+        assert_eq!(
+            title.font("q8_full").unwrap().glyph(' ').unwrap().advance,
+            5
+        );
+        assert_eq!(font.text_width("iI i"), 14);
+        assert_eq!(font.text_width("@red@iI@xyz@ i"), 14);
+        assert!(title.font("b12_full").is_err());
+    }
+
+    #[test]
+    fn test_font_rendering() {
+        let font: BitmapFont = font().font("p12_full").unwrap();
+        let text = font.render("@red@i@bla@I", 0xFFFFFF, false);
+        assert_eq!((text.width, text.height), (8, 5));
+        // i is drawn in red from the top of its glyph, below the top of the line
+        assert_eq!(text.pixels[1], 0);
+        assert_eq!(text.pixels[2 * 8 + 1], 0xFF0000);
+        // I is drawn in black, kept visible as 1, one pixel right of the pen
+        assert_eq!(text.pixels[2 * 8 + 3], 0);
+        assert_eq!(text.pixels[2 * 8 + 4], 1);
+
+        let shadowed = font.render("i", 0xFFFFFF, true);
+        assert_eq!((shadowed.width, shadowed.height), (4, 6));
+        assert_eq!(shadowed.pixels[5 * 4 + 2], 1);
+    }
+}
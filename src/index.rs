@@ -1,6 +1,6 @@
 use crate::errors::FileSystemError;
 use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
+use std::io::{Read, Seek, SeekFrom, Write};
 
 #[derive(Debug)]
 pub struct IndexType(u8);
@@ -60,6 +60,39 @@ impl Index {
         })
     }
 
+    pub(crate) fn write_entry(&mut self, entry: &IndexEntry) -> Result<(), FileSystemError> {
+        let ptr = (entry.id as u64) * (Index::SIZE as u64);
+        let buffer: [u8; Index::SIZE as usize] = [
+            (entry.size >> 16) as u8,
+            (entry.size >> 8) as u8,
+            entry.size as u8,
+            (entry.offset >> 16) as u8,
+            (entry.offset >> 8) as u8,
+            entry.offset as u8,
+        ];
+        let mut index_file = &self.file;
+        index_file.seek(SeekFrom::Start(ptr))?;
+        index_file.write_all(&buffer)?;
+        self.file_size = std::cmp::max(self.file_size, ptr + Index::SIZE as u64);
+        Ok(())
+    }
+
+    // drops the trailing entries that have neither a size nor an offset
+    pub(crate) fn truncate(&mut self) -> Result<u64, FileSystemError> {
+        let mut index_file = &self.file;
+        let mut buffer = Vec::with_capacity(self.file_size as usize);
+        index_file.seek(SeekFrom::Start(0))?;
+        index_file.read_to_end(&mut buffer)?;
+        let used_entries = buffer
+            .chunks(Index::SIZE as usize)
+            .rposition(|entry| entry.len() == Index::SIZE as usize && entry.iter().any(|b| *b != 0))
+            .map_or(0, |last| last as u64 + 1);
+        let removed = self.file_count() - used_entries;
+        self.file_size = used_entries * Index::SIZE as u64;
+        self.file.set_len(self.file_size)?;
+        Ok(removed)
+    }
+
     pub fn index_type(&self) -> &IndexType {
         &self.index_type
     }
@@ -76,6 +109,10 @@ pub struct IndexEntry {
 }
 
 impl IndexEntry {
+    pub(crate) fn new(id: u32, size: u32, offset: u64) -> Self {
+        IndexEntry { id, size, offset }
+    }
+
     pub fn id(&self) -> u32 {
         self.id
     }
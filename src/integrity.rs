@@ -0,0 +1,543 @@
+use crate::archive::{Archive, ArchiveType};
+use crate::compression;
+use crate::errors::{FileSystemError, SectorDataType};
+use crate::filesystem::FileSystem;
+use crate::index::{IndexEntry, IndexType};
+use crate::versionlist::{self, CrcList, VERSION_TRAILER_SIZE};
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::path::Path;
+
+/// The result of checking every entry of every index in a cache.
+#[derive(Debug)]
+pub struct VerifyReport {
+    entries_checked: u64,
+    problems: Vec<EntryProblem>,
+}
+
+impl VerifyReport {
+    /// The number of non-empty entries that were checked.
+    pub fn entries_checked(&self) -> u64 {
+        self.entries_checked
+    }
+
+    pub fn problems(&self) -> &[EntryProblem] {
+        &self.problems
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// The result of rebuilding a cache from a damaged one.
+#[derive(Debug)]
+pub struct RepairReport {
+    entries_copied: u64,
+    recovered: Vec<(u8, u32)>,
+    unverified: Vec<(u8, u32)>,
+    dropped: Vec<(u8, u32)>,
+}
+
+impl RepairReport {
+    /// The number of non-empty entries that were copied over intact.
+    pub fn entries_copied(&self) -> u64 {
+        self.entries_copied
+    }
+
+    /// The index and entry ids of the broken entries that were taken from the donor cache after
+    /// matching the CRC of the damaged cache.
+    pub fn recovered(&self) -> &[(u8, u32)] {
+        &self.recovered
+    }
+
+    /// The index and entry ids of the broken entries that were taken from the donor cache without
+    /// a CRC of the damaged cache to check them against. These are the archives, and every entry
+    /// when the versions archive of the damaged cache cannot be read.
+    pub fn unverified(&self) -> &[(u8, u32)] {
+        &self.unverified
+    }
+
+    /// The index and entry ids of the broken entries that were left empty.
+    pub fn dropped(&self) -> &[(u8, u32)] {
+        &self.dropped
+    }
+}
+
+/// A problem found with a single index entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryProblem {
+    index_id: u8,
+    entry_id: u32,
+    kind: ProblemKind,
+}
+
+impl EntryProblem {
+    pub fn index_id(&self) -> u8 {
+        self.index_id
+    }
+
+    pub fn entry_id(&self) -> u32 {
+        self.entry_id
+    }
+
+    pub fn kind(&self) -> &ProblemKind {
+        &self.kind
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProblemKind {
+    /// A sector of the chain was written for another index.
+    WrongIndexId {
+        sector: u64,
+        expected: u8,
+        actual: u8,
+    },
+    /// A sector of the chain is out of order.
+    BrokenSequence {
+        sector: u64,
+        expected: u32,
+        actual: u32,
+    },
+    /// A sector of the chain was written for another entry.
+    WrongEntryId {
+        sector: u64,
+        expected: u32,
+        actual: u32,
+    },
+    /// The chain points to a sector that lies past the end of the data file.
+    PastEndOfFile { sector: u64 },
+    /// The chain runs into a sector that belongs to another entry.
+    SharedSector {
+        sector: u64,
+        index_id: u8,
+        entry_id: u32,
+    },
+    /// The chain loops back to one of its own sectors.
+    Cycle { sector: u64 },
+    /// The data of an entry in the archive index could not be decoded as an `Archive`.
+    InvalidArchive(String),
+    /// The data of an entry in any other index is not valid GZIP.
+    InvalidGzip(String),
+}
+
+impl ProblemKind {
+    // the sector errors of a chain become problems, everything else stays an error
+    fn from_error(error: FileSystemError) -> Result<Self, FileSystemError> {
+        match error {
+            FileSystemError::SectorReadingDataMismatch {
+                sector,
+                data_type,
+                expected,
+                actual,
+            } => Ok(match data_type {
+                SectorDataType::IndexId => ProblemKind::WrongIndexId {
+                    sector,
+                    expected: expected as u8,
+                    actual: actual as u8,
+                },
+                SectorDataType::Sequence => ProblemKind::BrokenSequence {
+                    sector,
+                    expected: expected as u32,
+                    actual: actual as u32,
+                },
+                SectorDataType::EntryId => ProblemKind::WrongEntryId {
+                    sector,
+                    expected: expected as u32,
+                    actual: actual as u32,
+                },
+            }),
+            FileSystemError::SectorOutOfBounds(sector) => Ok(ProblemKind::PastEndOfFile { sector }),
+            FileSystemError::SectorCycle(sector) => Ok(ProblemKind::Cycle { sector }),
+            other => Err(other),
+        }
+    }
+}
+
+impl FileSystem {
+    /// Checks the sector chain and the payload of every entry of every index.
+    ///
+    /// Unlike `FileSystem::read`, a broken entry does not stop the check. Every entry that has a
+    /// problem is listed in the report instead.
+    pub fn verify(&self) -> Result<VerifyReport, FileSystemError> {
+        let mut entries_checked = 0;
+        let mut checked_entries = Vec::new();
+        let mut owners: HashMap<u64, (u8, u32)> = HashMap::new();
+        for index_id in self.index_ids() {
+            let index = self.index(IndexType::new(index_id))?;
+            for entry_id in 0..index.file_count() as u32 {
+                let index_entry = index.entry(entry_id)?;
+                if index_entry.size() == 0 {
+                    continue;
+                }
+                entries_checked += 1;
+                let mut sectors = Vec::new();
+                let problem = self
+                    .checked_read(index_id, &index_entry, &mut sectors)?
+                    .err();
+                for sector in sectors {
+                    owners.entry(sector).or_insert((index_id, entry_id));
+                }
+                if let Some(kind) = problem {
+                    checked_entries.push(EntryProblem {
+                        index_id,
+                        entry_id,
+                        kind,
+                    });
+                }
+            }
+        }
+        // a chain that runs into a sector of another entry is reported as sharing it
+        let problems = checked_entries
+            .into_iter()
+            .map(|mut problem| {
+                let sector = match problem.kind {
+                    ProblemKind::WrongIndexId { sector, .. }
+                    | ProblemKind::BrokenSequence { sector, .. }
+                    | ProblemKind::WrongEntryId { sector, .. } => sector,
+                    _ => return problem,
+                };
+                if let Some((index_id, entry_id)) = owners.get(&sector) {
+                    problem.kind = ProblemKind::SharedSector {
+                        sector,
+                        index_id: *index_id,
+                        entry_id: *entry_id,
+                    };
+                }
+                problem
+            })
+            .collect();
+        Ok(VerifyReport {
+            entries_checked,
+            problems,
+        })
+    }
+
+    /// Writes a repaired copy of the cache to `output`.
+    ///
+    /// Every entry whose sector chain and payload are intact is copied over. A broken entry is
+    /// taken from the `donor` cache instead if the donor's copy is intact too and matches the CRC
+    /// in the versions archive of this cache. Donor copies that no CRC of this cache covers are
+    /// taken as well, but reported as unverified. Broken entries that cannot be recovered are
+    /// left empty. The output directory must not contain a cache yet.
+    pub fn repair<P: AsRef<Path>>(
+        &self,
+        output: P,
+        donor: Option<&FileSystem>,
+    ) -> Result<RepairReport, FileSystemError> {
+        let index_ids = self.index_ids();
+        let index_types: Vec<IndexType> = index_ids.iter().map(|id| IndexType::new(*id)).collect();
+        let mut repaired = FileSystem::create(output, &index_types)?;
+        let mut report = RepairReport {
+            entries_copied: 0,
+            recovered: Vec::new(),
+            unverified: Vec::new(),
+            dropped: Vec::new(),
+        };
+        for index_id in index_ids {
+            // only the CRCs of this cache vouch for a donor copy, the donor's own prove nothing
+            let crcs = self.expected_crcs(index_id);
+            let index = self.index(IndexType::new(index_id))?;
+            for entry_id in 0..index.file_count() as u32 {
+                let index_entry = index.entry(entry_id)?;
+                let data = if index_entry.size() == 0 {
+                    Vec::new()
+                } else if let Ok(data) =
+                    self.checked_read(index_id, &index_entry, &mut Vec::new())?
+                {
+                    report.entries_copied += 1;
+                    data
+                } else {
+                    let expected_crc = crcs.as_ref().and_then(|crcs| crcs.get(entry_id));
+                    let donated = match donor {
+                        Some(donor) => donor.donated_entry(index_id, entry_id, expected_crc)?,
+                        None => None,
+                    };
+                    match donated {
+                        Some(data) => {
+                            if expected_crc.is_some() {
+                                report.recovered.push((index_id, entry_id));
+                            } else {
+                                report.unverified.push((index_id, entry_id));
+                            }
+                            data
+                        }
+                        None => {
+                            report.dropped.push((index_id, entry_id));
+                            Vec::new()
+                        }
+                    }
+                };
+                repaired.write(IndexType::new(index_id), entry_id, &data)?;
+            }
+        }
+        Ok(report)
+    }
+
+    // reads an entry, returning its data only if both its sector chain and its payload are intact
+    fn checked_read(
+        &self,
+        index_id: u8,
+        index_entry: &IndexEntry,
+        sectors: &mut Vec<u64>,
+    ) -> Result<Result<Vec<u8>, ProblemKind>, FileSystemError> {
+        match self.read_sectors(index_id, index_entry, sectors) {
+            Ok(data) => Ok(match check_payload(index_id, &data) {
+                Some(problem) => Err(problem),
+                None => Ok(data),
+            }),
+            Err(e) => Ok(Err(ProblemKind::from_error(e)?)),
+        }
+    }
+
+    // the CRC list of an on-demand index, if the versions archive can still be read
+    fn expected_crcs(&self, index_id: u8) -> Option<CrcList> {
+        let (_, crc_name) = versionlist::entry_names(index_id)?;
+        let archive = self.read_archive(ArchiveType::VERSIONS).ok()?;
+        CrcList::from_archive(&archive, crc_name).ok()
+    }
+
+    // an intact copy of the entry whose CRC matches the expected one, if there is one
+    fn donated_entry(
+        &self,
+        index_id: u8,
+        entry_id: u32,
+        expected_crc: Option<&u32>,
+    ) -> Result<Option<Vec<u8>>, FileSystemError> {
+        let index_entry = match self.index(IndexType::new(index_id)) {
+            Ok(index) => match index.entry(entry_id) {
+                Ok(index_entry) if index_entry.size() > 0 => index_entry,
+                _ => return Ok(None),
+            },
+            Err(_) => return Ok(None),
+        };
+        let data = match self.checked_read(index_id, &index_entry, &mut Vec::new())? {
+            Ok(data) => data,
+            Err(_) => return Ok(None),
+        };
+        if let Some(expected_crc) = expected_crc {
+            // the CRC leaves out the version trailer of the file
+            let payload_size = data.len().saturating_sub(VERSION_TRAILER_SIZE);
+            if CrcList::checksum(&data[..payload_size]) != *expected_crc {
+                return Ok(None);
+            }
+        }
+        Ok(Some(data))
+    }
+}
+
+// archives have to decode, the files of every other index have to be valid GZIP
+fn check_payload(index_id: u8, data: &[u8]) -> Option<ProblemKind> {
+    if index_id == IndexType::ARCHIVE.id() {
+        Archive::try_from(data.to_vec())
+            .err()
+            .map(|e| ProblemKind::InvalidArchive(e.to_string()))
+    } else {
+        compression::decompress_gzip(data.to_vec())
+            .err()
+            .map(|e| ProblemKind::InvalidGzip(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::archive::{Archive, ArchiveCompression, ArchiveType};
+    use crate::compression::compress_gzip;
+    use crate::filesystem::{FileSystem, DEFAULT_DATA_FILE_NAME, TOTAL_BLOCK_SIZE};
+    use crate::index::IndexType;
+    use crate::integrity::ProblemKind;
+    use crate::testing::TempDir;
+    use std::fs::OpenOptions;
+    use std::io::{Seek, SeekFrom, Write};
+    use std::path::Path;
+
+    fn cache(name: &str) -> (TempDir, FileSystem) {
+        let path = TempDir::new(name);
+        let mut fs = FileSystem::create(&path, &[IndexType::ARCHIVE, IndexType::MODEL]).unwrap();
+        let mut archive = Archive::new(ArchiveCompression::Entries);
+        archive.insert("data", vec![1, 2, 3]);
+        fs.write_archive(ArchiveType::TITLE, &archive).unwrap();
+        for model_id in 0..3 {
+            let model = compress_gzip(&noise(1200, model_id)).unwrap();
+            fs.write(IndexType::MODEL, model_id, &model).unwrap();
+        }
+        (path, fs)
+    }
+
+    // data that does not shrink when compressed
+    fn noise(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed.wrapping_mul(2_654_435_761).wrapping_add(1);
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    // overwrites part of a sector in the data file
+    fn corrupt(path: &Path, sector: u64, position: u64, bytes: &[u8]) {
+        let mut data_file = OpenOptions::new()
+            .write(true)
+            .open(path.join(DEFAULT_DATA_FILE_NAME))
+            .unwrap();
+        data_file
+            .seek(SeekFrom::Start(sector * TOTAL_BLOCK_SIZE + position))
+            .unwrap();
+        data_file.write_all(bytes).unwrap();
+    }
+
+    #[test]
+    fn test_verify_clean_cache() {
+        let (_path, fs) = cache("verify-clean");
+        let report = fs.verify().unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.entries_checked(), 4);
+    }
+
+    #[test]
+    fn test_verify_broken_chains() {
+        let (path, mut fs) = cache("verify-broken");
+        fs.write(IndexType::MODEL, 3, &[1, 2, 3]).unwrap();
+        fs.write(IndexType::MODEL, 4, &compress_gzip(&[4]).unwrap())
+            .unwrap();
+        let first = |fs: &FileSystem, id| {
+            fs.index(IndexType::MODEL)
+                .unwrap()
+                .entry(id)
+                .unwrap()
+                .offset()
+        };
+        let (model_0, model_1, model_2) = (first(&fs, 0), first(&fs, 1), first(&fs, 2));
+        // model 0 now claims to belong to entry 9
+        corrupt(&path, model_0, 0, &[0, 9]);
+        // the second sector of model 1 has the wrong sequence number
+        corrupt(&path, model_1 + 1, 2, &[0, 5]);
+        // the second sector of model 2 points back to its first sector
+        corrupt(
+            &path,
+            model_2 + 1,
+            4,
+            &[(model_2 >> 16) as u8, (model_2 >> 8) as u8, model_2 as u8],
+        );
+        // model 4 now starts in the sector of the title archive
+        let mut index_file = OpenOptions::new()
+            .write(true)
+            .open(path.join("main_file_cache.idx1"))
+            .unwrap();
+        index_file.seek(SeekFrom::Start(4 * 6 + 3)).unwrap();
+        let sector = fs
+            .index(IndexType::ARCHIVE)
+            .unwrap()
+            .entry(1)
+            .unwrap()
+            .offset();
+        index_file
+            .write_all(&[(sector >> 16) as u8, (sector >> 8) as u8, sector as u8])
+            .unwrap();
+        let report = fs.verify().unwrap();
+        let mut kinds: Vec<(u32, &ProblemKind)> = report
+            .problems()
+            .iter()
+            .map(|problem| (problem.entry_id(), problem.kind()))
+            .collect();
+        assert!(matches!(kinds.remove(3), (3, ProblemKind::InvalidGzip(_))));
+        assert_eq!(
+            kinds,
+            vec![
+                (
+                    0,
+                    &ProblemKind::WrongEntryId {
+                        sector: model_0,
+                        expected: 0,
+                        actual: 9
+                    }
+                ),
+                (
+                    1,
+                    &ProblemKind::BrokenSequence {
+                        sector: model_1 + 1,
+                        expected: 1,
+                        actual: 5
+                    }
+                ),
+                (2, &ProblemKind::Cycle { sector: model_2 }),
+                (
+                    4,
+                    &ProblemKind::SharedSector {
+                        sector: 1,
+                        index_id: 0,
+                        entry_id: 1
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_repair_with_donor() {
+        let path = TempDir::new("repair");
+        let (damaged_path, donor_path, output_path) = (
+            path.join("damaged"),
+            path.join("donor"),
+            path.join("output"),
+        );
+        let mut damaged =
+            FileSystem::create(&damaged_path, &[IndexType::ARCHIVE, IndexType::MODEL]).unwrap();
+        let versions = Archive::new(ArchiveCompression::Whole);
+        damaged
+            .write_archive(ArchiveType::VERSIONS, &versions)
+            .unwrap();
+        let mut title = Archive::new(ArchiveCompression::Whole);
+        title.insert("logo.dat", vec![1, 2, 3]);
+        damaged.write_archive(ArchiveType::TITLE, &title).unwrap();
+        let models: Vec<Vec<u8>> = (0..3)
+            .map(|model_id| compress_gzip(&noise(600, model_id)).unwrap())
+            .collect();
+        for (model_id, model) in models.iter().enumerate() {
+            damaged
+                .write_versioned(IndexType::MODEL, model_id as u32, model)
+                .unwrap();
+        }
+        std::fs::create_dir_all(&donor_path).unwrap();
+        for entry in std::fs::read_dir(&damaged_path).unwrap() {
+            let entry = entry.unwrap();
+            std::fs::copy(entry.path(), donor_path.join(entry.file_name())).unwrap();
+        }
+        let mut donor = FileSystem::open_writable(&donor_path).unwrap();
+        // the donor's copy of model 2 no longer matches the CRC of the damaged cache
+        donor
+            .write(IndexType::MODEL, 2, &compress_gzip(&noise(600, 9)).unwrap())
+            .unwrap();
+        for model_id in 1..3 {
+            let sector = damaged
+                .index(IndexType::MODEL)
+                .unwrap()
+                .entry(model_id)
+                .unwrap()
+                .offset();
+            corrupt(&damaged_path, sector, 0, &[0, 7]);
+        }
+        // no CRC of the damaged cache covers the archives
+        let sector = damaged
+            .index(IndexType::ARCHIVE)
+            .unwrap()
+            .entry(ArchiveType::TITLE.id())
+            .unwrap()
+            .offset();
+        corrupt(&damaged_path, sector, 0, &[0, 7]);
+
+        let report = damaged.repair(&output_path, Some(&donor)).unwrap();
+        assert_eq!(report.entries_copied(), 2);
+        assert_eq!(report.recovered(), &[(1, 1)]);
+        assert_eq!(report.unverified(), &[(0, 1)]);
+        assert_eq!(report.dropped(), &[(1, 2)]);
+        let repaired = FileSystem::new(&output_path).unwrap();
+        assert!(repaired.verify().unwrap().is_ok());
+        let model_1 = repaired.read(IndexType::MODEL, 1).unwrap();
+        assert_eq!(model_1[..model_1.len() - 2], models[1][..]);
+        assert!(repaired.read(IndexType::MODEL, 2).unwrap().is_empty());
+    }
+}
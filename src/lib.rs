@@ -1,10 +1,22 @@
 pub mod archive;
+mod buffer;
+pub mod color;
 pub mod compression;
-mod errors;
+pub mod config;
+pub mod errors;
 pub mod filesystem;
+pub mod font;
 pub mod index;
+pub mod integrity;
+pub mod model;
+pub mod sprite;
 mod str;
-mod versionlist;
+#[cfg(test)]
+mod testing;
+pub mod texture;
+pub mod title;
+pub mod versionlist;
+pub mod widget;
 
 // TODO proper tests
 #[cfg(test)]
@@ -15,6 +27,7 @@ mod tests {
     use crate::index::IndexType;
 
     use crate::archive::ArchiveType;
+    use crate::title::TitleScreen;
     use std::fs::File;
     use std::io::Write;
 
@@ -22,8 +35,9 @@ mod tests {
     fn archive_decoding() {
         let fs = FileSystem::new("./data/cache/").unwrap();
         let title_archive = fs.read_archive(ArchiveType::TITLE).unwrap();
-        let logo_archive_entry = title_archive.entry_name("logo.dat").unwrap();
-        println!("{:#?}", logo_archive_entry);
+        let title_screen = TitleScreen::decode(&title_archive).unwrap();
+        assert_eq!(title_screen.fonts.len(), 4);
+        assert!(!title_screen.runes.is_empty());
     }
 
     #[test]
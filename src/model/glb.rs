@@ -0,0 +1,824 @@
+use crate::errors::FileSystemError;
+use crate::model::{ImportOptions, Model, ModelBuilder, DEFAULT_IMPORT_COLOR};
+use crate::texture::Texture;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use serde_json::{json, Value};
+
+/// The magic number a binary glTF file starts with, `glTF`.
+pub const GLB_MAGIC: u32 = 0x4654_6C67;
+pub const GLB_VERSION: u32 = 2;
+/// The chunk type of the JSON document, `JSON`.
+pub const JSON_CHUNK_TYPE: u32 = 0x4E4F_534A;
+/// The chunk type of the binary buffer, `BIN`.
+pub const BINARY_CHUNK_TYPE: u32 = 0x004E_4942;
+
+const FLOAT_COMPONENT_TYPE: u32 = 5126;
+const MAX_ZERO_ACCESSOR_COUNT: usize = 3 * u16::MAX as usize;
+const IDENTITY: [f64; 16] = [
+    1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0,
+];
+
+impl Model {
+    /// Exports the model as binary glTF 2.0.
+    ///
+    /// Every face gets vertices of its own, so faces keep their own colour and transparency as
+    /// vertex colours. Textured faces are drawn with the texture of the same id in `textures`,
+    /// which is embedded as a PNG, and in white when it is not there. Like the other exporters,
+    /// the model is turned upright and keeps the units of the client. A glTF mesh needs at least
+    /// one primitive, so models without faces can not be exported.
+    pub fn to_glb(&self, textures: &[Texture]) -> Result<Vec<u8>, FileSystemError> {
+        if self.faces.is_empty() {
+            return Err(FileSystemError::EmptyModelExport);
+        }
+        // the faces drawn with every material, the untextured faces first
+        let mut groups: Vec<(Option<&Texture>, Vec<usize>)> = vec![(None, Vec::new())];
+        for face in 0..self.faces.len() {
+            let texture = self.face_texture(face).and_then(|face_texture| {
+                textures
+                    .iter()
+                    .find(|texture| texture.id == u32::from(face_texture.texture_id))
+            });
+            match groups
+                .iter_mut()
+                .find(|(group_texture, _)| group_texture.map(|t| t.id) == texture.map(|t| t.id))
+            {
+                Some((_, faces)) => faces.push(face),
+                None => groups.push((texture, vec![face])),
+            }
+        }
+
+        let mut document = GlbDocument::default();
+        let mut primitives = Vec::new();
+        for (texture, faces) in groups.iter().filter(|(_, faces)| !faces.is_empty()) {
+            let mut positions = Vec::with_capacity(faces.len() * 3);
+            let mut colors = Vec::with_capacity(faces.len() * 3);
+            let mut texture_coordinates = Vec::new();
+            for face in faces {
+                let triangle = self.faces[*face];
+                let rgb = match texture {
+                    Some(_) => 0xFFFFFF,
+                    None if self.face_texture(*face).is_some() => 0xFFFFFF,
+                    None => self.face_rgb(*face),
+                };
+                let channel = |shift: u32| f32::from((rgb >> shift) as u8) / 255.0;
+                let color = [
+                    channel(16),
+                    channel(8),
+                    channel(0),
+                    self.face_opacity(*face),
+                ];
+                for vertex in [triangle.a, triangle.b, triangle.c].iter() {
+                    positions.push(self.upright_position(*vertex));
+                    colors.push(color);
+                }
+                if texture.is_some() {
+                    let coordinates = self.face_texture_coordinates(*face).unwrap_or_default();
+                    texture_coordinates.extend_from_slice(&coordinates);
+                }
+            }
+            let mut attributes = json!({
+                "POSITION": document.float_accessor(&positions, "VEC3", true),
+                "COLOR_0": document.float_accessor(&colors, "VEC4", false),
+            });
+            let mut material = json!({
+                "pbrMetallicRoughness": {
+                    "baseColorFactor": [1.0, 1.0, 1.0, 1.0],
+                    "metallicFactor": 0.0,
+                    "roughnessFactor": 1.0,
+                },
+                "doubleSided": true,
+            });
+            if colors.iter().any(|color| color[3] < 1.0) {
+                material["alphaMode"] = json!("BLEND");
+            }
+            if let Some(texture) = texture {
+                attributes["TEXCOORD_0"] =
+                    json!(document.float_accessor(&texture_coordinates, "VEC2", false));
+                let image = document.buffer_view(&texture.to_png()?);
+                document.images.push(json!({
+                    "bufferView": image,
+                    "mimeType": "image/png",
+                    "name": texture.id.to_string(),
+                }));
+                document.textures.push(json!({
+                    "source": document.images.len() - 1,
+                    "sampler": 0,
+                }));
+                material["pbrMetallicRoughness"]["baseColorTexture"] =
+                    json!({ "index": document.textures.len() - 1 });
+            }
+            document.materials.push(material);
+            primitives.push(json!({
+                "attributes": attributes,
+                "material": document.materials.len() - 1,
+            }));
+        }
+        document.align();
+        let mut gltf = json!({
+            "asset": { "version": "2.0", "generator": "legacy-rsfs" },
+            "scene": 0,
+            "scenes": [{ "nodes": [0] }],
+            "nodes": [{ "mesh": 0 }],
+            "meshes": [{ "primitives": primitives }],
+            "materials": document.materials,
+            "buffers": [{ "byteLength": document.buffer.len() }],
+            "bufferViews": document.buffer_views,
+            "accessors": document.accessors,
+        });
+        if !document.textures.is_empty() {
+            // textures repeat and are sampled without filtering, like the client draws them
+            gltf["samplers"] = json!([{ "magFilter": 9728, "minFilter": 9728 }]);
+            gltf["textures"] = json!(document.textures);
+            gltf["images"] = json!(document.images);
+        }
+        document.encode(&gltf)
+    }
+
+    /// Imports the meshes of the default scene of a binary glTF 2.0 file.
+    ///
+    /// Nodes are placed by their transforms and polygons are split into triangles. Faces get
+    /// the base colour of their material times the average colour of their vertices, which
+    /// becomes the closest colour of the client's colour table. The skins of `options` are
+    /// looked up by the name of the node, or of its mesh when the node has none. Textures and
+    /// points or lines are not imported, and files with sparse accessors are rejected.
+    pub fn from_glb(glb: &[u8], options: &ImportOptions) -> Result<Model, FileSystemError> {
+        let reader = GlbReader::new(glb)?;
+        let gltf = &reader.gltf;
+        let nodes = gltf["nodes"].as_array().map_or(&[][..], |nodes| nodes);
+        let scene = gltf["scene"].as_u64().unwrap_or(0) as usize;
+        let roots: Vec<usize> = match gltf["scenes"][scene]["nodes"].as_array() {
+            Some(roots) => roots.iter().filter_map(index).collect(),
+            // without scenes every node that is not a child of another is shown
+            None => {
+                let children: Vec<usize> = nodes
+                    .iter()
+                    .filter_map(|node| node["children"].as_array())
+                    .flatten()
+                    .filter_map(index)
+                    .collect();
+                (0..nodes.len())
+                    .filter(|node| !children.contains(node))
+                    .collect()
+            }
+        };
+
+        let mut builder = ModelBuilder::new(options);
+        let mut visited = vec![false; nodes.len()];
+        let mut pending: Vec<(usize, [f64; 16])> =
+            roots.iter().rev().map(|node| (*node, IDENTITY)).collect();
+        while let Some((node_index, parent)) = pending.pop() {
+            let node = nodes
+                .get(node_index)
+                .ok_or_else(|| invalid(format!("node {} does not exist", node_index)))?;
+            if std::mem::replace(&mut visited[node_index], true) {
+                return Err(invalid(format!("node {} is used twice", node_index)));
+            }
+            let transform = multiply(&parent, &node_transform(node));
+            if let Some(children) = node["children"].as_array() {
+                for child in children.iter().rev().filter_map(index) {
+                    pending.push((child, transform));
+                }
+            }
+            let mesh = match index(&node["mesh"]) {
+                Some(mesh) => &gltf["meshes"][mesh],
+                None => continue,
+            };
+            let group = node["name"].as_str().or_else(|| mesh["name"].as_str());
+            let primitives = mesh["primitives"].as_array().map_or(&[][..], |p| p);
+            for primitive in primitives {
+                reader.import_primitive(primitive, &transform, group, &mut builder)?;
+            }
+        }
+        Ok(builder.model)
+    }
+}
+
+// the parts of a glTF document that refer to each other by their position
+#[derive(Default)]
+struct GlbDocument {
+    buffer: Vec<u8>,
+    buffer_views: Vec<Value>,
+    accessors: Vec<Value>,
+    materials: Vec<Value>,
+    textures: Vec<Value>,
+    images: Vec<Value>,
+}
+
+impl GlbDocument {
+    // pads the buffer to a multiple of 4 bytes, which floats and chunks are aligned to
+    fn align(&mut self) {
+        self.buffer
+            .resize(self.buffer.len() + padding(self.buffer.len()), 0);
+    }
+
+    fn buffer_view(&mut self, data: &[u8]) -> usize {
+        self.align();
+        self.buffer_views.push(json!({
+            "buffer": 0,
+            "byteOffset": self.buffer.len(),
+            "byteLength": data.len(),
+        }));
+        self.buffer.extend_from_slice(data);
+        self.buffer_views.len() - 1
+    }
+
+    fn float_accessor<const N: usize>(
+        &mut self,
+        elements: &[[f32; N]],
+        element_type: &str,
+        bounds: bool,
+    ) -> usize {
+        let mut data = Vec::with_capacity(elements.len() * N * 4);
+        for value in elements.iter().flatten() {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        let view = self.buffer_view(&data);
+        let mut accessor = json!({
+            "bufferView": view,
+            "componentType": FLOAT_COMPONENT_TYPE,
+            "count": elements.len(),
+            "type": element_type,
+        });
+        if bounds {
+            let bound = |pick: fn(f32, f32) -> f32| -> Vec<f32> {
+                (0..N)
+                    .map(|component| {
+                        elements
+                            .iter()
+                            .map(|element| element[component])
+                            .fold(elements[0][component], pick)
+                    })
+                    .collect()
+            };
+            accessor["min"] = json!(bound(f32::min));
+            accessor["max"] = json!(bound(f32::max));
+        }
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+
+    fn encode(&self, gltf: &Value) -> Result<Vec<u8>, FileSystemError> {
+        let mut json = gltf.to_string().into_bytes();
+        json.resize(json.len() + padding(json.len()), b' ');
+        let buffer = &self.buffer;
+        let length = 12 + 8 + json.len() + 8 + buffer.len();
+        let mut glb = Vec::with_capacity(length);
+        glb.write_u32::<LittleEndian>(GLB_MAGIC)?;
+        glb.write_u32::<LittleEndian>(GLB_VERSION)?;
+        glb.write_u32::<LittleEndian>(length as u32)?;
+        glb.write_u32::<LittleEndian>(json.len() as u32)?;
+        glb.write_u32::<LittleEndian>(JSON_CHUNK_TYPE)?;
+        glb.extend_from_slice(&json);
+        glb.write_u32::<LittleEndian>(buffer.len() as u32)?;
+        glb.write_u32::<LittleEndian>(BINARY_CHUNK_TYPE)?;
+        glb.extend_from_slice(buffer);
+        Ok(glb)
+    }
+}
+
+// the bytes that pad a length to a multiple of 4
+fn padding(length: usize) -> usize {
+    (4 - length % 4) % 4
+}
+
+// the JSON document of a binary glTF file and its binary chunk
+struct GlbReader<'a> {
+    gltf: Value,
+    binary: &'a [u8],
+}
+
+impl<'a> GlbReader<'a> {
+    fn new(glb: &'a [u8]) -> Result<Self, FileSystemError> {
+        let mut header = glb;
+        if header.len() < 20
+            || header.read_u32::<LittleEndian>()? != GLB_MAGIC
+            || header.read_u32::<LittleEndian>()? != GLB_VERSION
+        {
+            return Err(invalid("not a binary glTF 2.0 file".to_string()));
+        }
+        let length = (header.read_u32::<LittleEndian>()? as usize).min(glb.len());
+        let mut chunks = &glb[12..length];
+        let mut json = None;
+        let mut binary: &[u8] = &[];
+        while chunks.len() >= 8 {
+            let chunk_length = chunks.read_u32::<LittleEndian>()? as usize;
+            let chunk_type = chunks.read_u32::<LittleEndian>()?;
+            let chunk = chunks
+                .get(..chunk_length)
+                .ok_or_else(|| invalid("a chunk goes past the end of the file".to_string()))?;
+            match chunk_type {
+                JSON_CHUNK_TYPE if json.is_none() => json = Some(chunk),
+                BINARY_CHUNK_TYPE => binary = chunk,
+                _ => {}
+            }
+            chunks = &chunks[chunk_length..];
+        }
+        let json = json.ok_or_else(|| invalid("the JSON chunk is missing".to_string()))?;
+        let gltf = serde_json::from_slice(json)
+            .map_err(|error| invalid(format!("the JSON chunk is invalid: {}", error)))?;
+        Ok(GlbReader { gltf, binary })
+    }
+
+    fn import_primitive(
+        &self,
+        primitive: &Value,
+        transform: &[f64; 16],
+        group: Option<&str>,
+        builder: &mut ModelBuilder,
+    ) -> Result<(), FileSystemError> {
+        let mode = primitive["mode"].as_u64().unwrap_or(4);
+        if !(4..=6).contains(&mode) {
+            return Ok(());
+        }
+        let attributes = &primitive["attributes"];
+        let positions = self.accessor(&attributes["POSITION"])?;
+        let indices: Vec<usize> = if primitive["indices"].is_null() {
+            (0..positions.len()).collect()
+        } else {
+            let indices = self.accessor(&primitive["indices"])?;
+            indices.iter().map(|index| index[0] as usize).collect()
+        };
+        let colors = if attributes["COLOR_0"].is_null() {
+            None
+        } else {
+            Some(self.accessor(&attributes["COLOR_0"])?)
+        };
+        let material = index(&primitive["material"]).map(|material| {
+            let factor =
+                &self.gltf["materials"][material]["pbrMetallicRoughness"]["baseColorFactor"];
+            let mut color = [1.0; 4];
+            for (component, value) in color.iter_mut().enumerate() {
+                *value = factor[component].as_f64().unwrap_or(1.0);
+            }
+            color
+        });
+
+        // the corners of every triangle, keeping the winding of strips and fans
+        let triangles: Vec<[usize; 3]> = match mode {
+            4 => indices
+                .chunks_exact(3)
+                .map(|corners| [corners[0], corners[1], corners[2]])
+                .collect(),
+            5 => (2..indices.len())
+                .map(|i| match i % 2 {
+                    0 => [indices[i - 2], indices[i - 1], indices[i]],
+                    _ => [indices[i - 1], indices[i - 2], indices[i]],
+                })
+                .collect(),
+            _ => (2..indices.len())
+                .map(|i| [indices[0], indices[i - 1], indices[i]])
+                .collect(),
+        };
+        for corners in triangles {
+            let mut vertices = [0; 3];
+            let mut color = material.unwrap_or([1.0; 4]);
+            if let Some(colors) = &colors {
+                let mut average = [0.0, 0.0, 0.0, 0.0];
+                for corner in corners.iter() {
+                    let vertex_color = colors.get(*corner).ok_or_else(|| missing(*corner))?;
+                    for (component, value) in average.iter_mut().enumerate() {
+                        *value += vertex_color.get(component).copied().unwrap_or(1.0) / 3.0;
+                    }
+                }
+                for (value, average) in color.iter_mut().zip(average.iter()) {
+                    *value *= average;
+                }
+            }
+            for (vertex, corner) in vertices.iter_mut().zip(corners.iter()) {
+                let position = positions.get(*corner).ok_or_else(|| missing(*corner))?;
+                *vertex = builder.vertex(transform_point(transform, position), group)?;
+            }
+            let rgb = if material.is_none() && colors.is_none() {
+                DEFAULT_IMPORT_COLOR
+            } else {
+                let channel = |value: f64| (value.clamp(0.0, 1.0) * 255.0).round() as u32;
+                (channel(color[0]) << 16) | (channel(color[1]) << 8) | channel(color[2])
+            };
+            builder.polygon(&vertices, rgb, color[3]);
+        }
+        Ok(())
+    }
+
+    // the elements of an accessor, with normalized integers turned into fractions
+    fn accessor(&self, accessor_index: &Value) -> Result<Vec<Vec<f64>>, FileSystemError> {
+        let accessor = index(accessor_index)
+            .and_then(|accessor| self.gltf["accessors"].get(accessor))
+            .ok_or_else(|| invalid(format!("accessor {} does not exist", accessor_index)))?;
+        if !accessor["sparse"].is_null() {
+            return Err(invalid("sparse accessors are not supported".to_string()));
+        }
+        let count = accessor["count"].as_u64().unwrap_or(0) as usize;
+        let components = match accessor["type"].as_str() {
+            Some("SCALAR") => 1,
+            Some("VEC2") => 2,
+            Some("VEC3") => 3,
+            Some("VEC4") => 4,
+            element_type => {
+                return Err(invalid(format!(
+                    "accessors of type {:?} are not supported",
+                    element_type
+                )))
+            }
+        };
+        let component_type = accessor["componentType"].as_u64().unwrap_or(0);
+        let size = match component_type {
+            5120 | 5121 => 1,
+            5122 | 5123 => 2,
+            5125 | 5126 => 4,
+            _ => {
+                return Err(invalid(format!(
+                    "component type {} is not supported",
+                    component_type
+                )))
+            }
+        };
+        let normalized = accessor["normalized"].as_bool().unwrap_or(false);
+        let view = match index(&accessor["bufferView"]) {
+            Some(view) => &self.gltf["bufferViews"][view],
+            // accessors without a buffer view are all zeros, and never need more elements than the
+            // corners of the most faces a model holds
+            None if count <= MAX_ZERO_ACCESSOR_COUNT => {
+                return Ok(vec![vec![0.0; components]; count])
+            }
+            None => return Err(too_long()),
+        };
+        let buffer = index(&view["buffer"]).unwrap_or(0);
+        if buffer != 0 || self.gltf["buffers"][buffer]["uri"].is_string() {
+            return Err(invalid(
+                "only the binary chunk is supported as a buffer".to_string(),
+            ));
+        }
+        let view_start = view["byteOffset"].as_u64().unwrap_or(0) as usize;
+        let view_length = view["byteLength"].as_u64().unwrap_or(0) as usize;
+        let data = view_start
+            .checked_add(view_length)
+            .and_then(|view_end| self.binary.get(view_start..view_end))
+            .ok_or_else(|| invalid("a buffer view goes past the binary chunk".to_string()))?;
+        let offset = accessor["byteOffset"].as_u64().unwrap_or(0) as usize;
+        let stride = view["byteStride"]
+            .as_u64()
+            .map_or(components * size, |stride| stride as usize);
+        // the last element has to end within the view before any element is read
+        if count > 0 {
+            (count - 1)
+                .checked_mul(stride)
+                .and_then(|start| start.checked_add(offset))
+                .and_then(|start| start.checked_add(components * size))
+                .filter(|end| *end <= data.len())
+                .ok_or_else(too_long)?;
+        }
+
+        let mut elements = Vec::with_capacity(count);
+        for element in 0..count {
+            let start = offset + element * stride;
+            let mut values = Vec::with_capacity(components);
+            for component in 0..components {
+                let position = start + component * size;
+                let mut bytes = &data[position..position + size];
+                let value = match component_type {
+                    5120 => f64::from(bytes.read_i8()?),
+                    5121 => f64::from(bytes.read_u8()?),
+                    5122 => f64::from(bytes.read_i16::<LittleEndian>()?),
+                    5123 => f64::from(bytes.read_u16::<LittleEndian>()?),
+                    5125 => f64::from(bytes.read_u32::<LittleEndian>()?),
+                    _ => f64::from(bytes.read_f32::<LittleEndian>()?),
+                };
+                values.push(match (normalized, component_type) {
+                    (true, 5120) => (value / 127.0).max(-1.0),
+                    (true, 5121) => value / 255.0,
+                    (true, 5122) => (value / 32767.0).max(-1.0),
+                    (true, 5123) => value / 65535.0,
+                    _ => value,
+                });
+            }
+            elements.push(values);
+        }
+        Ok(elements)
+    }
+}
+
+fn invalid(reason: String) -> FileSystemError {
+    FileSystemError::InvalidModelImport(reason)
+}
+
+fn too_long() -> FileSystemError {
+    invalid("an accessor goes past its buffer view".to_string())
+}
+
+fn missing(vertex: usize) -> FileSystemError {
+    invalid(format!("vertex {} of a primitive does not exist", vertex))
+}
+
+fn index(value: &Value) -> Option<usize> {
+    value.as_u64().map(|index| index as usize)
+}
+
+// the column-major matrix that places a node within its parent
+fn node_transform(node: &Value) -> [f64; 16] {
+    let numbers = |key: &str, default: &[f64]| -> Vec<f64> {
+        (0..default.len())
+            .map(|i| node[key][i].as_f64().unwrap_or(default[i]))
+            .collect()
+    };
+    if node["matrix"].is_array() {
+        let mut matrix = IDENTITY;
+        matrix.copy_from_slice(&numbers("matrix", &IDENTITY));
+        return matrix;
+    }
+    let t = numbers("translation", &[0.0; 3]);
+    let r = numbers("rotation", &[0.0, 0.0, 0.0, 1.0]);
+    let s = numbers("scale", &[1.0; 3]);
+    let (x, y, z, w) = (r[0], r[1], r[2], r[3]);
+    [
+        (1.0 - 2.0 * (y * y + z * z)) * s[0],
+        2.0 * (x * y + z * w) * s[0],
+        2.0 * (x * z - y * w) * s[0],
+        0.0,
+        2.0 * (x * y - z * w) * s[1],
+        (1.0 - 2.0 * (x * x + z * z)) * s[1],
+        2.0 * (y * z + x * w) * s[1],
+        0.0,
+        2.0 * (x * z + y * w) * s[2],
+        2.0 * (y * z - x * w) * s[2],
+        (1.0 - 2.0 * (x * x + y * y)) * s[2],
+        0.0,
+        t[0],
+        t[1],
+        t[2],
+        1.0,
+    ]
+}
+
+fn multiply(a: &[f64; 16], b: &[f64; 16]) -> [f64; 16] {
+    let mut product = [0.0; 16];
+    for column in 0..4 {
+        for row in 0..4 {
+            product[column * 4 + row] = (0..4).map(|k| a[k * 4 + row] * b[column * 4 + k]).sum();
+        }
+    }
+    product
+}
+
+fn transform_point(matrix: &[f64; 16], point: &[f64]) -> [f64; 3] {
+    let mut transformed = [0.0; 3];
+    for (row, value) in transformed.iter_mut().enumerate() {
+        *value = (0..3)
+            .map(|k| matrix[k * 4 + row] * point.get(k).copied().unwrap_or(0.0))
+            .sum::<f64>()
+            + matrix[12 + row];
+    }
+    transformed
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::FileSystemError;
+    use crate::model::glb::{BINARY_CHUNK_TYPE, GLB_MAGIC, JSON_CHUNK_TYPE};
+    use crate::model::{FacePriorities, ImportOptions, Model, Triangle, Vertex};
+    use crate::texture::Texture;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn chunk(glb: &[u8], offset: usize) -> (u32, &[u8]) {
+        let length = u32::from_le_bytes([
+            glb[offset],
+            glb[offset + 1],
+            glb[offset + 2],
+            glb[offset + 3],
+        ]) as usize;
+        let chunk_type = u32::from_le_bytes([
+            glb[offset + 4],
+            glb[offset + 5],
+            glb[offset + 6],
+            glb[offset + 7],
+        ]);
+        (chunk_type, &glb[offset + 8..offset + 8 + length])
+    }
+
+    #[test]
+    fn test_glb_export() {
+        let model = Model {
+            vertices: vec![
+                Vertex { x: 0, y: 0, z: 0 },
+                Vertex { x: 128, y: 0, z: 0 },
+                Vertex {
+                    x: 0,
+                    y: -128,
+                    z: 64,
+                },
+            ],
+            vertex_skins: None,
+            faces: vec![Triangle { a: 0, b: 1, c: 2 }; 3],
+            face_colors: vec![127, 5, 6],
+            face_render_types: Some(vec![0, 2, 2]),
+            face_priorities: FacePriorities::Model(0),
+            face_alphas: Some(vec![0, 0, 128]),
+            face_skins: None,
+            texture_triangles: vec![Triangle { a: 0, b: 1, c: 2 }],
+        };
+        let texture = Texture {
+            id: 5,
+            width: 1,
+            height: 1,
+            palette: vec![0, 0xFF0000],
+            pixels: vec![1],
+        };
+        let glb = model.to_glb(&[texture]).unwrap();
+        assert_eq!(glb[..4], GLB_MAGIC.to_le_bytes());
+        assert_eq!(glb[8..12], (glb.len() as u32).to_le_bytes());
+        let (json_type, json) = chunk(&glb, 12);
+        assert_eq!(json_type, JSON_CHUNK_TYPE);
+        let (binary_type, binary) = chunk(&glb, 20 + json.len());
+        assert_eq!(binary_type, BINARY_CHUNK_TYPE);
+
+        let gltf: serde_json::Value = serde_json::from_slice(json).unwrap();
+        // the face of the missing texture 6 is drawn with the untextured faces
+        let primitives = gltf["meshes"][0]["primitives"].as_array().unwrap();
+        assert_eq!(primitives.len(), 2);
+        assert_eq!(gltf["materials"][0]["alphaMode"], "BLEND");
+        assert!(gltf["materials"][1]["alphaMode"].is_null());
+        assert_eq!(
+            gltf["materials"][1]["pbrMetallicRoughness"]["baseColorTexture"]["index"],
+            0
+        );
+        assert!(primitives[1]["attributes"]["TEXCOORD_0"].is_number());
+        let position = &gltf["accessors"][0];
+        assert_eq!(position["count"], 6);
+        assert_eq!(position["min"], serde_json::json!([0.0, 0.0, -64.0]));
+        assert_eq!(position["max"], serde_json::json!([128.0, 128.0, 0.0]));
+        assert_eq!(gltf["buffers"][0]["byteLength"], binary.len());
+        let image =
+            &gltf["bufferViews"][gltf["images"][0]["bufferView"].as_u64().unwrap() as usize];
+        let start = image["byteOffset"].as_u64().unwrap() as usize;
+        assert_eq!(binary[start..start + 4], [0x89, b'P', b'N', b'G']);
+
+        let empty = Model {
+            faces: Vec::new(),
+            face_colors: Vec::new(),
+            face_render_types: None,
+            face_alphas: None,
+            ..model
+        };
+        assert!(matches!(
+            empty.to_glb(&[]),
+            Err(FileSystemError::EmptyModelExport)
+        ));
+    }
+
+    #[test]
+    fn test_glb_import() {
+        let model = Model {
+            vertices: vec![
+                Vertex { x: 0, y: 0, z: 0 },
+                Vertex { x: 128, y: 0, z: 0 },
+                Vertex {
+                    x: 0,
+                    y: -128,
+                    z: 64,
+                },
+                Vertex {
+                    x: 128,
+                    y: -128,
+                    z: 64,
+                },
+            ],
+            vertex_skins: None,
+            faces: vec![Triangle { a: 0, b: 1, c: 2 }, Triangle { a: 2, b: 1, c: 3 }],
+            face_colors: vec![127, 933],
+            face_render_types: None,
+            face_priorities: FacePriorities::Model(0),
+            face_alphas: Some(vec![0, 128]),
+            face_skins: None,
+            texture_triangles: Vec::new(),
+        };
+        let glb = model.to_glb(&[]).unwrap();
+        let imported = Model::from_glb(&glb, &ImportOptions::default()).unwrap();
+        assert_eq!(imported.vertices, model.vertices);
+        assert_eq!(imported.faces, model.faces);
+        assert_eq!(imported.face_alphas, model.face_alphas);
+        let table = crate::color::ColorTable::new(crate::color::DEFAULT_BRIGHTNESS);
+        for (imported, original) in imported.face_colors.iter().zip(model.face_colors.iter()) {
+            assert_eq!(table.rgb(*imported), table.rgb(*original));
+        }
+        assert!(Model::from_glb(&glb[..16], &ImportOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_glb_import_invalid_accessors() {
+        let binary: Vec<u8> = [0.0f32; 9].iter().flat_map(|v| v.to_le_bytes()).collect();
+        let glb = |accessor: serde_json::Value| {
+            let gltf = json!({
+                "asset": { "version": "2.0" },
+                "scenes": [{ "nodes": [0] }],
+                "nodes": [{ "mesh": 0 }],
+                "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 } }] }],
+                "buffers": [{ "byteLength": binary.len() }],
+                "bufferViews": [{ "buffer": 0, "byteLength": 36, "byteStride": 12 }],
+                "accessors": [accessor],
+            });
+            let document = super::GlbDocument {
+                buffer: binary.clone(),
+                ..Default::default()
+            };
+            document.encode(&gltf).unwrap()
+        };
+        let accessors = [
+            json!({ "bufferView": 0, "componentType": 5126, "count": 4, "type": "VEC3" }),
+            json!({ "bufferView": 0, "componentType": 5126, "count": u64::MAX, "type": "VEC3" }),
+            json!({
+                "bufferView": 0, "byteOffset": u64::MAX,
+                "componentType": 5126, "count": 1, "type": "VEC3",
+            }),
+            json!({ "componentType": 5126, "count": 1u64 << 40, "type": "VEC3" }),
+            json!({
+                "componentType": 5126, "count": 3, "type": "VEC3",
+                "sparse": {
+                    "count": 1,
+                    "indices": { "bufferView": 0, "componentType": 5125 },
+                    "values": { "bufferView": 0 },
+                },
+            }),
+        ];
+        for accessor in accessors.iter() {
+            assert!(matches!(
+                Model::from_glb(&glb(accessor.clone()), &ImportOptions::default()),
+                Err(FileSystemError::InvalidModelImport(_))
+            ));
+        }
+        let model =
+            glb(json!({ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3" }));
+        assert!(Model::from_glb(&model, &ImportOptions::default()).is_ok());
+    }
+
+    #[test]
+    fn test_glb_import_nodes() {
+        // a quad of unsigned short indices, placed by a parent and a child node
+        let mut binary = Vec::new();
+        for value in [
+            0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0,
+        ] {
+            binary.extend_from_slice(&value.to_le_bytes());
+        }
+        for index in [0u16, 1, 2, 0, 2, 3] {
+            binary.extend_from_slice(&index.to_le_bytes());
+        }
+        let gltf = json!({
+            "asset": { "version": "2.0" },
+            "scenes": [{ "nodes": [0] }],
+            "nodes": [
+                { "name": "body", "children": [1], "scale": [128.0, 128.0, 128.0] },
+                { "name": "head", "mesh": 0, "translation": [0.0, 1.0, 0.0] },
+            ],
+            "meshes": [{ "primitives": [{
+                "attributes": { "POSITION": 0 },
+                "indices": 1,
+                "material": 0,
+            }] }],
+            "materials": [{ "pbrMetallicRoughness": { "baseColorFactor": [1.0, 0.0, 0.0, 1.0] } }],
+            "buffers": [{ "byteLength": binary.len() }],
+            "bufferViews": [
+                { "buffer": 0, "byteLength": 48 },
+                { "buffer": 0, "byteOffset": 48, "byteLength": 12 },
+            ],
+            "accessors": [
+                { "bufferView": 0, "componentType": 5126, "count": 4, "type": "VEC3" },
+                { "bufferView": 1, "componentType": 5123, "count": 6, "type": "SCALAR" },
+            ],
+        });
+        let document = super::GlbDocument {
+            buffer: binary,
+            ..Default::default()
+        };
+        let glb = document.encode(&gltf).unwrap();
+
+        let mut skins = HashMap::new();
+        skins.insert("head".to_string(), 2);
+        let options = ImportOptions {
+            scale: 1.0,
+            vertex_skins: Some(skins),
+        };
+        let model = Model::from_glb(&glb, &options).unwrap();
+        assert_eq!(model.faces.len(), 2);
+        assert_eq!(
+            model.vertices[0],
+            Vertex {
+                x: 0,
+                y: -128,
+                z: 0
+            }
+        );
+        assert_eq!(
+            model.vertices[2],
+            Vertex {
+                x: 128,
+                y: -256,
+                z: 0
+            }
+        );
+        assert_eq!(model.vertex_skins, Some(vec![2; 4]));
+        let table = crate::color::ColorTable::new(crate::color::DEFAULT_BRIGHTNESS);
+        let red = table.rgb(model.face_colors[0]);
+        assert!(red >> 16 > 0xC0 && red & 0xFFFF < 0x4040);
+    }
+}
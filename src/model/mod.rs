@@ -0,0 +1,826 @@
+use crate::archive::ArchiveType;
+use crate::buffer::{BufferReadExt, BufferWriteExt};
+use crate::color::{self, ColorTable, DEFAULT_BRIGHTNESS};
+use crate::compression::{compress_gzip, decompress_gzip};
+use crate::errors::FileSystemError;
+use crate::filesystem::FileSystem;
+use crate::index::IndexType;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+pub mod glb;
+pub mod obj;
+
+/// The size of the counts and section lengths stored at the end of a model.
+pub const FOOTER_SIZE: usize = 18;
+/// The footer priority that gives every face a priority of its own.
+pub const PER_FACE_PRIORITY: u8 = 255;
+
+/// A model of the model index.
+///
+/// The model is kept the way it is stored, with one entry per face in every face attribute. The
+/// optional attributes are only stored by some models.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Model {
+    pub vertices: Vec<Vertex>,
+    /// The labels animation frames select vertices by.
+    pub vertex_skins: Option<Vec<u8>>,
+    /// The vertices of every face.
+    pub faces: Vec<Triangle>,
+    /// The colour of every face as 16 bit HSL, or the texture of textured faces.
+    pub face_colors: Vec<u16>,
+    /// How every face is drawn, see `is_flat_shaded` and `face_texture`.
+    pub face_render_types: Option<Vec<u8>>,
+    pub face_priorities: FacePriorities,
+    /// The transparency of every face, from 0 for opaque to 255.
+    pub face_alphas: Option<Vec<u8>>,
+    /// The labels animation frames select faces by, to change their transparency.
+    pub face_skins: Option<Vec<u8>>,
+    /// The vertices that map the textures of textured faces.
+    pub texture_triangles: Vec<Triangle>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Vertex {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+}
+
+/// Three vertex indices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Triangle {
+    pub a: u16,
+    pub b: u16,
+    pub c: u16,
+}
+
+/// The order the client draws overlapping faces of the model in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FacePriorities {
+    /// Every face is drawn with the same priority.
+    Model(u8),
+    Faces(Vec<u8>),
+}
+
+/// The colour of imported faces without a material.
+pub const DEFAULT_IMPORT_COLOR: u32 = 0x808080;
+
+/// How an OBJ or glTF mesh is turned into a model.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportOptions {
+    /// What positions are multiplied by before they are rounded to whole units. The exporters
+    /// keep the units of the client, where a tile is 128 units wide.
+    pub scale: f64,
+    /// The vertex skins of the vertices of every named OBJ group or object, or glTF node. When
+    /// given, vertices outside of these groups get skin 0.
+    pub vertex_skins: Option<HashMap<String, u8>>,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        ImportOptions {
+            scale: 1.0,
+            vertex_skins: None,
+        }
+    }
+}
+
+/// The file formats models are exported to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModelFormat {
+    /// Wavefront OBJ with an MTL material library, see `Model::to_obj`.
+    Obj,
+    /// Binary glTF 2.0, see `Model::to_glb`.
+    Glb,
+}
+
+impl ModelFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ModelFormat::Obj => "obj",
+            ModelFormat::Glb => "glb",
+        }
+    }
+}
+
+/// The texture a textured face is drawn with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FaceTexture {
+    pub texture_id: u16,
+    /// The vertices the texture is mapped onto the face by.
+    pub triangle: Triangle,
+}
+
+impl Model {
+    /// Decodes a model from its decompressed data.
+    pub fn decode(data: &[u8]) -> Result<Self, FileSystemError> {
+        if data.len() < FOOTER_SIZE {
+            return Err(FileSystemError::ModelTooShort(data.len()));
+        }
+        let mut footer = &data[data.len() - FOOTER_SIZE..];
+        let vertex_count = footer.read_u16::<BigEndian>()? as usize;
+        let face_count = footer.read_u16::<BigEndian>()? as usize;
+        let texture_triangle_count = footer.read_u8()? as usize;
+        let has_render_types = footer.read_u8()? == 1;
+        let priority = footer.read_u8()?;
+        let has_alphas = footer.read_u8()? == 1;
+        let has_face_skins = footer.read_u8()? == 1;
+        let has_vertex_skins = footer.read_u8()? == 1;
+        let x_length = footer.read_u16::<BigEndian>()? as usize;
+        let y_length = footer.read_u16::<BigEndian>()? as usize;
+        let z_length = footer.read_u16::<BigEndian>()? as usize;
+        let face_index_length = footer.read_u16::<BigEndian>()? as usize;
+
+        // the sections follow each other in this order, and the optional ones are left out
+        let mut offset = 0;
+        let mut section = |length: usize, present: bool| {
+            let start = offset;
+            if present {
+                offset += length;
+            }
+            data.get(start..).unwrap_or(&[])
+        };
+        let mut vertex_flags = section(vertex_count, true);
+        let mut face_types = section(face_count, true);
+        let mut priorities = section(face_count, priority == PER_FACE_PRIORITY);
+        let mut face_skin_data = section(face_count, has_face_skins);
+        let mut render_type_data = section(face_count, has_render_types);
+        let mut vertex_skin_data = section(vertex_count, has_vertex_skins);
+        let mut alpha_data = section(face_count, has_alphas);
+        let mut face_indices = section(face_index_length, true);
+        let mut colors = section(face_count * 2, true);
+        let mut texture_triangle_data = section(texture_triangle_count * 6, true);
+        let mut x_data = section(x_length, true);
+        let mut y_data = section(y_length, true);
+        let mut z_data = section(z_length, true);
+
+        let mut vertices = Vec::with_capacity(vertex_count);
+        let mut vertex = Vertex::default();
+        for _ in 0..vertex_count {
+            let flags = vertex_flags.read_u8()?;
+            if flags & 1 != 0 {
+                vertex.x += x_data.read_signed_smart()?;
+            }
+            if flags & 2 != 0 {
+                vertex.y += y_data.read_signed_smart()?;
+            }
+            if flags & 4 != 0 {
+                vertex.z += z_data.read_signed_smart()?;
+            }
+            vertices.push(vertex);
+        }
+        let vertex_skins = if has_vertex_skins {
+            Some(read_bytes(&mut vertex_skin_data, vertex_count)?)
+        } else {
+            None
+        };
+
+        let mut face_colors = Vec::with_capacity(face_count);
+        for _ in 0..face_count {
+            face_colors.push(colors.read_u16::<BigEndian>()?);
+        }
+        let face_render_types = if has_render_types {
+            Some(read_bytes(&mut render_type_data, face_count)?)
+        } else {
+            None
+        };
+        let face_priorities = match priority {
+            PER_FACE_PRIORITY => FacePriorities::Faces(read_bytes(&mut priorities, face_count)?),
+            priority => FacePriorities::Model(priority),
+        };
+        let face_alphas = if has_alphas {
+            Some(read_bytes(&mut alpha_data, face_count)?)
+        } else {
+            None
+        };
+        let face_skins = if has_face_skins {
+            Some(read_bytes(&mut face_skin_data, face_count)?)
+        } else {
+            None
+        };
+
+        let mut faces = Vec::with_capacity(face_count);
+        let mut face = Triangle::default();
+        let mut last = 0;
+        let mut next_index = |data: &mut &[u8]| -> Result<u16, FileSystemError> {
+            last += data.read_signed_smart()?;
+            Ok(last as u16)
+        };
+        for _ in 0..face_count {
+            // every type but the first shares two vertices with the previous face
+            match face_types.read_u8()? {
+                1 => {
+                    face.a = next_index(&mut face_indices)?;
+                    face.b = next_index(&mut face_indices)?;
+                    face.c = next_index(&mut face_indices)?;
+                }
+                2 => {
+                    face.b = face.c;
+                    face.c = next_index(&mut face_indices)?;
+                }
+                3 => {
+                    face.a = face.c;
+                    face.c = next_index(&mut face_indices)?;
+                }
+                4 => {
+                    std::mem::swap(&mut face.a, &mut face.b);
+                    face.c = next_index(&mut face_indices)?;
+                }
+                // like the client, a face of an unknown type repeats the previous one
+                _ => {}
+            }
+            faces.push(face);
+        }
+
+        let mut texture_triangles = Vec::with_capacity(texture_triangle_count);
+        for _ in 0..texture_triangle_count {
+            texture_triangles.push(Triangle {
+                a: texture_triangle_data.read_u16::<BigEndian>()?,
+                b: texture_triangle_data.read_u16::<BigEndian>()?,
+                c: texture_triangle_data.read_u16::<BigEndian>()?,
+            });
+        }
+
+        Ok(Model {
+            vertices,
+            vertex_skins,
+            faces,
+            face_colors,
+            face_render_types,
+            face_priorities,
+            face_alphas,
+            face_skins,
+            texture_triangles,
+        })
+    }
+
+    /// Encodes the model, with the vertices stored as the differences between them and the faces
+    /// sharing the vertices of the previous face wherever they can.
+    ///
+    /// A model priority of `PER_FACE_PRIORITY` is stored as that priority for every face.
+    pub fn encode(&self) -> Result<Vec<u8>, FileSystemError> {
+        let vertex_count = count(self.vertices.len(), u16::MAX as usize, "vertices")?;
+        let face_count = count(self.faces.len(), u16::MAX as usize, "faces")?;
+        let texture_triangle_count = count(
+            self.texture_triangles.len(),
+            u8::MAX as usize,
+            "texture triangles",
+        )?;
+        let faces = self.faces.len();
+        let priorities = match &self.face_priorities {
+            FacePriorities::Model(PER_FACE_PRIORITY) => Some(vec![PER_FACE_PRIORITY; faces]),
+            FacePriorities::Model(_) => None,
+            FacePriorities::Faces(priorities) => Some(priorities.clone()),
+        };
+        check_length("face colours", &self.face_colors, faces)?;
+        if let Some(priorities) = &priorities {
+            check_length("face priorities", priorities, faces)?;
+        }
+        let optional = [
+            ("vertex skins", &self.vertex_skins, self.vertices.len()),
+            ("face render types", &self.face_render_types, faces),
+            ("face alphas", &self.face_alphas, faces),
+            ("face skins", &self.face_skins, faces),
+        ];
+        for (attribute, values, expected) in optional.iter() {
+            if let Some(values) = values {
+                check_length(attribute, values, *expected)?;
+            }
+        }
+
+        let mut vertex_flags = Vec::with_capacity(self.vertices.len());
+        let (mut x_data, mut y_data, mut z_data) = (Vec::new(), Vec::new(), Vec::new());
+        let mut previous = Vertex::default();
+        for vertex in &self.vertices {
+            let mut flags = 0;
+            let deltas = [
+                (1, vertex.x - previous.x, &mut x_data),
+                (2, vertex.y - previous.y, &mut y_data),
+                (4, vertex.z - previous.z, &mut z_data),
+            ];
+            for (flag, delta, data) in deltas {
+                if delta != 0 {
+                    flags |= flag;
+                    data.write_signed_smart(delta)?;
+                }
+            }
+            vertex_flags.push(flags);
+            previous = *vertex;
+        }
+
+        let mut face_types = Vec::with_capacity(faces);
+        let mut face_indices = Vec::new();
+        let mut previous = Triangle::default();
+        let mut last = 0;
+        let mut write_index = |data: &mut Vec<u8>, index: u16| -> Result<(), FileSystemError> {
+            data.write_signed_smart(i32::from(index) - last)?;
+            last = i32::from(index);
+            Ok(())
+        };
+        for face in &self.faces {
+            let shared = if face.a == previous.a && face.b == previous.c {
+                Some(2)
+            } else if face.a == previous.c && face.b == previous.b {
+                Some(3)
+            } else if face.a == previous.b && face.b == previous.a {
+                Some(4)
+            } else {
+                None
+            };
+            match shared {
+                Some(face_type) => face_types.push(face_type),
+                None => {
+                    face_types.push(1);
+                    write_index(&mut face_indices, face.a)?;
+                    write_index(&mut face_indices, face.b)?;
+                }
+            }
+            write_index(&mut face_indices, face.c)?;
+            previous = *face;
+        }
+
+        let mut buffer = Vec::new();
+        buffer.extend_from_slice(&vertex_flags);
+        buffer.extend_from_slice(&face_types);
+        // the optional sections in the order they are stored in
+        let sections = [
+            &priorities,
+            &self.face_skins,
+            &self.face_render_types,
+            &self.vertex_skins,
+            &self.face_alphas,
+        ];
+        for section in sections.iter().copied().flatten() {
+            buffer.extend_from_slice(section);
+        }
+        buffer.extend_from_slice(&face_indices);
+        for color in &self.face_colors {
+            buffer.write_u16::<BigEndian>(*color)?;
+        }
+        for triangle in &self.texture_triangles {
+            buffer.write_u16::<BigEndian>(triangle.a)?;
+            buffer.write_u16::<BigEndian>(triangle.b)?;
+            buffer.write_u16::<BigEndian>(triangle.c)?;
+        }
+        buffer.extend_from_slice(&x_data);
+        buffer.extend_from_slice(&y_data);
+        buffer.extend_from_slice(&z_data);
+
+        buffer.write_u16::<BigEndian>(vertex_count as u16)?;
+        buffer.write_u16::<BigEndian>(face_count as u16)?;
+        buffer.write_u8(texture_triangle_count as u8)?;
+        buffer.write_u8(self.face_render_types.is_some() as u8)?;
+        buffer.write_u8(match &self.face_priorities {
+            FacePriorities::Model(priority) => *priority,
+            FacePriorities::Faces(_) => PER_FACE_PRIORITY,
+        })?;
+        buffer.write_u8(self.face_alphas.is_some() as u8)?;
+        buffer.write_u8(self.face_skins.is_some() as u8)?;
+        buffer.write_u8(self.vertex_skins.is_some() as u8)?;
+        for (section, data) in [
+            ("bytes of x coordinates", &x_data),
+            ("bytes of y coordinates", &y_data),
+            ("bytes of z coordinates", &z_data),
+            ("bytes of face indices", &face_indices),
+        ]
+        .iter()
+        {
+            let length = count(data.len(), u16::MAX as usize, section)?;
+            buffer.write_u16::<BigEndian>(length as u16)?;
+        }
+        Ok(buffer)
+    }
+
+    /// The render type of a face, 0 for faces of models without render types.
+    pub fn face_render_type(&self, face: usize) -> u8 {
+        self.face_render_types
+            .as_ref()
+            .and_then(|render_types| render_types.get(face).copied())
+            .unwrap_or(0)
+    }
+
+    /// Whether the face is drawn in one colour instead of blending the colours of its vertices.
+    pub fn is_flat_shaded(&self, face: usize) -> bool {
+        self.face_render_type(face) & 1 != 0
+    }
+
+    /// The texture of a textured face. Its colour is the texture id, and the upper bits of its
+    /// render type select the texture triangle.
+    pub fn face_texture(&self, face: usize) -> Option<FaceTexture> {
+        let render_type = self.face_render_type(face);
+        if render_type & 2 == 0 {
+            return None;
+        }
+        Some(FaceTexture {
+            texture_id: *self.face_colors.get(face)?,
+            triangle: *self.texture_triangles.get((render_type >> 2) as usize)?,
+        })
+    }
+
+    /// The priority of a face.
+    pub fn face_priority(&self, face: usize) -> u8 {
+        match &self.face_priorities {
+            FacePriorities::Model(priority) => *priority,
+            FacePriorities::Faces(priorities) => priorities.get(face).copied().unwrap_or(0),
+        }
+    }
+}
+
+// the helpers the exporters share
+impl Model {
+    // the position of a vertex with the y and z axes turned around, as the client points y down
+    fn upright_position(&self, vertex: u16) -> [f32; 3] {
+        let vertex = self
+            .vertices
+            .get(vertex as usize)
+            .copied()
+            .unwrap_or_default();
+        [vertex.x as f32, (-vertex.y) as f32, (-vertex.z) as f32]
+    }
+
+    // the colour of an untextured face as 0xRRGGBB
+    fn face_rgb(&self, face: usize) -> u32 {
+        let hsl = self.face_colors.get(face).copied().unwrap_or(0);
+        color::hsl16_to_rgb(hsl, DEFAULT_BRIGHTNESS)
+    }
+
+    // how opaque a face is, from 0 to 1
+    fn face_opacity(&self, face: usize) -> f32 {
+        let alpha = self
+            .face_alphas
+            .as_ref()
+            .and_then(|alphas| alphas.get(face).copied())
+            .unwrap_or(0);
+        1.0 - f32::from(alpha) / 255.0
+    }
+
+    // the texture coordinates of the vertices of a textured face, with the first vertex of the
+    // texture triangle at the top left of the texture, the second at the top right and the third
+    // at the bottom left
+    fn face_texture_coordinates(&self, face: usize) -> Option<[[f32; 2]; 3]> {
+        let triangle = self.face_texture(face)?.triangle;
+        let position = |vertex: u16| {
+            let vertex = self
+                .vertices
+                .get(vertex as usize)
+                .copied()
+                .unwrap_or_default();
+            [vertex.x as f64, vertex.y as f64, vertex.z as f64]
+        };
+        let subtract = |a: [f64; 3], b: [f64; 3]| [a[0] - b[0], a[1] - b[1], a[2] - b[2]];
+        let dot = |a: [f64; 3], b: [f64; 3]| a[0] * b[0] + a[1] * b[1] + a[2] * b[2];
+        let origin = position(triangle.a);
+        let u_axis = subtract(position(triangle.b), origin);
+        let v_axis = subtract(position(triangle.c), origin);
+        let (uu, uv, vv) = (
+            dot(u_axis, u_axis),
+            dot(u_axis, v_axis),
+            dot(v_axis, v_axis),
+        );
+        let denominator = uu * vv - uv * uv;
+        let face = self.faces.get(face)?;
+        let mut coordinates = [[0.0; 2]; 3];
+        for (coordinate, vertex) in coordinates.iter_mut().zip([face.a, face.b, face.c].iter()) {
+            if denominator == 0.0 {
+                continue;
+            }
+            let offset = subtract(position(*vertex), origin);
+            let (ou, ov) = (dot(offset, u_axis), dot(offset, v_axis));
+            *coordinate = [
+                ((vv * ou - uv * ov) / denominator) as f32,
+                ((uu * ov - uv * ou) / denominator) as f32,
+            ];
+        }
+        Some(coordinates)
+    }
+}
+
+// collects the vertices and faces of an imported mesh, sharing the vertices faces have in common
+struct ModelBuilder<'a> {
+    options: &'a ImportOptions,
+    color_table: ColorTable,
+    hsl_colors: HashMap<u32, u16>,
+    vertex_indices: HashMap<(Vertex, u8), u16>,
+    model: Model,
+}
+
+impl<'a> ModelBuilder<'a> {
+    fn new(options: &'a ImportOptions) -> Self {
+        ModelBuilder {
+            options,
+            color_table: ColorTable::new(DEFAULT_BRIGHTNESS),
+            hsl_colors: HashMap::new(),
+            vertex_indices: HashMap::new(),
+            model: Model {
+                vertices: Vec::new(),
+                vertex_skins: options.vertex_skins.as_ref().map(|_| Vec::new()),
+                faces: Vec::new(),
+                face_colors: Vec::new(),
+                face_render_types: None,
+                face_priorities: FacePriorities::Model(0),
+                face_alphas: None,
+                face_skins: None,
+                texture_triangles: Vec::new(),
+            },
+        }
+    }
+
+    // adds a vertex of an upright mesh, turning it back around like the client points y down
+    fn vertex(&mut self, position: [f64; 3], group: Option<&str>) -> Result<u16, FileSystemError> {
+        let scale = self.options.scale;
+        let vertex = Vertex {
+            x: (position[0] * scale).round() as i32,
+            y: (-position[1] * scale).round() as i32,
+            z: (-position[2] * scale).round() as i32,
+        };
+        let skin = match (&self.options.vertex_skins, group) {
+            (Some(skins), Some(group)) => skins.get(group).copied().unwrap_or(0),
+            _ => 0,
+        };
+        if let Some(index) = self.vertex_indices.get(&(vertex, skin)) {
+            return Ok(*index);
+        }
+        let index = count(self.model.vertices.len(), u16::MAX as usize - 1, "vertices")? as u16;
+        self.model.vertices.push(vertex);
+        if let Some(skins) = &mut self.model.vertex_skins {
+            skins.push(skin);
+        }
+        self.vertex_indices.insert((vertex, skin), index);
+        Ok(index)
+    }
+
+    // adds the triangles of a polygon as a fan around its first vertex
+    fn polygon(&mut self, vertices: &[u16], rgb: u32, opacity: f64) {
+        let hsl = match self.hsl_colors.get(&rgb) {
+            Some(hsl) => *hsl,
+            None => {
+                let hsl = self.color_table.nearest_hsl16(rgb);
+                self.hsl_colors.insert(rgb, hsl);
+                hsl
+            }
+        };
+        let alpha = ((1.0 - opacity.clamp(0.0, 1.0)) * 255.0).round() as u8;
+        for index in 2..vertices.len() {
+            self.model.faces.push(Triangle {
+                a: vertices[0],
+                b: vertices[index - 1],
+                c: vertices[index],
+            });
+            self.model.face_colors.push(hsl);
+            if alpha != 0 && self.model.face_alphas.is_none() {
+                self.model.face_alphas = Some(vec![0; self.model.faces.len() - 1]);
+            }
+            if let Some(alphas) = &mut self.model.face_alphas {
+                alphas.push(alpha);
+            }
+        }
+    }
+}
+
+fn count(count: usize, max: usize, element: &'static str) -> Result<usize, FileSystemError> {
+    if count > max {
+        return Err(FileSystemError::ModelTooLarge { element, count });
+    }
+    Ok(count)
+}
+
+fn check_length<T>(
+    attribute: &'static str,
+    values: &[T],
+    expected: usize,
+) -> Result<(), FileSystemError> {
+    if values.len() != expected {
+        return Err(FileSystemError::ModelAttributeMismatch {
+            attribute,
+            expected,
+            actual: values.len(),
+        });
+    }
+    Ok(())
+}
+
+fn read_bytes(data: &mut &[u8], count: usize) -> Result<Vec<u8>, FileSystemError> {
+    let mut bytes = vec![0; count];
+    data.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+impl FileSystem {
+    /// Reads, decompresses and decodes a model of the model index.
+    pub fn read_model(&self, model_id: u32) -> Result<Model, FileSystemError> {
+        let data = decompress_gzip(self.read(IndexType::MODEL, model_id)?)?;
+        Model::decode(&data)
+    }
+
+    /// Encodes and compresses a model and stores it in the model index through
+    /// `write_versioned`, so the client picks up the new model.
+    pub fn write_model(&mut self, model_id: u32, model: &Model) -> Result<(), FileSystemError> {
+        let data = compress_gzip(&model.encode()?)?;
+        self.write_versioned(IndexType::MODEL, model_id, &data)
+    }
+
+    /// Writes every model of the model index to `directory` as `<id>.obj` and `<id>.mtl`, or as
+    /// `<id>.glb`, returning the paths of the models written.
+    ///
+    /// Entries that are empty, do not hold a model or hold a model without faces are skipped.
+    /// The textures are written next to OBJ models and embedded in GLB models when the cache has
+    /// a textures archive.
+    pub fn export_models(
+        &self,
+        directory: &Path,
+        format: ModelFormat,
+    ) -> Result<Vec<PathBuf>, FileSystemError> {
+        fs::create_dir_all(directory)?;
+        let textures = self.read_archive(ArchiveType::TEXTURES).ok();
+        let embedded_textures = match (format, &textures) {
+            (ModelFormat::Glb, Some(textures)) => textures.textures(),
+            _ => Vec::new(),
+        };
+        if let (ModelFormat::Obj, Some(textures)) = (format, &textures) {
+            textures.export_textures(&directory.join(obj::TEXTURE_DIRECTORY))?;
+        }
+        let mut paths = Vec::new();
+        for model_id in 0..self.file_count(IndexType::MODEL)? as u32 {
+            let model = match self.read_model(model_id) {
+                Ok(model) if !model.faces.is_empty() => model,
+                _ => continue,
+            };
+            let path = directory.join(format!("{}.{}", model_id, format.extension()));
+            match format {
+                ModelFormat::Obj => {
+                    let material_library = format!("{}.mtl", model_id);
+                    let files = model.to_obj(&material_library);
+                    fs::write(&path, files.obj)?;
+                    fs::write(directory.join(material_library), files.mtl)?;
+                }
+                ModelFormat::Glb => fs::write(&path, model.to_glb(&embedded_textures)?)?,
+            }
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::archive::{Archive, ArchiveCompression, ArchiveType};
+    use crate::filesystem::FileSystem;
+    use crate::index::IndexType;
+    use crate::model::{FacePriorities, FaceTexture, Model, ModelFormat, Triangle, Vertex};
+    use crate::testing::TempDir;
+
+    // a textured quad of two faces sharing an edge, with a vertex skin and a face alpha each
+    #[rustfmt::skip]
+    const QUAD: &[u8] = &[
+        // vertex flags
+        3, 1, 6, 1,
+        // face types
+        1, 3,
+        // face skins
+        0, 1,
+        // render types
+        0, 2,
+        // vertex skins
+        0, 0, 1, 1,
+        // alphas
+        0, 128,
+        // face indices: 0, 1, 2 and then 3
+        0x40, 0x41, 0x41, 0x41,
+        // colours
+        0x00, 0x7F, 0x00, 0x05,
+        // texture triangles
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x02,
+        // x, y and z
+        0xBF, 0x80, 0xC0, 0x80, 0x3F,
+        0x30, 0x10,
+        0x20,
+        // footer
+        0x00, 0x04, 0x00, 0x02, 1, 1, 3, 1, 1, 1,
+        0x00, 0x05, 0x00, 0x02, 0x00, 0x01, 0x00, 0x04,
+    ];
+
+    #[test]
+    fn test_model_decoding() {
+        let model = Model::decode(QUAD).unwrap();
+        assert_eq!(
+            model.vertices,
+            vec![
+                Vertex {
+                    x: -128,
+                    y: -16,
+                    z: 0
+                },
+                Vertex { x: 0, y: -16, z: 0 },
+                Vertex {
+                    x: 0,
+                    y: -64,
+                    z: -32
+                },
+                Vertex {
+                    x: -1,
+                    y: -64,
+                    z: -32
+                },
+            ]
+        );
+        assert_eq!(model.vertex_skins, Some(vec![0, 0, 1, 1]));
+        assert_eq!(
+            model.faces,
+            vec![Triangle { a: 0, b: 1, c: 2 }, Triangle { a: 2, b: 1, c: 3 },]
+        );
+        assert_eq!(model.face_colors, vec![127, 5]);
+        assert_eq!(model.face_priorities, FacePriorities::Model(3));
+        assert_eq!(model.face_priority(1), 3);
+        assert_eq!(model.face_alphas, Some(vec![0, 128]));
+        assert_eq!(model.face_skins, Some(vec![0, 1]));
+        assert!(!model.is_flat_shaded(0));
+        assert_eq!(model.face_texture(0), None);
+        assert_eq!(
+            model.face_texture(1),
+            Some(FaceTexture {
+                texture_id: 5,
+                triangle: Triangle { a: 0, b: 1, c: 2 },
+            })
+        );
+        assert!(Model::decode(&QUAD[QUAD.len() - 17..]).is_err());
+    }
+
+    #[test]
+    fn test_model_encoding() {
+        let quad = Model::decode(QUAD).unwrap();
+        assert_eq!(quad.encode().unwrap(), QUAD);
+
+        // a fan of faces that shares vertices in every way the strips allow
+        let model = Model {
+            vertices: (0..6)
+                .map(|i| Vertex {
+                    x: i * 100,
+                    y: -i,
+                    z: 5000 - i * 2000,
+                })
+                .collect(),
+            vertex_skins: None,
+            faces: vec![
+                Triangle { a: 0, b: 1, c: 2 },
+                Triangle { a: 0, b: 2, c: 3 },
+                Triangle { a: 3, b: 2, c: 4 },
+                Triangle { a: 2, b: 3, c: 5 },
+                Triangle { a: 5, b: 4, c: 1 },
+            ],
+            face_colors: vec![1, 2, 3, 4, 5],
+            face_render_types: None,
+            face_priorities: FacePriorities::Faces(vec![0, 1, 2, 3, 4]),
+            face_alphas: None,
+            face_skins: Some(vec![1; 5]),
+            texture_triangles: Vec::new(),
+        };
+        let encoded = model.encode().unwrap();
+        assert_eq!(&encoded[6..11], &[1, 2, 3, 4, 1]);
+        assert_eq!(Model::decode(&encoded).unwrap(), model);
+
+        let mut invalid = model.clone();
+        invalid.face_colors.pop();
+        assert!(invalid.encode().is_err());
+        invalid = model;
+        invalid.vertices[5].x = 20_000;
+        assert!(invalid.encode().is_err());
+    }
+
+    #[test]
+    fn test_model_writing() {
+        let path = TempDir::new("model");
+        let mut fs = FileSystem::create(&path, &[IndexType::ARCHIVE, IndexType::MODEL]).unwrap();
+        let mut versions = Archive::new(ArchiveCompression::Whole);
+        versions.insert("model_version", Vec::new());
+        fs.write_archive(ArchiveType::VERSIONS, &versions).unwrap();
+
+        let quad = Model::decode(QUAD).unwrap();
+        fs.write_model(2, &quad).unwrap();
+        assert_eq!(fs.read_model(2).unwrap(), quad);
+        assert_eq!(fs.versions(IndexType::MODEL).unwrap().get(2), Some(&1));
+        let faceless = Model {
+            faces: Vec::new(),
+            face_colors: Vec::new(),
+            face_render_types: None,
+            face_priorities: FacePriorities::Model(0),
+            face_alphas: None,
+            face_skins: None,
+            texture_triangles: Vec::new(),
+            ..quad.clone()
+        };
+        fs.write_model(1, &faceless).unwrap();
+
+        // model 0 is empty and model 1 has no faces, both are skipped
+        let exported = path.join("exported");
+        let paths = fs.export_models(&exported, ModelFormat::Obj).unwrap();
+        assert_eq!(paths, vec![exported.join("2.obj")]);
+        assert!(exported.join("2.mtl").exists());
+        let paths = fs.export_models(&exported, ModelFormat::Glb).unwrap();
+        assert_eq!(paths, vec![exported.join("2.glb")]);
+    }
+}
@@ -0,0 +1,325 @@
+use crate::errors::FileSystemError;
+use crate::model::{ImportOptions, Model, ModelBuilder, DEFAULT_IMPORT_COLOR};
+
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+
+/// The directory next to an exported OBJ model that its material library takes textures from,
+/// as written by `Archive::export_textures`.
+pub const TEXTURE_DIRECTORY: &str = "textures";
+
+/// An exported model and its material library.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjModel {
+    pub obj: String,
+    pub mtl: String,
+}
+
+impl Model {
+    /// Exports the model as Wavefront OBJ, with a material for every colour, texture and
+    /// transparency of its faces in the material library `material_library`.
+    ///
+    /// Like the other exporters, the model is turned upright and keeps the units of the client,
+    /// where a tile is 128 units wide.
+    pub fn to_obj(&self, material_library: &str) -> ObjModel {
+        let mut files = ObjModel {
+            obj: String::new(),
+            mtl: String::new(),
+        };
+        // writing to a string cannot fail
+        let _ = self.write_obj(material_library, &mut files);
+        files
+    }
+
+    /// Imports a Wavefront OBJ mesh, with the colours and transparency of the materials in the
+    /// material library `mtl`.
+    ///
+    /// Polygons are split into triangles, and every colour becomes the closest colour of the
+    /// client's colour table. Textures are not imported, textured materials keep their colour.
+    pub fn from_obj(
+        obj: &str,
+        mtl: Option<&str>,
+        options: &ImportOptions,
+    ) -> Result<Model, FileSystemError> {
+        let materials = mtl.map(parse_materials).unwrap_or_default();
+        let mut builder = ModelBuilder::new(options);
+        let mut positions: Vec<[f64; 3]> = Vec::new();
+        let mut material = (DEFAULT_IMPORT_COLOR, 1.0);
+        let mut group: Option<&str> = None;
+        for (line_number, line) in obj.lines().enumerate() {
+            let invalid = || {
+                FileSystemError::InvalidModelImport(format!(
+                    "line {} of the OBJ file is invalid: {}",
+                    line_number + 1,
+                    line
+                ))
+            };
+            let mut tokens = line.split_whitespace();
+            match tokens.next() {
+                Some("v") => {
+                    let mut position = [0.0; 3];
+                    for component in position.iter_mut() {
+                        let token = tokens.next().ok_or_else(invalid)?;
+                        *component = token.parse().map_err(|_| invalid())?;
+                    }
+                    positions.push(position);
+                }
+                Some("f") => {
+                    let mut vertices = Vec::new();
+                    for token in tokens {
+                        let index: i64 = token
+                            .split('/')
+                            .next()
+                            .and_then(|index| index.parse().ok())
+                            .ok_or_else(invalid)?;
+                        // indices start at 1, and negative indices count back from the last vertex
+                        let index = if index < 0 {
+                            positions.len() as i64 + index
+                        } else {
+                            index - 1
+                        };
+                        let position = positions.get(index as usize).ok_or_else(invalid)?;
+                        vertices.push(builder.vertex(*position, group)?);
+                    }
+                    if vertices.len() < 3 {
+                        return Err(invalid());
+                    }
+                    builder.polygon(&vertices, material.0, material.1);
+                }
+                Some("usemtl") => {
+                    let name = tokens.next().unwrap_or("");
+                    material = materials
+                        .get(name)
+                        .copied()
+                        .unwrap_or((DEFAULT_IMPORT_COLOR, 1.0));
+                }
+                Some("g") | Some("o") => group = tokens.next(),
+                _ => {}
+            }
+        }
+        Ok(builder.model)
+    }
+
+    fn write_obj(&self, material_library: &str, files: &mut ObjModel) -> fmt::Result {
+        let ObjModel { obj, mtl } = files;
+        let mut materials: Vec<String> = Vec::new();
+        writeln!(obj, "mtllib {}", material_library)?;
+        for vertex in 0..self.vertices.len() {
+            let [x, y, z] = self.upright_position(vertex as u16);
+            writeln!(obj, "v {} {} {}", x, y, z)?;
+        }
+        let mut texture_coordinate_count = 0;
+        let mut current_material = None;
+        for (face, triangle) in self.faces.iter().enumerate() {
+            let opacity = self.face_opacity(face);
+            let texture = self.face_texture(face);
+            let mut material = match texture {
+                Some(texture) => format!("texture_{}", texture.texture_id),
+                None => format!("color_{:06X}", self.face_rgb(face)),
+            };
+            if opacity < 1.0 {
+                write!(material, "_{:.0}", opacity * 255.0)?;
+            }
+            if !materials.contains(&material) {
+                writeln!(mtl, "newmtl {}", material)?;
+                match texture {
+                    Some(texture) => {
+                        writeln!(mtl, "Kd 1 1 1")?;
+                        writeln!(
+                            mtl,
+                            "map_Kd {}/{}.png",
+                            TEXTURE_DIRECTORY, texture.texture_id
+                        )?;
+                    }
+                    None => {
+                        let rgb = self.face_rgb(face);
+                        let channel = |shift: u32| f32::from((rgb >> shift) as u8) / 255.0;
+                        writeln!(
+                            mtl,
+                            "Kd {:.4} {:.4} {:.4}",
+                            channel(16),
+                            channel(8),
+                            channel(0)
+                        )?;
+                    }
+                }
+                writeln!(mtl, "d {:.4}", opacity)?;
+                writeln!(mtl)?;
+                materials.push(material.clone());
+            }
+            if current_material.as_ref() != Some(&material) {
+                writeln!(obj, "usemtl {}", material)?;
+                current_material = Some(material);
+            }
+            let vertices = [triangle.a, triangle.b, triangle.c];
+            match self.face_texture_coordinates(face) {
+                Some(coordinates) => {
+                    // OBJ puts the origin of texture coordinates at the bottom left
+                    for [u, v] in coordinates.iter() {
+                        writeln!(obj, "vt {} {}", u, 1.0 - v)?;
+                    }
+                    writeln!(
+                        obj,
+                        "f {}/{} {}/{} {}/{}",
+                        vertices[0] as usize + 1,
+                        texture_coordinate_count + 1,
+                        vertices[1] as usize + 1,
+                        texture_coordinate_count + 2,
+                        vertices[2] as usize + 1,
+                        texture_coordinate_count + 3,
+                    )?;
+                    texture_coordinate_count += 3;
+                }
+                None => {
+                    writeln!(
+                        obj,
+                        "f {} {} {}",
+                        vertices[0] as usize + 1,
+                        vertices[1] as usize + 1,
+                        vertices[2] as usize + 1,
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// the colour and opacity of every material of a material library
+fn parse_materials(mtl: &str) -> HashMap<String, (u32, f64)> {
+    let mut materials = HashMap::new();
+    let mut current: Option<String> = None;
+    for line in mtl.lines() {
+        let mut tokens = line.split_whitespace();
+        let keyword = tokens.next();
+        let values: Vec<f64> = tokens
+            .clone()
+            .filter_map(|token| token.parse().ok())
+            .collect();
+        if keyword == Some("newmtl") {
+            let name = tokens.next().unwrap_or("").to_string();
+            materials.insert(name.clone(), (DEFAULT_IMPORT_COLOR, 1.0));
+            current = Some(name);
+            continue;
+        }
+        let material = match current.as_ref().and_then(|name| materials.get_mut(name)) {
+            Some(material) => material,
+            None => continue,
+        };
+        match (keyword, values.as_slice()) {
+            (Some("Kd"), [red, green, blue, ..]) => {
+                let channel = |value: f64| (value.clamp(0.0, 1.0) * 255.0).round() as u32;
+                material.0 = (channel(*red) << 16) | (channel(*green) << 8) | channel(*blue);
+            }
+            (Some("d"), [opacity, ..]) => material.1 = *opacity,
+            (Some("Tr"), [transparency, ..]) => material.1 = 1.0 - *transparency,
+            _ => {}
+        }
+    }
+    materials
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::{FacePriorities, ImportOptions, Model, Triangle, Vertex};
+    use std::collections::HashMap;
+
+    fn textured_quad() -> Model {
+        Model {
+            vertices: vec![
+                Vertex { x: 0, y: 0, z: 0 },
+                Vertex { x: 128, y: 0, z: 0 },
+                Vertex {
+                    x: 0,
+                    y: -128,
+                    z: 0,
+                },
+                Vertex {
+                    x: 128,
+                    y: -128,
+                    z: 0,
+                },
+            ],
+            vertex_skins: None,
+            faces: vec![Triangle { a: 0, b: 1, c: 2 }, Triangle { a: 2, b: 1, c: 3 }],
+            face_colors: vec![127, 5],
+            face_render_types: Some(vec![0, 2]),
+            face_priorities: FacePriorities::Model(0),
+            face_alphas: Some(vec![0, 51]),
+            face_skins: None,
+            texture_triangles: vec![Triangle { a: 2, b: 3, c: 0 }],
+        }
+    }
+
+    #[test]
+    fn test_obj_export() {
+        let files = textured_quad().to_obj("quad.mtl");
+        let obj: Vec<&str> = files.obj.lines().collect();
+        assert_eq!(obj[0], "mtllib quad.mtl");
+        // y points up
+        assert_eq!(obj[3], "v 0 128 0");
+        assert_eq!(obj[5], "usemtl color_FEFDFD");
+        assert_eq!(obj[6], "f 1 2 3");
+        assert_eq!(obj[7], "usemtl texture_5_204");
+        // the texture triangle starts at the top left of the texture
+        assert_eq!(&obj[8..11], &["vt 0 1", "vt 1 0", "vt 1 1"]);
+        assert_eq!(obj[11], "f 3/1 2/2 4/3");
+        assert!(files.mtl.contains("map_Kd textures/5.png\nd 0.8000\n"));
+        assert!(files
+            .mtl
+            .contains("newmtl color_FEFDFD\nKd 0.9961 0.9922 0.9922\nd 1.0000\n"));
+    }
+
+    #[test]
+    fn test_obj_import() {
+        let mut quad = textured_quad();
+        quad.face_render_types = None;
+        quad.texture_triangles.clear();
+        let files = quad.to_obj("quad.mtl");
+        let imported =
+            Model::from_obj(&files.obj, Some(&files.mtl), &ImportOptions::default()).unwrap();
+        assert_eq!(imported.vertices, quad.vertices);
+        assert_eq!(imported.faces, quad.faces);
+        assert_eq!(imported.face_alphas, quad.face_alphas);
+        let table = crate::color::ColorTable::new(crate::color::DEFAULT_BRIGHTNESS);
+        for (imported, original) in imported.face_colors.iter().zip(quad.face_colors.iter()) {
+            assert_eq!(table.rgb(*imported), table.rgb(*original));
+        }
+    }
+
+    #[test]
+    fn test_obj_polygons_and_groups() {
+        let obj = "\
+            v 0 0 0\n\
+            v 1 0 0\n\
+            v 1 1 0\n\
+            v 0 1 0\n\
+            g hat\n\
+            f 1 2 3 4\n\
+            g brim\n\
+            f -4/1/1 -2/2/2 -1/3/3\n";
+        let mut skins = HashMap::new();
+        skins.insert("brim".to_string(), 3);
+        let options = ImportOptions {
+            scale: 64.0,
+            vertex_skins: Some(skins),
+        };
+        let model = Model::from_obj(obj, None, &options).unwrap();
+        // the quad is split in two, and the brim does not share the vertices of the hat
+        assert_eq!(model.faces.len(), 3);
+        assert_eq!(model.faces[1], Triangle { a: 0, b: 2, c: 3 });
+        assert_eq!(model.faces[2], Triangle { a: 4, b: 5, c: 6 });
+        assert_eq!(
+            model.vertices[2],
+            Vertex {
+                x: 64,
+                y: -64,
+                z: 0
+            }
+        );
+        assert_eq!(model.vertex_skins, Some(vec![0, 0, 0, 0, 3, 3, 3]));
+        assert!(model.face_alphas.is_none());
+        assert!(Model::from_obj("v 0 0\n", None, &options).is_err());
+        assert!(Model::from_obj("f 1 2 3\n", None, &options).is_err());
+    }
+}
@@ -0,0 +1,785 @@
+use crate::archive::Archive;
+use crate::errors::FileSystemError;
+use crate::str::StrExt;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// The archive entry that holds the palettes and frame sizes of every sprite in an archive.
+pub const INDEX_ENTRY_NAME: &str = "index.dat";
+/// The colour the client skips when drawing a `Sprite`.
+pub const TRANSPARENT: u32 = 0;
+/// The most colours a sprite palette holds besides transparency, as its size is stored in a byte.
+pub const MAX_PALETTE_COLORS: usize = 254;
+// images are imported with pixels below this alpha left transparent
+const ALPHA_THRESHOLD: u8 = 128;
+
+/// The order the pixels of a sprite frame are stored in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelOrder {
+    /// Row by row, from the top left.
+    Horizontal,
+    /// Column by column, from the top left.
+    Vertical,
+}
+
+impl PixelOrder {
+    pub fn id(self) -> u8 {
+        match self {
+            PixelOrder::Horizontal => 0,
+            PixelOrder::Vertical => 1,
+        }
+    }
+}
+
+/// A sprite frame with its colours looked up in the palette.
+///
+/// Every pixel is a `0xRRGGBB` colour, where `TRANSPARENT` is not drawn. Black pixels in the
+/// palette are stored as 1 by the client so they stay visible.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sprite {
+    /// The size of the area every frame of the sprite is drawn in.
+    pub max_width: u16,
+    pub max_height: u16,
+    /// The position of this frame within that area.
+    pub offset_x: u8,
+    pub offset_y: u8,
+    pub width: u16,
+    pub height: u16,
+    pub pixel_order: PixelOrder,
+    /// The pixels row by row, whatever order they were stored in.
+    pub pixels: Vec<u32>,
+}
+
+/// A sprite frame that keeps its pixels as indices into its palette, like the textures and the
+/// title screen runes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexedSprite {
+    pub max_width: u16,
+    pub max_height: u16,
+    pub offset_x: u8,
+    pub offset_y: u8,
+    pub width: u16,
+    pub height: u16,
+    pub pixel_order: PixelOrder,
+    /// The colours as `0xRRGGBB`. Index 0 is transparent.
+    pub palette: Vec<u32>,
+    /// The palette indices row by row, whatever order they were stored in.
+    pub pixels: Vec<u8>,
+}
+
+impl Sprite {
+    /// The pixels as RGBA bytes, row by row, with transparent pixels fully see-through.
+    pub fn to_rgba(&self) -> Vec<u8> {
+        let mut rgba = Vec::with_capacity(self.pixels.len() * 4);
+        for pixel in &self.pixels {
+            rgba.extend_from_slice(&rgba_bytes(*pixel, *pixel != TRANSPARENT));
+        }
+        rgba
+    }
+
+    /// Encodes the frame as an RGBA PNG the size of the area the sprite is drawn in, with the
+    /// frame placed at its offset so `from_png` gives the same frame back.
+    pub fn to_png(&self) -> Result<Vec<u8>, FileSystemError> {
+        let width = (self.max_width as usize).max(self.offset_x as usize + self.width as usize);
+        let height = (self.max_height as usize).max(self.offset_y as usize + self.height as usize);
+        let frame = self.to_rgba();
+        let mut rgba = vec![0; width * height * 4];
+        for (y, row) in frame.chunks(self.width.max(1) as usize * 4).enumerate() {
+            let start = ((self.offset_y as usize + y) * width + self.offset_x as usize) * 4;
+            rgba[start..start + row.len()].copy_from_slice(row);
+        }
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, width as u32, height as u32);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&rgba)?;
+        writer.finish()?;
+        Ok(png)
+    }
+
+    /// Decodes a PNG into a frame the size of the image.
+    ///
+    /// Pixels that are less than half opaque become transparent and black is kept visible as 1.
+    /// The transparent border is trimmed off into the offset of the frame, as far as an offset of
+    /// 255 allows.
+    pub fn from_png(data: &[u8]) -> Result<Self, FileSystemError> {
+        let mut decoder = png::Decoder::new(data);
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder.read_info()?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer)?;
+        let too_large = || FileSystemError::ImageTooLarge {
+            width: info.width,
+            height: info.height,
+        };
+        let max_width = u16::try_from(info.width).map_err(|_| too_large())?;
+        let max_height = u16::try_from(info.height).map_err(|_| too_large())?;
+        let channels = info.color_type.samples();
+        let pixels: Vec<u32> = buffer[..info.buffer_size()]
+            .chunks(info.line_size)
+            .flat_map(|row| row[..info.width as usize * channels].chunks(channels))
+            .map(|pixel| {
+                let (rgb, alpha) = match *pixel {
+                    [gray] => ([gray; 3], 0xFF),
+                    [gray, alpha] => ([gray; 3], alpha),
+                    [red, green, blue] => ([red, green, blue], 0xFF),
+                    [red, green, blue, alpha, ..] => ([red, green, blue], alpha),
+                    _ => ([0; 3], 0),
+                };
+                if alpha < ALPHA_THRESHOLD {
+                    return TRANSPARENT;
+                }
+                let color = u32::from_be_bytes([0, rgb[0], rgb[1], rgb[2]]);
+                color.max(1)
+            })
+            .collect();
+        Ok(Sprite::trimmed(max_width, max_height, &pixels))
+    }
+
+    // cuts the transparent border off an image of max_width by max_height pixels
+    fn trimmed(max_width: u16, max_height: u16, pixels: &[u32]) -> Self {
+        let width = max_width as usize;
+        let visible = |x: usize, y: usize| pixels[x + y * width] != TRANSPARENT;
+        let columns: Vec<usize> = (0..width)
+            .filter(|x| (0..max_height as usize).any(|y| visible(*x, y)))
+            .collect();
+        let rows: Vec<usize> = (0..max_height as usize)
+            .filter(|y| (0..width).any(|x| visible(x, *y)))
+            .collect();
+        let (left, right, top, bottom) =
+            match (columns.first(), columns.last(), rows.first(), rows.last()) {
+                (Some(left), Some(right), Some(top), Some(bottom)) => {
+                    ((*left).min(255), *right + 1, (*top).min(255), *bottom + 1)
+                }
+                _ => (0, 0, 0, 0),
+            };
+        let pixels = (top..bottom)
+            .flat_map(|y| pixels[left + y * width..right + y * width].iter().copied())
+            .collect();
+        Sprite {
+            max_width,
+            max_height,
+            offset_x: left as u8,
+            offset_y: top as u8,
+            width: (right - left) as u16,
+            height: (bottom - top) as u16,
+            pixel_order: PixelOrder::Horizontal,
+            pixels,
+        }
+    }
+}
+
+/// Turns the frames of a sprite into frames that share one palette, like they are stored.
+///
+/// Frames with more than `MAX_PALETTE_COLORS` colours between them are quantised with a median
+/// cut, with every pixel taking the closest colour of the palette.
+pub fn quantize(frames: &[Sprite]) -> Vec<IndexedSprite> {
+    let mut counts: HashMap<u32, u64> = HashMap::new();
+    for pixel in frames.iter().flat_map(|frame| frame.pixels.iter()) {
+        if *pixel != TRANSPARENT {
+            *counts.entry(*pixel).or_insert(0) += 1;
+        }
+    }
+    let mut colors: Vec<(u32, u64)> = counts.into_iter().collect();
+    colors.sort_unstable();
+    let mut palette = vec![TRANSPARENT];
+    if colors.len() <= MAX_PALETTE_COLORS {
+        palette.extend(colors.iter().map(|(color, _)| *color));
+    } else {
+        palette.extend(median_cut(colors, MAX_PALETTE_COLORS));
+    }
+    let mut indices: HashMap<u32, u8> = HashMap::new();
+    indices.insert(TRANSPARENT, 0);
+    frames
+        .iter()
+        .map(|frame| IndexedSprite {
+            max_width: frame.max_width,
+            max_height: frame.max_height,
+            offset_x: frame.offset_x,
+            offset_y: frame.offset_y,
+            width: frame.width,
+            height: frame.height,
+            pixel_order: frame.pixel_order,
+            pixels: frame
+                .pixels
+                .iter()
+                .map(|pixel| {
+                    *indices
+                        .entry(*pixel)
+                        .or_insert_with(|| closest_color(&palette, *pixel))
+                })
+                .collect(),
+            palette: palette.clone(),
+        })
+        .collect()
+}
+
+// splits the box of colours with the widest channel at its median until there are enough boxes
+fn median_cut(colors: Vec<(u32, u64)>, max: usize) -> Vec<u32> {
+    let mut boxes = vec![colors];
+    while boxes.len() < max {
+        let (position, (shift, range)) = boxes
+            .iter()
+            .map(|colors| widest_channel(colors))
+            .enumerate()
+            .max_by_key(|(_, (_, range))| *range)
+            .unwrap_or((0, (0, 0)));
+        if range == 0 {
+            break;
+        }
+        let mut colors = boxes.swap_remove(position);
+        colors.sort_unstable_by_key(|(color, _)| (color >> shift) & 0xFF);
+        let total: u64 = colors.iter().map(|(_, count)| count).sum();
+        let mut seen = 0;
+        let median = colors
+            .iter()
+            .position(|(_, count)| {
+                seen += count;
+                seen * 2 >= total
+            })
+            .unwrap_or(0);
+        let upper = colors.split_off((median + 1).min(colors.len() - 1));
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+    boxes
+        .iter()
+        .map(|colors| {
+            let total: u64 = colors.iter().map(|(_, count)| count).sum();
+            let channel = |shift: u32| {
+                let sum: u64 = colors
+                    .iter()
+                    .map(|(color, count)| u64::from((color >> shift) & 0xFF) * count)
+                    .sum();
+                ((sum + total / 2) / total) as u32
+            };
+            // an average of black would become transparent
+            ((channel(16) << 16) | (channel(8) << 8) | channel(0)).max(1)
+        })
+        .collect()
+}
+
+fn widest_channel(colors: &[(u32, u64)]) -> (u32, u32) {
+    [16, 8, 0]
+        .iter()
+        .map(|shift| {
+            let values = colors.iter().map(|(color, _)| (color >> shift) & 0xFF);
+            let range = values.clone().max().unwrap_or(0) - values.min().unwrap_or(0);
+            (*shift, range)
+        })
+        .max_by_key(|(_, range)| *range)
+        .unwrap_or((0, 0))
+}
+
+fn closest_color(palette: &[u32], color: u32) -> u8 {
+    let distance = |other: u32| {
+        [16, 8, 0]
+            .iter()
+            .map(|shift| {
+                let difference =
+                    ((color >> shift) & 0xFF) as i32 - ((other >> shift) & 0xFF) as i32;
+                difference * difference
+            })
+            .sum::<i32>()
+    };
+    palette
+        .iter()
+        .enumerate()
+        .skip(1)
+        .min_by_key(|(_, other)| distance(**other))
+        .map_or(0, |(index, _)| index as u8)
+}
+
+impl IndexedSprite {
+    /// The colour of every pixel, looked up in the palette. Indices outside of the palette are
+    /// transparent.
+    pub fn to_rgba(&self) -> Vec<u8> {
+        let mut rgba = Vec::with_capacity(self.pixels.len() * 4);
+        for index in &self.pixels {
+            let color = self.palette.get(*index as usize).copied();
+            let visible = *index != 0 && color.is_some();
+            rgba.extend_from_slice(&rgba_bytes(color.unwrap_or(0), visible));
+        }
+        rgba
+    }
+}
+
+impl From<IndexedSprite> for Sprite {
+    fn from(sprite: IndexedSprite) -> Self {
+        let palette: Vec<u32> = sprite
+            .palette
+            .iter()
+            .enumerate()
+            .map(|(index, color)| match (index, *color) {
+                (0, _) => TRANSPARENT,
+                (_, TRANSPARENT) => 1,
+                (_, color) => color,
+            })
+            .collect();
+        Sprite {
+            max_width: sprite.max_width,
+            max_height: sprite.max_height,
+            offset_x: sprite.offset_x,
+            offset_y: sprite.offset_y,
+            width: sprite.width,
+            height: sprite.height,
+            pixel_order: sprite.pixel_order,
+            pixels: sprite
+                .pixels
+                .iter()
+                .map(|index| palette.get(*index as usize).copied().unwrap_or(TRANSPARENT))
+                .collect(),
+        }
+    }
+}
+
+impl Archive {
+    /// Decodes a frame of the sprite stored as `<name>.dat`, e.g. `media.sprite("headicons", 2)`.
+    pub fn sprite(&self, name: &str, frame: u32) -> Result<Sprite, FileSystemError> {
+        self.indexed_sprite(name, frame).map(Sprite::from)
+    }
+
+    /// Decodes a frame of the sprite stored as `<name>.dat`, keeping its palette.
+    pub fn indexed_sprite(&self, name: &str, frame: u32) -> Result<IndexedSprite, FileSystemError> {
+        let (data, mut index) = self.sprite_entries(name)?;
+        let mut header = SpriteHeader::decode(data, &mut index)?;
+        let not_found = || FileSystemError::SpriteFrameNotFound {
+            name: name.to_string(),
+            frame,
+        };
+        for _ in 0..frame {
+            header.skip_frame(&mut index).map_err(|_| not_found())?;
+        }
+        header
+            .decode_frame(data, &mut index)
+            .map_err(|error| match error {
+                FileSystemError::Io(_) => not_found(),
+                error => error,
+            })
+    }
+
+    /// Decodes every frame of the sprite stored as `<name>.dat`.
+    pub fn sprites(&self, name: &str) -> Result<Vec<Sprite>, FileSystemError> {
+        let frames = self.indexed_sprites(name)?;
+        Ok(frames.into_iter().map(Sprite::from).collect())
+    }
+
+    /// Decodes every frame of the sprite stored as `<name>.dat`, keeping their palette.
+    pub fn indexed_sprites(&self, name: &str) -> Result<Vec<IndexedSprite>, FileSystemError> {
+        let (data, index) = self.sprite_entries(name)?;
+        decode_frames(data, index, None)
+    }
+
+    /// Quantises the frames into one palette and stores them as the sprite `<name>`, see
+    /// `write_indexed_sprites`.
+    pub fn write_sprites(&mut self, name: &str, frames: &[Sprite]) -> Result<(), FileSystemError> {
+        self.write_indexed_sprites(name, &quantize(frames))
+    }
+
+    /// Stores the frames as the sprite `<name>`, writing their pixels to `<name>.dat` and their
+    /// header to `index.dat`.
+    ///
+    /// The frames are stored with the palette and the drawing area of the first frame. The header
+    /// replaces the one of an existing sprite of the same name when it has the same size, and is
+    /// appended to `index.dat` otherwise.
+    pub fn write_indexed_sprites(
+        &mut self,
+        name: &str,
+        frames: &[IndexedSprite],
+    ) -> Result<(), FileSystemError> {
+        let first = match frames.first() {
+            Some(first) => first,
+            None => return Err(FileSystemError::EmptySprite(name.to_string())),
+        };
+        if first.palette.len() > MAX_PALETTE_COLORS + 1 {
+            return Err(FileSystemError::SpritePaletteTooLarge(first.palette.len()));
+        }
+        let mut header = Vec::new();
+        header.write_u16::<BigEndian>(first.max_width)?;
+        header.write_u16::<BigEndian>(first.max_height)?;
+        header.write_u8(first.palette.len().max(1) as u8)?;
+        for color in first.palette.iter().skip(1) {
+            header.write_u24::<BigEndian>(*color)?;
+        }
+        // the offset of the header is filled in below
+        let mut data = vec![0; 2];
+        for frame in frames {
+            let size = frame.width as usize * frame.height as usize;
+            if frame.pixels.len() != size {
+                return Err(FileSystemError::SpriteFrameSizeMismatch {
+                    width: frame.width,
+                    height: frame.height,
+                    actual: frame.pixels.len(),
+                });
+            }
+            header.write_u8(frame.offset_x)?;
+            header.write_u8(frame.offset_y)?;
+            header.write_u16::<BigEndian>(frame.width)?;
+            header.write_u16::<BigEndian>(frame.height)?;
+            header.write_u8(frame.pixel_order.id())?;
+            match frame.pixel_order {
+                PixelOrder::Horizontal => data.extend_from_slice(&frame.pixels),
+                PixelOrder::Vertical => {
+                    for x in 0..frame.width as usize {
+                        for y in 0..frame.height as usize {
+                            data.push(frame.pixels[x + y * frame.width as usize]);
+                        }
+                    }
+                }
+            }
+        }
+        let mut index = self
+            .entry_name(INDEX_ENTRY_NAME)
+            .map(|entry| entry.uncompressed_data().to_vec())
+            .unwrap_or_default();
+        let offset = match self.stored_header(name) {
+            Some((offset, length)) if length == header.len() => {
+                index[offset..offset + length].copy_from_slice(&header);
+                offset
+            }
+            _ => {
+                let offset = index.len();
+                index.extend_from_slice(&header);
+                offset
+            }
+        };
+        let offset = u16::try_from(offset).map_err(|_| FileSystemError::SpriteIndexFull(offset))?;
+        data[..2].copy_from_slice(&offset.to_be_bytes());
+        self.insert(INDEX_ENTRY_NAME, index);
+        self.insert(&format!("{}.dat", name), data);
+        Ok(())
+    }
+
+    /// Writes every frame of every sprite in the archive to `directory` as `<name>_<frame>.png`.
+    ///
+    /// Entries are only stored by the hash of their name, so the names of the sprites to look for
+    /// are given. Sprites that are not among them are written under the identifier of their entry
+    /// instead, and entries that do not hold a sprite are skipped. Returns the paths written.
+    pub fn export_sprites(
+        &self,
+        directory: &Path,
+        names: &[&str],
+    ) -> Result<Vec<PathBuf>, FileSystemError> {
+        let index = match self.entry_name(INDEX_ENTRY_NAME) {
+            Some(entry) => entry.uncompressed_data(),
+            None => {
+                return Err(FileSystemError::ArchiveEntryNotFound(
+                    INDEX_ENTRY_NAME.to_string(),
+                ))
+            }
+        };
+        let index_identifier = INDEX_ENTRY_NAME.name_hash();
+        fs::create_dir_all(directory)?;
+        let mut paths = Vec::new();
+        for entry in self.entries() {
+            if entry.identifier() == index_identifier {
+                continue;
+            }
+            let frames = match decode_frames(entry.uncompressed_data(), index, None) {
+                Ok(frames) => frames,
+                Err(_) => continue,
+            };
+            let name = names
+                .iter()
+                .find(|name| format!("{}.dat", name).name_hash() == entry.identifier())
+                .map_or_else(|| entry.identifier().to_string(), |name| name.to_string());
+            for (frame, sprite) in frames.into_iter().enumerate() {
+                let path = directory.join(format!("{}_{}.png", name, frame));
+                fs::write(&path, Sprite::from(sprite).to_png()?)?;
+                paths.push(path);
+            }
+        }
+        Ok(paths)
+    }
+
+    // the offset and length of the header of an existing sprite in index.dat
+    fn stored_header(&self, name: &str) -> Option<(usize, usize)> {
+        let (data, index) = self.sprite_entries(name).ok()?;
+        let offset = u16::from_be_bytes([*data.first()?, *data.get(1)?]) as usize;
+        let mut remaining = index;
+        let mut header = SpriteHeader::decode(data, &mut remaining).ok()?;
+        while header.data_offset < data.len() {
+            header.skip_frame(&mut remaining).ok()?;
+        }
+        Some((offset, index.len().checked_sub(offset)? - remaining.len()))
+    }
+
+    pub(crate) fn sprite_entries(&self, name: &str) -> Result<(&[u8], &[u8]), FileSystemError> {
+        let data_name = format!("{}.dat", name);
+        let data = match self.entry_name(&data_name) {
+            Some(entry) => entry.uncompressed_data(),
+            None => return Err(FileSystemError::ArchiveEntryNotFound(data_name)),
+        };
+        match self.entry_name(INDEX_ENTRY_NAME) {
+            Some(entry) => Ok((data, entry.uncompressed_data())),
+            None => Err(FileSystemError::ArchiveEntryNotFound(
+                INDEX_ENTRY_NAME.to_string(),
+            )),
+        }
+    }
+}
+
+// decodes the given number of frames, or every frame up to the end of the data
+pub(crate) fn decode_frames(
+    data: &[u8],
+    mut index: &[u8],
+    count: Option<usize>,
+) -> Result<Vec<IndexedSprite>, FileSystemError> {
+    let mut header = SpriteHeader::decode(data, &mut index)?;
+    let mut frames = Vec::new();
+    while count.map_or(header.data_offset < data.len(), |count| {
+        frames.len() < count
+    }) {
+        frames.push(header.decode_frame(data, &mut index)?);
+    }
+    Ok(frames)
+}
+
+// the part of index.dat that every frame of a sprite shares
+struct SpriteHeader {
+    max_width: u16,
+    max_height: u16,
+    palette: Vec<u32>,
+    // where the pixels of the next frame start in the data entry
+    data_offset: usize,
+}
+
+impl SpriteHeader {
+    fn decode(mut data: &[u8], index: &mut &[u8]) -> Result<Self, FileSystemError> {
+        let index_offset = data.read_u16::<BigEndian>()? as usize;
+        *index = index.get(index_offset..).unwrap_or(&[]);
+        let max_width = index.read_u16::<BigEndian>()?;
+        let max_height = index.read_u16::<BigEndian>()?;
+        let palette_size = index.read_u8()? as usize;
+        // the first colour is transparent and not stored
+        let mut palette = vec![TRANSPARENT; palette_size.max(1)];
+        for color in palette.iter_mut().skip(1) {
+            *color = index.read_u24::<BigEndian>()?;
+        }
+        Ok(SpriteHeader {
+            max_width,
+            max_height,
+            palette,
+            data_offset: 2,
+        })
+    }
+
+    fn skip_frame(&mut self, index: &mut &[u8]) -> Result<(), FileSystemError> {
+        let mut frame = [0; 7];
+        index.read_exact(&mut frame)?;
+        let width = u16::from_be_bytes([frame[2], frame[3]]) as usize;
+        let height = u16::from_be_bytes([frame[4], frame[5]]) as usize;
+        self.data_offset += width * height;
+        Ok(())
+    }
+
+    fn decode_frame(
+        &mut self,
+        data: &[u8],
+        index: &mut &[u8],
+    ) -> Result<IndexedSprite, FileSystemError> {
+        let offset_x = index.read_u8()?;
+        let offset_y = index.read_u8()?;
+        let width = index.read_u16::<BigEndian>()?;
+        let height = index.read_u16::<BigEndian>()?;
+        let pixel_order = match index.read_u8()? {
+            0 => PixelOrder::Horizontal,
+            1 => PixelOrder::Vertical,
+            order => return Err(FileSystemError::InvalidPixelOrder(order)),
+        };
+        let size = width as usize * height as usize;
+        let stored = data
+            .get(self.data_offset..self.data_offset + size)
+            .ok_or_else(|| {
+                FileSystemError::Io(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "sprite frame lies past the end of its data",
+                ))
+            })?;
+        self.data_offset += size;
+        let pixels = match pixel_order {
+            PixelOrder::Horizontal => stored.to_vec(),
+            PixelOrder::Vertical => {
+                let mut pixels = vec![0; size];
+                for (position, index) in stored.iter().enumerate() {
+                    let x = position / height as usize;
+                    let y = position % height as usize;
+                    pixels[x + y * width as usize] = *index;
+                }
+                pixels
+            }
+        };
+        Ok(IndexedSprite {
+            max_width: self.max_width,
+            max_height: self.max_height,
+            offset_x,
+            offset_y,
+            width,
+            height,
+            pixel_order,
+            palette: self.palette.clone(),
+            pixels,
+        })
+    }
+}
+
+fn rgba_bytes(color: u32, visible: bool) -> [u8; 4] {
+    let alpha = if visible { 0xFF } else { 0 };
+    [(color >> 16) as u8, (color >> 8) as u8, color as u8, alpha]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::archive::{Archive, ArchiveCompression};
+    use crate::sprite::{self, PixelOrder, Sprite, INDEX_ENTRY_NAME, MAX_PALETTE_COLORS};
+    use crate::testing::TempDir;
+
+    // a sprite with a 2x2 frame stored row by row and a 3x1 frame stored column by column
+    fn media() -> Archive {
+        #[rustfmt::skip]
+        let index = vec![
+            0xAA, 0xBB,
+            0x00, 0x04, 0x00, 0x03,
+            3, 0x00, 0x00, 0x00, 0xFF, 0x00, 0x00,
+            0, 1, 0x00, 0x02, 0x00, 0x02, 0,
+            2, 0, 0x00, 0x03, 0x00, 0x01, 1,
+        ];
+        #[rustfmt::skip]
+        let data = vec![
+            0x00, 0x02,
+            0, 1, 2, 0,
+            2, 2, 1,
+        ];
+        let mut archive = Archive::new(ArchiveCompression::Whole);
+        archive.insert(INDEX_ENTRY_NAME, index);
+        archive.insert("headicons.dat", data);
+        archive
+    }
+
+    #[test]
+    fn test_sprite_decoding() {
+        let media = media();
+        let sprite = media.sprite("headicons", 0).unwrap();
+        assert_eq!((sprite.max_width, sprite.max_height), (4, 3));
+        assert_eq!((sprite.offset_x, sprite.offset_y), (0, 1));
+        assert_eq!((sprite.width, sprite.height), (2, 2));
+        // black is kept visible as 1, index 0 is transparent
+        assert_eq!(sprite.pixels, vec![0, 1, 0xFF0000, 0]);
+        assert_eq!(
+            sprite.to_rgba(),
+            vec![0, 0, 0, 0, 0, 0, 1, 255, 255, 0, 0, 255, 0, 0, 0, 0]
+        );
+
+        let indexed = media.indexed_sprite("headicons", 1).unwrap();
+        assert_eq!(indexed.pixel_order, PixelOrder::Vertical);
+        assert_eq!((indexed.width, indexed.height), (3, 1));
+        assert_eq!(indexed.palette, vec![0, 0, 0xFF0000]);
+        assert_eq!(indexed.pixels, vec![2, 2, 1]);
+
+        assert_eq!(media.sprites("headicons").unwrap().len(), 2);
+        assert!(media.sprite("headicons", 2).is_err());
+        assert!(media.sprite("hitmarks", 0).is_err());
+    }
+
+    #[test]
+    fn test_vertical_pixel_order() {
+        #[rustfmt::skip]
+        let index = vec![
+            0x00, 0x03, 0x00, 0x02,
+            5, 0x00, 0x00, 0x01, 0x00, 0x00, 0x02, 0x00, 0x00, 0x03, 0x00, 0x00, 0x04,
+            0, 0, 0x00, 0x03, 0x00, 0x02, 1,
+        ];
+        let data = vec![0x00, 0x00, 1, 2, 3, 4, 1, 2];
+        let mut archive = Archive::new(ArchiveCompression::Whole);
+        archive.insert(INDEX_ENTRY_NAME, index);
+        archive.insert("mapdots.dat", data);
+        let sprite = archive.indexed_sprite("mapdots", 0).unwrap();
+        // columns (1, 2), (3, 4) and (1, 2) become rows
+        assert_eq!(sprite.pixels, vec![1, 3, 1, 2, 4, 2]);
+    }
+
+    #[test]
+    fn test_sprite_writing() {
+        let mut media = media();
+        let frames = media.sprites("headicons").unwrap();
+        let index_length = media
+            .entry_name(INDEX_ENTRY_NAME)
+            .unwrap()
+            .uncompressed_size();
+        // the same sprite written back keeps its place in the index
+        media.write_sprites("headicons", &frames).unwrap();
+        assert_eq!(media.sprites("headicons").unwrap(), frames);
+        let index = media.entry_name(INDEX_ENTRY_NAME).unwrap();
+        assert_eq!(index.uncompressed_size(), index_length);
+
+        media.write_sprites("hitmarks", &frames[..1]).unwrap();
+        let data = media
+            .entry_name("hitmarks.dat")
+            .unwrap()
+            .uncompressed_data();
+        assert_eq!(data[..2], index_length.to_be_bytes()[2..]);
+        assert_eq!(media.sprites("hitmarks").unwrap(), frames[..1]);
+        assert!(media.write_sprites("hitmarks", &[]).is_err());
+    }
+
+    #[test]
+    fn test_sprite_quantization() {
+        // a gradient of 1024 reds and greens
+        let pixels: Vec<u32> = (0..1024)
+            .map(|color| (color / 32 * 8) << 16 | (color % 32 * 8) << 8 | 0x40)
+            .collect();
+        let frame = Sprite {
+            max_width: 32,
+            max_height: 32,
+            offset_x: 0,
+            offset_y: 0,
+            width: 32,
+            height: 32,
+            pixel_order: PixelOrder::Vertical,
+            pixels,
+        };
+        let quantized = sprite::quantize(std::slice::from_ref(&frame));
+        assert_eq!(quantized[0].palette.len(), MAX_PALETTE_COLORS + 1);
+        // every pixel stays visible and close to its colour
+        let decoded = Sprite::from(quantized[0].clone());
+        for (original, color) in frame.pixels.iter().zip(decoded.pixels.iter()) {
+            assert_ne!(*color, 0);
+            for shift in [16, 8].iter() {
+                let difference =
+                    (*original >> shift & 0xFF) as i32 - (*color >> shift & 0xFF) as i32;
+                assert!(difference.abs() <= 16);
+            }
+        }
+
+        let mut archive = Archive::new(ArchiveCompression::Whole);
+        archive.write_sprites("mapback", &[frame]).unwrap();
+        assert_eq!(archive.sprites("mapback").unwrap(), vec![decoded]);
+    }
+
+    #[test]
+    fn test_sprite_png_round_trip() {
+        let media = media();
+        let sprite = media.sprite("headicons", 0).unwrap();
+        let png = sprite.to_png().unwrap();
+        // the transparent column on the right is trimmed
+        let imported = Sprite::from_png(&png).unwrap();
+        assert_eq!((imported.max_width, imported.max_height), (4, 3));
+        assert_eq!((imported.offset_x, imported.offset_y), (0, 1));
+        assert_eq!((imported.width, imported.height), (2, 2));
+        assert_eq!(imported.pixels, sprite.pixels);
+
+        let directory = TempDir::new("sprites");
+        let paths = media.export_sprites(&directory, &[]).unwrap();
+        let identifier = crate::str::StrExt::name_hash("headicons.dat");
+        assert_eq!(paths[1], directory.join(format!("{}_1.png", identifier)));
+        let paths = media.export_sprites(&directory, &["headicons"]).unwrap();
+        assert_eq!(paths[0], directory.join("headicons_0.png"));
+        assert_eq!(std::fs::read(&paths[0]).unwrap(), png);
+    }
+}
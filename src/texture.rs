@@ -0,0 +1,214 @@
+use crate::archive::{Archive, ArchiveType};
+use crate::color::apply_brightness;
+use crate::errors::FileSystemError;
+use crate::filesystem::FileSystem;
+use crate::sprite::{IndexedSprite, PixelOrder, Sprite};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The number of textures the client tries to load, stored as `0.dat` to `49.dat`.
+pub const TEXTURE_COUNT: u32 = 50;
+// the client brightens the average colour of a texture once more with this exponent
+const AVERAGE_COLOR_EXPONENT: f64 = 1.4;
+
+/// A texture of the textures archive, expanded to its full size like the client does when it
+/// loads it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Texture {
+    pub id: u32,
+    /// Mostly 128, with a few textures of 64 pixels.
+    pub width: u16,
+    pub height: u16,
+    /// The colours as `0xRRGGBB`. Index 0 is transparent.
+    pub palette: Vec<u32>,
+    /// The palette indices row by row.
+    pub pixels: Vec<u8>,
+}
+
+impl Texture {
+    /// Places a sprite frame at its offset within its drawing area.
+    pub fn from_sprite(id: u32, sprite: &IndexedSprite) -> Self {
+        let width = sprite.max_width.max(sprite.offset_x as u16 + sprite.width);
+        let height = sprite
+            .max_height
+            .max(sprite.offset_y as u16 + sprite.height);
+        let mut pixels = vec![0; width as usize * height as usize];
+        for (y, row) in sprite
+            .pixels
+            .chunks(sprite.width.max(1) as usize)
+            .enumerate()
+        {
+            let start = (sprite.offset_y as usize + y) * width as usize + sprite.offset_x as usize;
+            pixels[start..start + row.len()].copy_from_slice(row);
+        }
+        Texture {
+            id,
+            width,
+            height,
+            palette: sprite.palette.clone(),
+            pixels,
+        }
+    }
+
+    /// Whether the client skips some pixels of the texture when drawing it, which it does for
+    /// every pixel of palette index 0.
+    pub fn is_transparent(&self) -> bool {
+        self.pixels.contains(&0)
+    }
+
+    /// The palette the client draws with at a brightness like `color::DEFAULT_BRIGHTNESS`.
+    /// Colours that become too dark to tell apart from transparency are kept visible as 1.
+    pub fn palette_with_brightness(&self, brightness: f64) -> Vec<u32> {
+        self.palette
+            .iter()
+            .enumerate()
+            .map(|(index, color)| {
+                let color = apply_brightness(*color, brightness);
+                if index != 0 && color & 0xF8F8FF == 0 {
+                    1
+                } else {
+                    color
+                }
+            })
+            .collect()
+    }
+
+    /// The colour the client draws the texture with from afar and on the minimap.
+    ///
+    /// Like the client this is the average of the palette at the given brightness, transparent
+    /// index included, brightened once more.
+    pub fn average_color(&self, brightness: f64) -> u32 {
+        let palette = self.palette_with_brightness(brightness);
+        let count = palette.len().max(1) as u32;
+        let channel = |shift: u32| {
+            palette
+                .iter()
+                .map(|color| (color >> shift) & 0xFF)
+                .sum::<u32>()
+                / count
+        };
+        let average = (channel(16) << 16) | (channel(8) << 8) | channel(0);
+        apply_brightness(average, AVERAGE_COLOR_EXPONENT).max(1)
+    }
+
+    /// The texture as an indexed sprite of its full size.
+    pub fn to_sprite(&self) -> IndexedSprite {
+        IndexedSprite {
+            max_width: self.width,
+            max_height: self.height,
+            offset_x: 0,
+            offset_y: 0,
+            width: self.width,
+            height: self.height,
+            pixel_order: PixelOrder::Horizontal,
+            palette: self.palette.clone(),
+            pixels: self.pixels.clone(),
+        }
+    }
+
+    /// The pixels as RGBA bytes, row by row, with palette index 0 fully see-through.
+    pub fn to_rgba(&self) -> Vec<u8> {
+        self.to_sprite().to_rgba()
+    }
+
+    pub fn to_png(&self) -> Result<Vec<u8>, FileSystemError> {
+        Sprite::from(self.to_sprite()).to_png()
+    }
+}
+
+impl Archive {
+    /// Decodes the texture stored as `<id>.dat`.
+    pub fn texture(&self, id: u32) -> Result<Texture, FileSystemError> {
+        let sprite = self.indexed_sprite(&id.to_string(), 0)?;
+        Ok(Texture::from_sprite(id, &sprite))
+    }
+
+    /// Decodes every texture the client loads. Like the client, textures that are missing or do
+    /// not decode are left out.
+    pub fn textures(&self) -> Vec<Texture> {
+        (0..TEXTURE_COUNT)
+            .filter_map(|id| self.texture(id).ok())
+            .collect()
+    }
+
+    /// Writes every texture to `directory` as `<id>.png`, returning the paths written.
+    pub fn export_textures(&self, directory: &Path) -> Result<Vec<PathBuf>, FileSystemError> {
+        fs::create_dir_all(directory)?;
+        let mut paths = Vec::new();
+        for texture in self.textures() {
+            let path = directory.join(format!("{}.png", texture.id));
+            fs::write(&path, texture.to_png()?)?;
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+}
+
+impl FileSystem {
+    /// Reads the textures archive and decodes every texture in it.
+    pub fn read_textures(&self) -> Result<Vec<Texture>, FileSystemError> {
+        Ok(self.read_archive(ArchiveType::TEXTURES)?.textures())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::archive::{Archive, ArchiveCompression};
+    use crate::color::DEFAULT_BRIGHTNESS;
+    use crate::sprite::{IndexedSprite, PixelOrder, INDEX_ENTRY_NAME};
+
+    fn textures() -> Archive {
+        let frame = IndexedSprite {
+            max_width: 4,
+            max_height: 4,
+            offset_x: 1,
+            offset_y: 2,
+            width: 3,
+            height: 2,
+            pixel_order: PixelOrder::Horizontal,
+            palette: vec![0, 0x808080, 0x000000],
+            pixels: vec![1, 2, 1, 2, 1, 2],
+        };
+        let mut full = frame.clone();
+        full.offset_x = 0;
+        full.offset_y = 0;
+        full.width = 4;
+        full.height = 4;
+        full.pixels = vec![1; 16];
+        let mut archive = Archive::new(ArchiveCompression::Whole);
+        archive.insert(INDEX_ENTRY_NAME, Vec::new());
+        archive.write_indexed_sprites("0", &[frame]).unwrap();
+        archive.write_indexed_sprites("2", &[full]).unwrap();
+        archive
+    }
+
+    #[test]
+    fn test_texture_decoding() {
+        let textures = textures();
+        let texture = textures.texture(0).unwrap();
+        assert_eq!((texture.width, texture.height), (4, 4));
+        // the frame is placed at its offset
+        assert_eq!(&texture.pixels[8..], &[0, 1, 2, 1, 0, 2, 1, 2]);
+        assert!(texture.is_transparent());
+        assert!(!textures.texture(2).unwrap().is_transparent());
+        assert!(textures.texture(1).is_err());
+        let ids: Vec<u32> = textures
+            .textures()
+            .iter()
+            .map(|texture| texture.id)
+            .collect();
+        assert_eq!(ids, vec![0, 2]);
+    }
+
+    #[test]
+    fn test_texture_colours() {
+        let texture = textures().texture(0).unwrap();
+        // black is kept apart from transparency
+        assert_eq!(
+            texture.palette_with_brightness(DEFAULT_BRIGHTNESS),
+            vec![0, 0x939393, 1]
+        );
+        assert_eq!(texture.average_color(DEFAULT_BRIGHTNESS), 0x191919);
+    }
+}
@@ -0,0 +1,105 @@
+use crate::archive::Archive;
+use crate::errors::FileSystemError;
+use crate::font::BitmapFont;
+use crate::sprite::IndexedSprite;
+use crate::widget::FONT_NAMES;
+
+/// The archive entry holding the title screen background as a JPEG.
+pub const BACKGROUND_ENTRY_NAME: &str = "title.dat";
+pub const LOGO_SPRITE_NAME: &str = "logo";
+pub const TITLE_BOX_SPRITE_NAME: &str = "titlebox";
+pub const TITLE_BUTTON_SPRITE_NAME: &str = "titlebutton";
+/// The sprite holding the frames of the runes that burn on both sides of the logo.
+pub const RUNES_SPRITE_NAME: &str = "runes";
+
+/// Everything the client loads from the title archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TitleScreen {
+    /// The background, a JPEG as it is stored.
+    pub background: Vec<u8>,
+    pub logo: IndexedSprite,
+    /// The box the login form is drawn on.
+    pub title_box: IndexedSprite,
+    pub title_button: IndexedSprite,
+    pub runes: Vec<IndexedSprite>,
+    /// The fonts in the order of `FONT_NAMES`, so a widget's font indexes into them.
+    pub fonts: Vec<BitmapFont>,
+}
+
+impl TitleScreen {
+    pub fn decode(archive: &Archive) -> Result<Self, FileSystemError> {
+        let background = match archive.entry_name(BACKGROUND_ENTRY_NAME) {
+            Some(entry) => entry.uncompressed_data().to_vec(),
+            None => {
+                return Err(FileSystemError::ArchiveEntryNotFound(
+                    BACKGROUND_ENTRY_NAME.to_string(),
+                ))
+            }
+        };
+        let fonts = FONT_NAMES
+            .iter()
+            .map(|name| archive.font(name))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(TitleScreen {
+            background,
+            logo: archive.indexed_sprite(LOGO_SPRITE_NAME, 0)?,
+            title_box: archive.indexed_sprite(TITLE_BOX_SPRITE_NAME, 0)?,
+            title_button: archive.indexed_sprite(TITLE_BUTTON_SPRITE_NAME, 0)?,
+            runes: archive.indexed_sprites(RUNES_SPRITE_NAME)?,
+            fonts,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::archive::{Archive, ArchiveCompression};
+    use crate::font::GLYPH_COUNT;
+    use crate::sprite::{IndexedSprite, PixelOrder, INDEX_ENTRY_NAME};
+    use crate::title::{TitleScreen, BACKGROUND_ENTRY_NAME};
+    use crate::widget::FONT_NAMES;
+
+    fn frame(width: u16, height: u16) -> IndexedSprite {
+        IndexedSprite {
+            max_width: width,
+            max_height: height,
+            offset_x: 0,
+            offset_y: 0,
+            width,
+            height,
+            pixel_order: PixelOrder::Horizontal,
+            palette: vec![0, 0x554433],
+            pixels: vec![1; width as usize * height as usize],
+        }
+    }
+
+    #[test]
+    fn test_title_screen_decoding() {
+        let mut title = Archive::new(ArchiveCompression::Whole);
+        title.insert(BACKGROUND_ENTRY_NAME, vec![0xFF, 0xD8, 0xFF, 0xD9]);
+        title.insert(INDEX_ENTRY_NAME, Vec::new());
+        title.write_indexed_sprites("logo", &[frame(4, 2)]).unwrap();
+        title
+            .write_indexed_sprites("titlebox", &[frame(3, 3)])
+            .unwrap();
+        title
+            .write_indexed_sprites("titlebutton", &[frame(2, 1)])
+            .unwrap();
+        assert!(TitleScreen::decode(&title).is_err());
+        title
+            .write_indexed_sprites("runes", &vec![frame(1, 1); 12])
+            .unwrap();
+        for name in FONT_NAMES.iter() {
+            title
+                .write_indexed_sprites(name, &vec![frame(1, 2); GLYPH_COUNT])
+                .unwrap();
+        }
+        let screen = TitleScreen::decode(&title).unwrap();
+        assert_eq!(screen.background, vec![0xFF, 0xD8, 0xFF, 0xD9]);
+        assert_eq!(screen.logo, frame(4, 2));
+        assert_eq!(screen.title_box.width, 3);
+        assert_eq!(screen.runes.len(), 12);
+        assert_eq!(screen.fonts.len(), FONT_NAMES.len());
+        assert_eq!(screen.fonts[1].height, 2);
+    }
+}
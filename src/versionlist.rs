@@ -1,6 +1,8 @@
-use byteorder::{BigEndian, ReadBytesExt};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 
+use crate::archive::Archive;
 use crate::errors::FileSystemError;
+use flate2::Crc;
 use std::io::Read;
 
 pub const DEFAULT_VERSION_ENTRY_NAMES: &[&str; 4] = &[
@@ -10,6 +12,28 @@ pub const DEFAULT_VERSION_ENTRY_NAMES: &[&str; 4] = &[
     "map_version",
 ];
 pub const DEFAULT_CRC_ENTRY_NAMES: &[&str; 4] = &["model_crc", "anim_crc", "midi_crc", "map_crc"];
+pub const MODEL_INDEX_ENTRY_NAME: &str = "model_index";
+pub const ANIM_INDEX_ENTRY_NAME: &str = "anim_index";
+pub const MIDI_INDEX_ENTRY_NAME: &str = "midi_index";
+pub const MAP_INDEX_ENTRY_NAME: &str = "map_index";
+/// The size of the version that the files of the on-demand indexes end with.
+pub const VERSION_TRAILER_SIZE: usize = 2;
+
+// the on-demand indexes (models, animations, midis and maps) are the ones with version lists
+pub(crate) fn entry_names(index_id: u8) -> Option<(&'static str, &'static str)> {
+    let list_id = (index_id as usize).checked_sub(1)?;
+    Some((
+        DEFAULT_VERSION_ENTRY_NAMES.get(list_id)?,
+        DEFAULT_CRC_ENTRY_NAMES.get(list_id)?,
+    ))
+}
+
+// an entry missing from the versions archive is treated as an empty list
+fn entry_data<'a>(archive: &'a Archive, name: &str) -> &'a [u8] {
+    archive
+        .entry_name(name)
+        .map_or(&[], |entry| entry.uncompressed_data())
+}
 
 #[derive(Debug)]
 pub struct VersionList {
@@ -19,20 +43,51 @@ pub struct VersionList {
 impl VersionList {
     pub fn decode<R: Read>(reader: &mut R, len: usize) -> Result<Self, FileSystemError> {
         let count = len / 2;
-        let mut versions: Vec<u32> = vec![0; count];
-        for i in 0..count {
-            versions[i] = reader.read_u16::<BigEndian>()? as u32;
+        let mut versions: Vec<u32> = Vec::with_capacity(count);
+        for _ in 0..count {
+            versions.push(reader.read_u16::<BigEndian>()? as u32);
         }
         Ok(VersionList { versions })
     }
 
+    pub(crate) fn from_archive(archive: &Archive, name: &str) -> Result<Self, FileSystemError> {
+        let mut data = entry_data(archive, name);
+        let len = data.len();
+        VersionList::decode(&mut data, len)
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, FileSystemError> {
+        let mut buffer = Vec::with_capacity(self.versions.len() * 2);
+        for version in &self.versions {
+            buffer.write_u16::<BigEndian>(*version as u16)?;
+        }
+        Ok(buffer)
+    }
+
     pub fn get(&self, file_id: u32) -> Option<&u32> {
         self.versions.get(file_id as usize)
     }
 
+    /// Sets the version of a file, growing the list with zeroed versions if needed.
+    pub fn set(&mut self, file_id: u32, version: u32) {
+        let file_id = file_id as usize;
+        if file_id >= self.versions.len() {
+            self.versions.resize(file_id + 1, 0);
+        }
+        self.versions[file_id] = version & 0xFFFF;
+    }
+
     pub fn len(&self) -> usize {
         self.versions.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.versions.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &u32> {
+        self.versions.iter()
+    }
 }
 
 #[derive(Debug)]
@@ -43,18 +98,285 @@ pub struct CrcList {
 impl CrcList {
     pub fn decode<R: Read>(reader: &mut R, len: usize) -> Result<Self, FileSystemError> {
         let count = len / 4;
-        let mut crcs: Vec<u32> = vec![0; count];
-        for i in 0..count {
-            crcs[i] = reader.read_u32::<BigEndian>()?;
+        let mut crcs: Vec<u32> = Vec::with_capacity(count);
+        for _ in 0..count {
+            crcs.push(reader.read_u32::<BigEndian>()?);
         }
         Ok(CrcList { crcs })
     }
 
+    pub(crate) fn from_archive(archive: &Archive, name: &str) -> Result<Self, FileSystemError> {
+        let mut data = entry_data(archive, name);
+        let len = data.len();
+        CrcList::decode(&mut data, len)
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, FileSystemError> {
+        let mut buffer = Vec::with_capacity(self.crcs.len() * 4);
+        for crc in &self.crcs {
+            buffer.write_u32::<BigEndian>(*crc)?;
+        }
+        Ok(buffer)
+    }
+
+    /// Computes the CRC-32 checksum of a file's data the way the client does.
+    pub fn checksum(data: &[u8]) -> u32 {
+        let mut crc = Crc::new();
+        crc.update(data);
+        crc.sum()
+    }
+
     pub fn get(&self, file_id: u32) -> Option<&u32> {
         self.crcs.get(file_id as usize)
     }
 
+    /// Sets the CRC of a file, growing the list with zeroed CRCs if needed.
+    pub fn set(&mut self, file_id: u32, crc: u32) {
+        let file_id = file_id as usize;
+        if file_id >= self.crcs.len() {
+            self.crcs.resize(file_id + 1, 0);
+        }
+        self.crcs[file_id] = crc;
+    }
+
     pub fn len(&self) -> usize {
         self.crcs.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.crcs.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &u32> {
+        self.crcs.iter()
+    }
+}
+
+/// The flags of every model file, taken from `model_index`.
+#[derive(Debug)]
+pub struct ModelIndex {
+    flags: Vec<u8>,
+}
+
+impl ModelIndex {
+    pub fn decode<R: Read>(reader: &mut R, len: usize) -> Result<Self, FileSystemError> {
+        let mut flags: Vec<u8> = vec![0; len];
+        reader.read_exact(&mut flags)?;
+        Ok(ModelIndex { flags })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        self.flags.clone()
+    }
+
+    /// The flags of a model. Models past the end of the table have no flags set.
+    pub fn flags(&self, model_id: u32) -> u8 {
+        self.flags.get(model_id as usize).copied().unwrap_or(0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.flags.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.flags.is_empty()
+    }
+}
+
+/// The value `anim_index` holds for every animation file.
+#[derive(Debug)]
+pub struct AnimIndex {
+    values: Vec<u16>,
+}
+
+impl AnimIndex {
+    pub fn decode<R: Read>(reader: &mut R, len: usize) -> Result<Self, FileSystemError> {
+        let count = len / 2;
+        let mut values: Vec<u16> = Vec::with_capacity(count);
+        for _ in 0..count {
+            values.push(reader.read_u16::<BigEndian>()?);
+        }
+        Ok(AnimIndex { values })
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, FileSystemError> {
+        let mut buffer = Vec::with_capacity(self.values.len() * 2);
+        for value in &self.values {
+            buffer.write_u16::<BigEndian>(*value)?;
+        }
+        Ok(buffer)
+    }
+
+    pub fn get(&self, file_id: u32) -> Option<&u16> {
+        self.values.get(file_id as usize)
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+/// Which MIDI files the client preloads, taken from `midi_index`.
+#[derive(Debug)]
+pub struct MidiIndex {
+    flags: Vec<u8>,
+}
+
+impl MidiIndex {
+    pub fn decode<R: Read>(reader: &mut R, len: usize) -> Result<Self, FileSystemError> {
+        let mut flags: Vec<u8> = vec![0; len];
+        reader.read_exact(&mut flags)?;
+        Ok(MidiIndex { flags })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        self.flags.clone()
+    }
+
+    pub fn is_preloaded(&self, midi_id: u32) -> bool {
+        self.flags.get(midi_id as usize) == Some(&1)
+    }
+
+    pub fn len(&self) -> usize {
+        self.flags.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.flags.is_empty()
+    }
+}
+
+/// The map files of every region, taken from `map_index`.
+#[derive(Debug)]
+pub struct MapIndex {
+    entries: Vec<MapIndexEntry>,
+}
+
+impl MapIndex {
+    pub const ENTRY_SIZE: usize = 7;
+
+    pub fn decode<R: Read>(reader: &mut R, len: usize) -> Result<Self, FileSystemError> {
+        let count = len / MapIndex::ENTRY_SIZE;
+        let mut entries: Vec<MapIndexEntry> = Vec::with_capacity(count);
+        for _ in 0..count {
+            entries.push(MapIndexEntry {
+                region_id: reader.read_u16::<BigEndian>()?,
+                landscape_file_id: reader.read_u16::<BigEndian>()?,
+                object_file_id: reader.read_u16::<BigEndian>()?,
+                members: reader.read_u8()? == 1,
+            });
+        }
+        Ok(MapIndex { entries })
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, FileSystemError> {
+        let mut buffer = Vec::with_capacity(self.entries.len() * MapIndex::ENTRY_SIZE);
+        for entry in &self.entries {
+            buffer.write_u16::<BigEndian>(entry.region_id)?;
+            buffer.write_u16::<BigEndian>(entry.landscape_file_id)?;
+            buffer.write_u16::<BigEndian>(entry.object_file_id)?;
+            buffer.write_u8(entry.members as u8)?;
+        }
+        Ok(buffer)
+    }
+
+    /// Finds the map files of the region with the given region coordinates.
+    pub fn region(&self, region_x: u8, region_y: u8) -> Option<&MapIndexEntry> {
+        let region_id = ((region_x as u16) << 8) | region_y as u16;
+        self.entries
+            .iter()
+            .find(|entry| entry.region_id == region_id)
+    }
+
+    pub fn entries(&self) -> &[MapIndexEntry] {
+        &self.entries
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MapIndexEntry {
+    region_id: u16,
+    landscape_file_id: u16,
+    object_file_id: u16,
+    members: bool,
+}
+
+impl MapIndexEntry {
+    /// The region's coordinates packed as `(region_x << 8) | region_y`.
+    pub fn region_id(&self) -> u16 {
+        self.region_id
+    }
+
+    pub fn region_x(&self) -> u8 {
+        (self.region_id >> 8) as u8
+    }
+
+    pub fn region_y(&self) -> u8 {
+        self.region_id as u8
+    }
+
+    /// The id of the region's landscape file in `IndexType::MAP`.
+    pub fn landscape_file_id(&self) -> u16 {
+        self.landscape_file_id
+    }
+
+    /// The id of the region's object map file in `IndexType::MAP`.
+    pub fn object_file_id(&self) -> u16 {
+        self.object_file_id
+    }
+
+    /// Whether the region is only accessible to members.
+    pub fn members(&self) -> bool {
+        self.members
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::versionlist::{CrcList, MapIndex, VersionList};
+
+    #[test]
+    fn test_version_list_round_trip() {
+        let data = [0x00, 0x01, 0x12, 0x34, 0xFF, 0xFF];
+        let mut versions = VersionList::decode(&mut &data[..], data.len()).unwrap();
+        assert_eq!(
+            versions.iter().copied().collect::<Vec<u32>>(),
+            vec![1, 0x1234, 0xFFFF]
+        );
+        versions.set(4, 0x1_0002);
+        assert_eq!(
+            versions.encode().unwrap(),
+            vec![0x00, 0x01, 0x12, 0x34, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x02]
+        );
+    }
+
+    #[test]
+    fn test_crc_list_round_trip() {
+        let data = [0xDE, 0xAD, 0xBE, 0xEF, 0x00, 0x00, 0x00, 0x01];
+        let crcs = CrcList::decode(&mut &data[..], data.len()).unwrap();
+        assert_eq!(crcs.get(0), Some(&0xDEAD_BEEF));
+        assert_eq!(crcs.get(2), None);
+        assert_eq!(crcs.encode().unwrap(), data.to_vec());
+        assert_eq!(CrcList::checksum(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_map_index_lookup() {
+        let data = [
+            0x32, 0x32, 0x00, 0x10, 0x00, 0x11, 0x00, //
+            0x32, 0x35, 0x01, 0x20, 0x01, 0x21, 0x01,
+        ];
+        let map_index = MapIndex::decode(&mut &data[..], data.len()).unwrap();
+        let region = map_index.region(50, 53).unwrap();
+        assert_eq!(region.landscape_file_id(), 0x120);
+        assert_eq!(region.object_file_id(), 0x121);
+        assert!(region.members());
+        assert!(!map_index.region(50, 50).unwrap().members());
+        assert!(map_index.region(1, 1).is_none());
+        assert_eq!(map_index.encode().unwrap(), data.to_vec());
+    }
 }
@@ -0,0 +1,1292 @@
+use crate::archive::Archive;
+use crate::buffer::{BufferReadExt, BufferWriteExt};
+use crate::errors::FileSystemError;
+use crate::font::BitmapFont;
+use crate::sprite::Sprite;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::collections::HashMap;
+use std::io;
+
+/// The entry of the interface archive that holds every widget.
+pub const DATA_ENTRY_NAME: &str = "data";
+/// The value that announces a new parent widget in the data entry.
+pub const PARENT_MARKER: u16 = u16::MAX;
+/// The number of sprites an inventory widget can draw in its slots.
+pub const INVENTORY_SPRITE_COUNT: usize = 20;
+pub const ACTION_COUNT: usize = 5;
+/// The fonts text widgets can use, by their index. Each is stored as `<name>.dat` in the title
+/// archive.
+pub const FONT_NAMES: [&str; 4] = ["p11_full", "p12_full", "b12_full", "q8_full"];
+
+/// Every widget of the interface archive.
+///
+/// Widgets are kept in the order they are stored in, which groups them by the interface they
+/// belong to. Containers refer to their children by id, which makes up the tree of an interface.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WidgetTree {
+    capacity: u16,
+    widgets: Vec<Widget>,
+    positions: HashMap<u16, usize>,
+}
+
+/// A single widget. The fields shared by every type of widget are stored here, the rest
+/// belong to its `WidgetKind`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Widget {
+    pub id: u16,
+    /// The id of the root widget of the interface this widget belongs to.
+    pub parent_id: Option<u16>,
+    /// What happens when the widget is clicked, like 1 for a button with a tooltip, 2 for a
+    /// spell that is cast on something and 6 for a "Click here to continue" button.
+    pub action_type: u8,
+    /// The id of the code the client runs to fill in the widget, like the friends list.
+    pub content_type: u16,
+    pub width: u16,
+    pub height: u16,
+    pub alpha: u8,
+    /// The widget that is shown while the mouse is over this one.
+    pub hover_id: Option<u16>,
+    /// The comparison and the value every script result is compared with. The comparison is
+    /// 2 for less than, 3 for greater than, 4 for not equal and anything else for equal.
+    pub script_conditions: Vec<(u8, u16)>,
+    /// The CS1 scripts, as their raw opcodes and operands. See `Cs1Instruction::decode`.
+    pub scripts: Vec<Vec<u16>>,
+    pub kind: WidgetKind,
+    /// The spell the widget selects, stored for inventories and for the spell action type.
+    pub spell: Option<WidgetSpell>,
+    /// The tooltip as it is stored, for the action types that show one.
+    pub tooltip: Option<String>,
+}
+
+/// The part of a widget that depends on its type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WidgetKind {
+    /// Type 0, holds other widgets at a position relative to itself.
+    Container {
+        /// The height of the content of the container. It can be scrolled when this is larger
+        /// than the height of the container.
+        scroll_height: u16,
+        hidden_until_hovered: bool,
+        children: Vec<WidgetChild>,
+    },
+    /// Type 1, which the client reads but never draws.
+    Unused {
+        unknown_u16: u16,
+        unknown_u8: u8,
+        style: TextStyle,
+        color: u32,
+    },
+    /// Type 2, the slots of an inventory.
+    Inventory {
+        swappable: bool,
+        has_actions: bool,
+        usable: bool,
+        /// Whether moving an item onto another slot replaces the item in it, instead of
+        /// inserting the item before it.
+        replaces: bool,
+        padding_x: u8,
+        padding_y: u8,
+        sprites: Box<[Option<InventorySprite>; INVENTORY_SPRITE_COUNT]>,
+        actions: [Option<String>; ACTION_COUNT],
+    },
+    /// Type 3, a filled or outlined rectangle.
+    Rectangle { filled: bool, colors: WidgetColors },
+    /// Type 4, a line of text.
+    Text {
+        style: TextStyle,
+        text: String,
+        /// The text shown instead while a script of the widget holds.
+        enabled_text: String,
+        colors: WidgetColors,
+    },
+    /// Type 5, a sprite from the media archive.
+    Sprite {
+        sprite: Option<SpriteReference>,
+        /// The sprite shown instead while a script of the widget holds.
+        enabled_sprite: Option<SpriteReference>,
+    },
+    /// Type 6, a model from `IndexType::MODEL`.
+    Model {
+        model: Option<u16>,
+        enabled_model: Option<u16>,
+        /// The id of the sequence in `seq.dat` that animates the model.
+        animation: Option<u16>,
+        enabled_animation: Option<u16>,
+        zoom: u16,
+        rotation_x: u16,
+        rotation_y: u16,
+    },
+    /// Type 7, a grid of item names.
+    ItemList {
+        style: TextStyle,
+        color: u32,
+        padding_x: i16,
+        padding_y: i16,
+        has_actions: bool,
+        actions: [Option<String>; ACTION_COUNT],
+    },
+    /// Type 8, a box of text that follows the mouse.
+    Tooltip { text: String },
+    /// A type the client does not know. Only the fields shared by every widget are stored.
+    Unknown(u8),
+}
+
+/// A child of a container and its position relative to the container.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WidgetChild {
+    pub id: u16,
+    pub x: i16,
+    pub y: i16,
+}
+
+/// A sprite drawn in the slot of an inventory while it is empty.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InventorySprite {
+    pub x: i16,
+    pub y: i16,
+    pub sprite: Option<SpriteReference>,
+}
+
+/// The way text widgets draw their text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextStyle {
+    pub centered: bool,
+    /// The index of the font in `FONT_NAMES`.
+    pub font: u8,
+    pub shadowed: bool,
+}
+
+/// The colours of a widget as `0xRRGGBB`, while its scripts do not or do hold and while the mouse
+/// is over it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WidgetColors {
+    pub color: u32,
+    pub enabled_color: u32,
+    pub hover_color: u32,
+    pub enabled_hover_color: u32,
+}
+
+/// The spell a widget selects when it is clicked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WidgetSpell {
+    /// The action shown in front of the spell name, like "Cast".
+    pub selected_action: String,
+    pub name: String,
+    /// The kinds of targets the spell can be used on, one bit each for ground items, NPCs,
+    /// objects, players and inventory items.
+    pub usable_on: u16,
+}
+
+/// A reference to a sprite in the media archive, stored as `name,frame`.
+///
+/// The sprite is the frame of the archive entry `<name>.dat`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpriteReference {
+    pub name: String,
+    pub frame: u32,
+}
+
+impl SpriteReference {
+    pub fn new(name: &str, frame: u32) -> Self {
+        SpriteReference {
+            name: name.to_string(),
+            frame,
+        }
+    }
+
+    /// Parses a reference the way the client does, splitting it at the last comma.
+    pub fn parse(reference: &str) -> Result<Self, FileSystemError> {
+        let invalid = || FileSystemError::InvalidSpriteReference(reference.to_string());
+        let separator = reference.rfind(',').ok_or_else(invalid)?;
+        let frame = reference[separator + 1..].parse().map_err(|_| invalid())?;
+        Ok(SpriteReference::new(&reference[..separator], frame))
+    }
+
+    /// The name of the entry in the media archive that holds the sprite.
+    pub fn entry_name(&self) -> String {
+        format!("{}.dat", self.name)
+    }
+
+    /// Decodes the referenced frame from the media archive.
+    pub fn resolve(&self, media: &Archive) -> Result<Sprite, FileSystemError> {
+        media.sprite(&self.name, self.frame)
+    }
+}
+
+impl std::fmt::Display for SpriteReference {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{},{}", self.name, self.frame)
+    }
+}
+
+/// A single instruction of a CS1 script.
+///
+/// The client runs the instructions from left to right, adding the value of every instruction to
+/// the result unless an operator instruction came right before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cs1Instruction {
+    Return,
+    SkillLevel(u16),
+    MaxSkillLevel(u16),
+    SkillExperience(u16),
+    ItemCount {
+        widget_id: u16,
+        item_id: u16,
+    },
+    Varp(u16),
+    ExperienceForLevel(u16),
+    /// The value of a varp multiplied by 100 and divided by 46875.
+    VarpPercentage(u16),
+    CombatLevel,
+    TotalLevel,
+    /// 999999999 when the item is in the widget, 0 otherwise.
+    HasItem {
+        widget_id: u16,
+        item_id: u16,
+    },
+    RunEnergy,
+    Weight,
+    VarpBit {
+        varp_id: u16,
+        bit: u16,
+    },
+    Varbit(u16),
+    Subtract,
+    Divide,
+    Multiply,
+    PositionX,
+    PositionY,
+    Constant(u16),
+    /// An opcode the client does not know. It takes no operands and adds nothing.
+    Unknown(u16),
+}
+
+impl Cs1Instruction {
+    /// Splits a script into its instructions, ending at its `Return`. Returns `None` when an
+    /// instruction is missing its operands.
+    pub fn decode(script: &[u16]) -> Option<Vec<Cs1Instruction>> {
+        let mut instructions = Vec::new();
+        let mut operands = script.iter().copied();
+        while let Some(opcode) = operands.next() {
+            let mut operand = || operands.next();
+            let instruction = match opcode {
+                0 => Cs1Instruction::Return,
+                1 => Cs1Instruction::SkillLevel(operand()?),
+                2 => Cs1Instruction::MaxSkillLevel(operand()?),
+                3 => Cs1Instruction::SkillExperience(operand()?),
+                4 => Cs1Instruction::ItemCount {
+                    widget_id: operand()?,
+                    item_id: operand()?,
+                },
+                5 => Cs1Instruction::Varp(operand()?),
+                6 => Cs1Instruction::ExperienceForLevel(operand()?),
+                7 => Cs1Instruction::VarpPercentage(operand()?),
+                8 => Cs1Instruction::CombatLevel,
+                9 => Cs1Instruction::TotalLevel,
+                10 => Cs1Instruction::HasItem {
+                    widget_id: operand()?,
+                    item_id: operand()?,
+                },
+                11 => Cs1Instruction::RunEnergy,
+                12 => Cs1Instruction::Weight,
+                13 => Cs1Instruction::VarpBit {
+                    varp_id: operand()?,
+                    bit: operand()?,
+                },
+                14 => Cs1Instruction::Varbit(operand()?),
+                15 => Cs1Instruction::Subtract,
+                16 => Cs1Instruction::Divide,
+                17 => Cs1Instruction::Multiply,
+                18 => Cs1Instruction::PositionX,
+                19 => Cs1Instruction::PositionY,
+                20 => Cs1Instruction::Constant(operand()?),
+                _ => Cs1Instruction::Unknown(opcode),
+            };
+            instructions.push(instruction);
+            if instruction == Cs1Instruction::Return {
+                break;
+            }
+        }
+        Some(instructions)
+    }
+
+    /// Turns instructions back into the opcodes and operands of a script.
+    pub fn encode(instructions: &[Cs1Instruction]) -> Vec<u16> {
+        let mut script = Vec::new();
+        for instruction in instructions {
+            match *instruction {
+                Cs1Instruction::Return => script.push(0),
+                Cs1Instruction::SkillLevel(skill) => script.extend(&[1, skill]),
+                Cs1Instruction::MaxSkillLevel(skill) => script.extend(&[2, skill]),
+                Cs1Instruction::SkillExperience(skill) => script.extend(&[3, skill]),
+                Cs1Instruction::ItemCount { widget_id, item_id } => {
+                    script.extend(&[4, widget_id, item_id])
+                }
+                Cs1Instruction::Varp(varp_id) => script.extend(&[5, varp_id]),
+                Cs1Instruction::ExperienceForLevel(skill) => script.extend(&[6, skill]),
+                Cs1Instruction::VarpPercentage(varp_id) => script.extend(&[7, varp_id]),
+                Cs1Instruction::CombatLevel => script.push(8),
+                Cs1Instruction::TotalLevel => script.push(9),
+                Cs1Instruction::HasItem { widget_id, item_id } => {
+                    script.extend(&[10, widget_id, item_id])
+                }
+                Cs1Instruction::RunEnergy => script.push(11),
+                Cs1Instruction::Weight => script.push(12),
+                Cs1Instruction::VarpBit { varp_id, bit } => script.extend(&[13, varp_id, bit]),
+                Cs1Instruction::Varbit(varbit_id) => script.extend(&[14, varbit_id]),
+                Cs1Instruction::Subtract => script.push(15),
+                Cs1Instruction::Divide => script.push(16),
+                Cs1Instruction::Multiply => script.push(17),
+                Cs1Instruction::PositionX => script.push(18),
+                Cs1Instruction::PositionY => script.push(19),
+                Cs1Instruction::Constant(value) => script.extend(&[20, value]),
+                Cs1Instruction::Unknown(opcode) => script.push(opcode),
+            }
+        }
+        script
+    }
+}
+
+impl Widget {
+    pub fn new(id: u16, kind: WidgetKind) -> Self {
+        Widget {
+            id,
+            parent_id: None,
+            action_type: 0,
+            content_type: 0,
+            width: 0,
+            height: 0,
+            alpha: 0,
+            hover_id: None,
+            script_conditions: Vec::new(),
+            scripts: Vec::new(),
+            kind,
+            spell: None,
+            tooltip: None,
+        }
+    }
+
+    /// The type the widget is stored with.
+    pub fn widget_type(&self) -> u8 {
+        match self.kind {
+            WidgetKind::Container { .. } => 0,
+            WidgetKind::Unused { .. } => 1,
+            WidgetKind::Inventory { .. } => 2,
+            WidgetKind::Rectangle { .. } => 3,
+            WidgetKind::Text { .. } => 4,
+            WidgetKind::Sprite { .. } => 5,
+            WidgetKind::Model { .. } => 6,
+            WidgetKind::ItemList { .. } => 7,
+            WidgetKind::Tooltip { .. } => 8,
+            WidgetKind::Unknown(widget_type) => widget_type,
+        }
+    }
+
+    /// The tooltip the client shows, filling in the default of the action type when the stored
+    /// tooltip is empty.
+    pub fn tooltip_text(&self) -> Option<&str> {
+        let tooltip = self.tooltip.as_deref()?;
+        if !tooltip.is_empty() {
+            return Some(tooltip);
+        }
+        match self.action_type {
+            1 => Some("Ok"),
+            4 | 5 => Some("Select"),
+            6 => Some("Continue"),
+            _ => Some(tooltip),
+        }
+    }
+
+    /// The name of the title archive entry of the font the widget draws its text with.
+    pub fn font_entry_name(&self) -> Option<String> {
+        Some(format!("{}.dat", self.font_name()?))
+    }
+
+    /// Decodes the font the widget draws its text with from the title archive, or `None` for
+    /// widgets without text.
+    pub fn font(&self, title: &Archive) -> Result<Option<BitmapFont>, FileSystemError> {
+        match self.font_name() {
+            Some(name) => title.font(name).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn font_name(&self) -> Option<&'static str> {
+        let style = match &self.kind {
+            WidgetKind::Unused { style, .. }
+            | WidgetKind::Text { style, .. }
+            | WidgetKind::ItemList { style, .. } => style,
+            _ => return None,
+        };
+        FONT_NAMES.get(style.font as usize).copied()
+    }
+
+    fn has_spell(&self) -> bool {
+        self.action_type == 2 || self.widget_type() == 2
+    }
+
+    fn has_tooltip(&self) -> bool {
+        matches!(self.action_type, 1 | 4 | 5 | 6)
+    }
+
+    fn decode(
+        id: u16,
+        parent_id: Option<u16>,
+        buffer: &mut &[u8],
+    ) -> Result<Self, FileSystemError> {
+        let widget_type = buffer.read_u8()?;
+        let mut widget = Widget::new(id, WidgetKind::Unknown(widget_type));
+        widget.parent_id = parent_id;
+        widget.action_type = buffer.read_u8()?;
+        widget.content_type = buffer.read_u16::<BigEndian>()?;
+        widget.width = buffer.read_u16::<BigEndian>()?;
+        widget.height = buffer.read_u16::<BigEndian>()?;
+        widget.alpha = buffer.read_u8()?;
+        widget.hover_id = read_packed_id(buffer)?;
+        let condition_count = buffer.read_u8()?;
+        for _ in 0..condition_count {
+            let comparison = buffer.read_u8()?;
+            widget
+                .script_conditions
+                .push((comparison, buffer.read_u16::<BigEndian>()?));
+        }
+        let script_count = buffer.read_u8()?;
+        for _ in 0..script_count {
+            let length = buffer.read_u16::<BigEndian>()?;
+            let mut script = Vec::with_capacity(length as usize);
+            for _ in 0..length {
+                script.push(buffer.read_u16::<BigEndian>()?);
+            }
+            widget.scripts.push(script);
+        }
+        widget.kind = match widget_type {
+            0 => {
+                let scroll_height = buffer.read_u16::<BigEndian>()?;
+                let hidden_until_hovered = buffer.read_u8()? == 1;
+                let child_count = buffer.read_u16::<BigEndian>()?;
+                let mut children = Vec::with_capacity(child_count as usize);
+                for _ in 0..child_count {
+                    children.push(WidgetChild {
+                        id: buffer.read_u16::<BigEndian>()?,
+                        x: buffer.read_i16::<BigEndian>()?,
+                        y: buffer.read_i16::<BigEndian>()?,
+                    });
+                }
+                WidgetKind::Container {
+                    scroll_height,
+                    hidden_until_hovered,
+                    children,
+                }
+            }
+            1 => WidgetKind::Unused {
+                unknown_u16: buffer.read_u16::<BigEndian>()?,
+                unknown_u8: buffer.read_u8()?,
+                style: read_text_style(buffer)?,
+                color: buffer.read_u32::<BigEndian>()?,
+            },
+            2 => {
+                let swappable = buffer.read_u8()? == 1;
+                let has_actions = buffer.read_u8()? == 1;
+                let usable = buffer.read_u8()? == 1;
+                let replaces = buffer.read_u8()? == 1;
+                let padding_x = buffer.read_u8()?;
+                let padding_y = buffer.read_u8()?;
+                let mut sprites: Box<[Option<InventorySprite>; INVENTORY_SPRITE_COUNT]> =
+                    Default::default();
+                for sprite in sprites.iter_mut() {
+                    if buffer.read_u8()? == 1 {
+                        *sprite = Some(InventorySprite {
+                            x: buffer.read_i16::<BigEndian>()?,
+                            y: buffer.read_i16::<BigEndian>()?,
+                            sprite: read_sprite_reference(buffer)?,
+                        });
+                    }
+                }
+                WidgetKind::Inventory {
+                    swappable,
+                    has_actions,
+                    usable,
+                    replaces,
+                    padding_x,
+                    padding_y,
+                    sprites,
+                    actions: read_actions(buffer)?,
+                }
+            }
+            3 => WidgetKind::Rectangle {
+                filled: buffer.read_u8()? == 1,
+                colors: read_colors(buffer)?,
+            },
+            4 => WidgetKind::Text {
+                style: read_text_style(buffer)?,
+                text: buffer.read_string()?,
+                enabled_text: buffer.read_string()?,
+                colors: read_colors(buffer)?,
+            },
+            5 => WidgetKind::Sprite {
+                sprite: read_sprite_reference(buffer)?,
+                enabled_sprite: read_sprite_reference(buffer)?,
+            },
+            6 => WidgetKind::Model {
+                model: read_packed_id(buffer)?,
+                enabled_model: read_packed_id(buffer)?,
+                animation: read_packed_id(buffer)?,
+                enabled_animation: read_packed_id(buffer)?,
+                zoom: buffer.read_u16::<BigEndian>()?,
+                rotation_x: buffer.read_u16::<BigEndian>()?,
+                rotation_y: buffer.read_u16::<BigEndian>()?,
+            },
+            7 => WidgetKind::ItemList {
+                style: read_text_style(buffer)?,
+                color: buffer.read_u32::<BigEndian>()?,
+                padding_x: buffer.read_i16::<BigEndian>()?,
+                padding_y: buffer.read_i16::<BigEndian>()?,
+                has_actions: buffer.read_u8()? == 1,
+                actions: read_actions(buffer)?,
+            },
+            _ => WidgetKind::Unknown(widget_type),
+        };
+        if widget.has_spell() {
+            widget.spell = Some(WidgetSpell {
+                selected_action: buffer.read_string()?,
+                name: buffer.read_string()?,
+                usable_on: buffer.read_u16::<BigEndian>()?,
+            });
+        }
+        if widget_type == 8 {
+            widget.kind = WidgetKind::Tooltip {
+                text: buffer.read_string()?,
+            };
+        }
+        if widget.has_tooltip() {
+            widget.tooltip = Some(buffer.read_string()?);
+        }
+        Ok(widget)
+    }
+
+    // checks that a list of the widget fits in the bytes its length is stored in
+    fn count(
+        &self,
+        count: usize,
+        max: usize,
+        element: &'static str,
+    ) -> Result<usize, FileSystemError> {
+        if count > max {
+            return Err(FileSystemError::WidgetTooLarge {
+                widget: self.id,
+                element,
+                count,
+                max,
+            });
+        }
+        Ok(count)
+    }
+
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), FileSystemError> {
+        buffer.write_u8(self.widget_type())?;
+        buffer.write_u8(self.action_type)?;
+        buffer.write_u16::<BigEndian>(self.content_type)?;
+        buffer.write_u16::<BigEndian>(self.width)?;
+        buffer.write_u16::<BigEndian>(self.height)?;
+        buffer.write_u8(self.alpha)?;
+        write_packed_id(buffer, self.hover_id)?;
+        let conditions = self.count(
+            self.script_conditions.len(),
+            u8::MAX as usize,
+            "script conditions",
+        )?;
+        buffer.write_u8(conditions as u8)?;
+        for (comparison, value) in &self.script_conditions {
+            buffer.write_u8(*comparison)?;
+            buffer.write_u16::<BigEndian>(*value)?;
+        }
+        buffer.write_u8(self.count(self.scripts.len(), u8::MAX as usize, "scripts")? as u8)?;
+        for script in &self.scripts {
+            let length = self.count(script.len(), u16::MAX as usize, "script instructions")?;
+            buffer.write_u16::<BigEndian>(length as u16)?;
+            for value in script {
+                buffer.write_u16::<BigEndian>(*value)?;
+            }
+        }
+        match &self.kind {
+            WidgetKind::Container {
+                scroll_height,
+                hidden_until_hovered,
+                children,
+            } => {
+                buffer.write_u16::<BigEndian>(*scroll_height)?;
+                buffer.write_u8(*hidden_until_hovered as u8)?;
+                let count = self.count(children.len(), u16::MAX as usize, "children")?;
+                buffer.write_u16::<BigEndian>(count as u16)?;
+                for child in children {
+                    buffer.write_u16::<BigEndian>(child.id)?;
+                    buffer.write_i16::<BigEndian>(child.x)?;
+                    buffer.write_i16::<BigEndian>(child.y)?;
+                }
+            }
+            WidgetKind::Unused {
+                unknown_u16,
+                unknown_u8,
+                style,
+                color,
+            } => {
+                buffer.write_u16::<BigEndian>(*unknown_u16)?;
+                buffer.write_u8(*unknown_u8)?;
+                write_text_style(buffer, style)?;
+                buffer.write_u32::<BigEndian>(*color)?;
+            }
+            WidgetKind::Inventory {
+                swappable,
+                has_actions,
+                usable,
+                replaces,
+                padding_x,
+                padding_y,
+                sprites,
+                actions,
+            } => {
+                buffer.write_u8(*swappable as u8)?;
+                buffer.write_u8(*has_actions as u8)?;
+                buffer.write_u8(*usable as u8)?;
+                buffer.write_u8(*replaces as u8)?;
+                buffer.write_u8(*padding_x)?;
+                buffer.write_u8(*padding_y)?;
+                for sprite in sprites.iter() {
+                    match sprite {
+                        Some(sprite) => {
+                            buffer.write_u8(1)?;
+                            buffer.write_i16::<BigEndian>(sprite.x)?;
+                            buffer.write_i16::<BigEndian>(sprite.y)?;
+                            write_sprite_reference(buffer, &sprite.sprite)?;
+                        }
+                        None => buffer.write_u8(0)?,
+                    }
+                }
+                write_actions(buffer, actions)?;
+            }
+            WidgetKind::Rectangle { filled, colors } => {
+                buffer.write_u8(*filled as u8)?;
+                write_colors(buffer, colors)?;
+            }
+            WidgetKind::Text {
+                style,
+                text,
+                enabled_text,
+                colors,
+            } => {
+                write_text_style(buffer, style)?;
+                buffer.write_string(text)?;
+                buffer.write_string(enabled_text)?;
+                write_colors(buffer, colors)?;
+            }
+            WidgetKind::Sprite {
+                sprite,
+                enabled_sprite,
+            } => {
+                write_sprite_reference(buffer, sprite)?;
+                write_sprite_reference(buffer, enabled_sprite)?;
+            }
+            WidgetKind::Model {
+                model,
+                enabled_model,
+                animation,
+                enabled_animation,
+                zoom,
+                rotation_x,
+                rotation_y,
+            } => {
+                for id in [model, enabled_model, animation, enabled_animation].iter() {
+                    write_packed_id(buffer, **id)?;
+                }
+                buffer.write_u16::<BigEndian>(*zoom)?;
+                buffer.write_u16::<BigEndian>(*rotation_x)?;
+                buffer.write_u16::<BigEndian>(*rotation_y)?;
+            }
+            WidgetKind::ItemList {
+                style,
+                color,
+                padding_x,
+                padding_y,
+                has_actions,
+                actions,
+            } => {
+                write_text_style(buffer, style)?;
+                buffer.write_u32::<BigEndian>(*color)?;
+                buffer.write_i16::<BigEndian>(*padding_x)?;
+                buffer.write_i16::<BigEndian>(*padding_y)?;
+                buffer.write_u8(*has_actions as u8)?;
+                write_actions(buffer, actions)?;
+            }
+            WidgetKind::Tooltip { .. } | WidgetKind::Unknown(_) => {}
+        }
+        if self.has_spell() {
+            let spell = self.spell.clone().unwrap_or(WidgetSpell {
+                selected_action: String::new(),
+                name: String::new(),
+                usable_on: 0,
+            });
+            buffer.write_string(&spell.selected_action)?;
+            buffer.write_string(&spell.name)?;
+            buffer.write_u16::<BigEndian>(spell.usable_on)?;
+        }
+        if let WidgetKind::Tooltip { text } = &self.kind {
+            buffer.write_string(text)?;
+        }
+        if self.has_tooltip() {
+            buffer.write_string(self.tooltip.as_deref().unwrap_or(""))?;
+        }
+        Ok(())
+    }
+}
+
+impl WidgetTree {
+    pub fn new() -> Self {
+        WidgetTree {
+            capacity: 0,
+            widgets: Vec::new(),
+            positions: HashMap::new(),
+        }
+    }
+
+    /// Decodes every widget from the `data` entry of the interface archive.
+    pub fn decode(archive: &Archive) -> Result<Self, FileSystemError> {
+        let mut buffer = match archive.entry_name(DATA_ENTRY_NAME) {
+            Some(entry) => entry.uncompressed_data(),
+            None => {
+                return Err(FileSystemError::ArchiveEntryNotFound(
+                    DATA_ENTRY_NAME.to_string(),
+                ))
+            }
+        };
+        let mut tree = WidgetTree::new();
+        tree.capacity = buffer.read_u16::<BigEndian>()?;
+        let mut parent_id = None;
+        while !buffer.is_empty() {
+            let mut id = buffer.read_u16::<BigEndian>()?;
+            if id == PARENT_MARKER {
+                parent_id = Some(buffer.read_u16::<BigEndian>()?);
+                id = buffer.read_u16::<BigEndian>()?;
+            }
+            tree.insert(Widget::decode(id, parent_id, &mut buffer)?);
+        }
+        Ok(tree)
+    }
+
+    /// Encodes every widget into the `data` entry of the interface archive, replacing the
+    /// widgets that were stored before.
+    pub fn encode(&self, archive: &mut Archive) -> Result<(), FileSystemError> {
+        let mut buffer = Vec::new();
+        buffer.write_u16::<BigEndian>(self.capacity)?;
+        let mut parent_id = None;
+        for widget in &self.widgets {
+            if widget.parent_id != parent_id {
+                // the client never forgets a parent, so a widget without one can only come first
+                buffer.write_u16::<BigEndian>(PARENT_MARKER)?;
+                buffer.write_u16::<BigEndian>(widget.parent_id.unwrap_or(widget.id))?;
+                parent_id = widget.parent_id;
+            }
+            buffer.write_u16::<BigEndian>(widget.id)?;
+            widget.encode(&mut buffer)?;
+        }
+        archive.insert(DATA_ENTRY_NAME, buffer);
+        Ok(())
+    }
+
+    /// The number of widget ids the client makes room for, at least one more than the highest id.
+    pub fn capacity(&self) -> u16 {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.widgets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.widgets.is_empty()
+    }
+
+    pub fn get(&self, id: u16) -> Option<&Widget> {
+        self.positions
+            .get(&id)
+            .map(|position| &self.widgets[*position])
+    }
+
+    pub fn get_mut(&mut self, id: u16) -> Option<&mut Widget> {
+        let position = *self.positions.get(&id)?;
+        Some(&mut self.widgets[position])
+    }
+
+    /// Adds a widget after the widgets already in the tree, or replaces the widget with the
+    /// same id in place.
+    pub fn insert(&mut self, widget: Widget) -> Option<Widget> {
+        match self.positions.get(&widget.id) {
+            Some(position) => Some(std::mem::replace(&mut self.widgets[*position], widget)),
+            None => {
+                self.capacity = self.capacity.max(widget.id.saturating_add(1));
+                self.positions.insert(widget.id, self.widgets.len());
+                self.widgets.push(widget);
+                None
+            }
+        }
+    }
+
+    /// The widgets in the order they are stored in.
+    pub fn widgets(&self) -> impl Iterator<Item = &Widget> {
+        self.widgets.iter()
+    }
+
+    /// The root widgets of the interfaces, which are their own parents.
+    pub fn roots(&self) -> impl Iterator<Item = &Widget> {
+        self.widgets
+            .iter()
+            .filter(|widget| widget.parent_id == Some(widget.id))
+    }
+
+    /// The children of a container with their positions. Children that are not in the tree are
+    /// left out.
+    pub fn children(&self, id: u16) -> Vec<(&Widget, i16, i16)> {
+        match self.get(id).map(|widget| &widget.kind) {
+            Some(WidgetKind::Container { children, .. }) => children
+                .iter()
+                .filter_map(|child| Some((self.get(child.id)?, child.x, child.y)))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl Default for WidgetTree {
+    fn default() -> Self {
+        WidgetTree::new()
+    }
+}
+
+// ids of up to 0xFEFF stored in one or two bytes, where a first byte of 0 means none
+fn read_packed_id(buffer: &mut &[u8]) -> Result<Option<u16>, FileSystemError> {
+    let high = buffer.read_u8()?;
+    if high == 0 {
+        return Ok(None);
+    }
+    let low = buffer.read_u8()?;
+    Ok(Some(((high as u16 - 1) << 8) + low as u16))
+}
+
+fn write_packed_id(buffer: &mut Vec<u8>, id: Option<u16>) -> Result<(), FileSystemError> {
+    match id {
+        Some(id) if id > 0xFEFF => Err(FileSystemError::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("id {} is too large for a widget", id),
+        ))),
+        Some(id) => {
+            buffer.write_u8((id >> 8) as u8 + 1)?;
+            buffer.write_u8(id as u8)?;
+            Ok(())
+        }
+        None => Ok(buffer.write_u8(0)?),
+    }
+}
+
+fn read_text_style(buffer: &mut &[u8]) -> Result<TextStyle, FileSystemError> {
+    Ok(TextStyle {
+        centered: buffer.read_u8()? == 1,
+        font: buffer.read_u8()?,
+        shadowed: buffer.read_u8()? == 1,
+    })
+}
+
+fn write_text_style(buffer: &mut Vec<u8>, style: &TextStyle) -> Result<(), FileSystemError> {
+    buffer.write_u8(style.centered as u8)?;
+    buffer.write_u8(style.font)?;
+    buffer.write_u8(style.shadowed as u8)?;
+    Ok(())
+}
+
+fn read_colors(buffer: &mut &[u8]) -> Result<WidgetColors, FileSystemError> {
+    Ok(WidgetColors {
+        color: buffer.read_u32::<BigEndian>()?,
+        enabled_color: buffer.read_u32::<BigEndian>()?,
+        hover_color: buffer.read_u32::<BigEndian>()?,
+        enabled_hover_color: buffer.read_u32::<BigEndian>()?,
+    })
+}
+
+fn write_colors(buffer: &mut Vec<u8>, colors: &WidgetColors) -> Result<(), FileSystemError> {
+    buffer.write_u32::<BigEndian>(colors.color)?;
+    buffer.write_u32::<BigEndian>(colors.enabled_color)?;
+    buffer.write_u32::<BigEndian>(colors.hover_color)?;
+    buffer.write_u32::<BigEndian>(colors.enabled_hover_color)?;
+    Ok(())
+}
+
+fn read_sprite_reference(buffer: &mut &[u8]) -> Result<Option<SpriteReference>, FileSystemError> {
+    let reference = buffer.read_string()?;
+    if reference.is_empty() {
+        return Ok(None);
+    }
+    SpriteReference::parse(&reference).map(Some)
+}
+
+fn write_sprite_reference(
+    buffer: &mut Vec<u8>,
+    reference: &Option<SpriteReference>,
+) -> Result<(), FileSystemError> {
+    match reference {
+        Some(reference) => buffer.write_string(&reference.to_string())?,
+        None => buffer.write_string("")?,
+    }
+    Ok(())
+}
+
+// empty actions are stored as empty strings
+fn read_actions(buffer: &mut &[u8]) -> Result<[Option<String>; ACTION_COUNT], FileSystemError> {
+    let mut actions: [Option<String>; ACTION_COUNT] = Default::default();
+    for action in actions.iter_mut() {
+        let text = buffer.read_string()?;
+        if !text.is_empty() {
+            *action = Some(text);
+        }
+    }
+    Ok(actions)
+}
+
+fn write_actions(
+    buffer: &mut Vec<u8>,
+    actions: &[Option<String>; ACTION_COUNT],
+) -> Result<(), FileSystemError> {
+    for action in actions.iter() {
+        buffer.write_string(action.as_deref().unwrap_or(""))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::archive::{Archive, ArchiveCompression};
+    use crate::errors::FileSystemError;
+    use crate::font::GLYPH_COUNT;
+    use crate::sprite::{IndexedSprite, PixelOrder, Sprite, INDEX_ENTRY_NAME};
+    use crate::widget::{
+        Cs1Instruction, InventorySprite, SpriteReference, TextStyle, Widget, WidgetChild,
+        WidgetColors, WidgetKind, WidgetSpell, WidgetTree, DATA_ENTRY_NAME,
+    };
+
+    fn text_style() -> TextStyle {
+        TextStyle {
+            centered: true,
+            font: 2,
+            shadowed: true,
+        }
+    }
+
+    fn colors() -> WidgetColors {
+        WidgetColors {
+            color: 0xFF981F,
+            enabled_color: 0x00FF00,
+            hover_color: 0xFFFFFF,
+            enabled_hover_color: 0,
+        }
+    }
+
+    fn interface() -> WidgetTree {
+        let root_children = (1..8)
+            .map(|id| WidgetChild {
+                id,
+                x: id as i16 * 10,
+                y: -(id as i16),
+            })
+            .collect();
+        let mut sprites: Box<[Option<InventorySprite>; 20]> = Default::default();
+        sprites[3] = Some(InventorySprite {
+            x: 4,
+            y: -4,
+            sprite: Some(SpriteReference::new("wornicons", 3)),
+        });
+        let kinds = vec![
+            WidgetKind::Container {
+                scroll_height: 300,
+                hidden_until_hovered: false,
+                children: root_children,
+            },
+            WidgetKind::Inventory {
+                swappable: true,
+                has_actions: true,
+                usable: false,
+                replaces: true,
+                padding_x: 10,
+                padding_y: 4,
+                sprites,
+                actions: [
+                    Some("Wield".to_string()),
+                    None,
+                    None,
+                    None,
+                    Some("Drop".to_string()),
+                ],
+            },
+            WidgetKind::Rectangle {
+                filled: true,
+                colors: colors(),
+            },
+            WidgetKind::Text {
+                style: text_style(),
+                text: "Welcome to RuneScape".to_string(),
+                enabled_text: String::new(),
+                colors: colors(),
+            },
+            WidgetKind::Sprite {
+                sprite: Some(SpriteReference::new("miscgraphics", 0)),
+                enabled_sprite: None,
+            },
+            WidgetKind::Model {
+                model: Some(2000),
+                enabled_model: None,
+                animation: Some(0xFEFF),
+                enabled_animation: None,
+                zoom: 800,
+                rotation_x: 150,
+                rotation_y: 0,
+            },
+            WidgetKind::ItemList {
+                style: text_style(),
+                color: 0xFFFF00,
+                padding_x: -3,
+                padding_y: 2,
+                has_actions: false,
+                actions: Default::default(),
+            },
+            WidgetKind::Tooltip {
+                text: "Hint".to_string(),
+            },
+        ];
+        let mut tree = WidgetTree::new();
+        for (id, kind) in kinds.into_iter().enumerate() {
+            let mut widget = Widget::new(id as u16, kind);
+            widget.parent_id = Some(0);
+            widget.width = 190;
+            widget.height = 261;
+            tree.insert(widget);
+        }
+        let button = tree.get_mut(3).unwrap();
+        button.action_type = 1;
+        button.tooltip = Some(String::new());
+        button.hover_id = Some(4);
+        button.script_conditions = vec![(3, 40)];
+        button.scripts = vec![vec![1, 5, 15, 20, 2, 0]];
+        let spell = tree.get_mut(4).unwrap();
+        spell.action_type = 2;
+        spell.spell = Some(WidgetSpell {
+            selected_action: "Cast On".to_string(),
+            name: "Wind Strike".to_string(),
+            usable_on: 2,
+        });
+        tree.get_mut(1).unwrap().spell = Some(WidgetSpell {
+            selected_action: "Use".to_string(),
+            name: String::new(),
+            usable_on: 16,
+        });
+        let mut other = Widget::new(20, WidgetKind::Unknown(9));
+        other.parent_id = Some(20);
+        tree.insert(other);
+        tree
+    }
+
+    #[test]
+    fn test_widget_round_trip() {
+        let tree = interface();
+        let mut archive = Archive::new(ArchiveCompression::Whole);
+        tree.encode(&mut archive).unwrap();
+        let data = archive
+            .entry_name(DATA_ENTRY_NAME)
+            .unwrap()
+            .uncompressed_data();
+        assert_eq!(data[..2], [0, 21]);
+        let decoded = WidgetTree::decode(&archive).unwrap();
+        assert_eq!(decoded, tree);
+        assert_eq!(decoded.len(), 9);
+        let roots: Vec<u16> = decoded.roots().map(|widget| widget.id).collect();
+        assert_eq!(roots, vec![0, 20]);
+        let children = decoded.children(0);
+        assert_eq!(children.len(), 7);
+        assert_eq!(
+            (children[2].0.id, children[2].1, children[2].2),
+            (3, 30, -3)
+        );
+        let button = decoded.get(3).unwrap();
+        assert_eq!(button.tooltip_text(), Some("Ok"));
+        assert_eq!(button.font_entry_name().as_deref(), Some("b12_full.dat"));
+        assert_eq!(button.widget_type(), 4);
+    }
+
+    #[test]
+    fn test_widget_fonts() {
+        let glyph = IndexedSprite {
+            max_width: 1,
+            max_height: 1,
+            offset_x: 0,
+            offset_y: 0,
+            width: 1,
+            height: 1,
+            pixel_order: PixelOrder::Horizontal,
+            palette: vec![0, 0xFFFFFF],
+            pixels: vec![1],
+        };
+        let mut title = Archive::new(ArchiveCompression::Whole);
+        title.insert(INDEX_ENTRY_NAME, Vec::new());
+        title
+            .write_indexed_sprites("b12_full", &vec![glyph; GLYPH_COUNT])
+            .unwrap();
+        let tree = interface();
+        let font = tree.get(3).unwrap().font(&title).unwrap().unwrap();
+        assert_eq!(font.glyphs.len(), GLYPH_COUNT);
+        assert_eq!(tree.get(2).unwrap().font(&title).unwrap(), None);
+        let empty = Archive::new(ArchiveCompression::Whole);
+        assert!(tree.get(3).unwrap().font(&empty).is_err());
+    }
+
+    #[test]
+    fn test_container_bytes() {
+        #[rustfmt::skip]
+        let data = vec![
+            0x00, 0x02,
+            0xFF, 0xFF, 0x00, 0x01, 0x00, 0x01,
+            0, 0, 0x00, 0x00, 0x00, 0x10, 0x00, 0x20, 0, 0, 0, 0,
+            0x00, 0x20, 0, 0x00, 0x01, 0x00, 0x00, 0xFF, 0xFB, 0xFF, 0xFF,
+        ];
+        let mut archive = Archive::new(ArchiveCompression::Whole);
+        archive.insert(DATA_ENTRY_NAME, data.clone());
+        let tree = WidgetTree::decode(&archive).unwrap();
+        let widget = tree.get(1).unwrap();
+        assert_eq!(widget.parent_id, Some(1));
+        assert_eq!((widget.width, widget.height), (16, 32));
+        match &widget.kind {
+            WidgetKind::Container { children, .. } => {
+                assert_eq!(
+                    children,
+                    &vec![WidgetChild {
+                        id: 0,
+                        x: -5,
+                        y: -1
+                    }]
+                )
+            }
+            kind => panic!("unexpected widget kind {:?}", kind),
+        }
+        let mut encoded = Archive::new(ArchiveCompression::Whole);
+        tree.encode(&mut encoded).unwrap();
+        let encoded = encoded
+            .entry_name(DATA_ENTRY_NAME)
+            .unwrap()
+            .uncompressed_data();
+        assert_eq!(encoded, &data[..]);
+    }
+
+    #[test]
+    fn test_widgets_too_large() {
+        let encode = |widget: Widget| {
+            let mut tree = WidgetTree::new();
+            tree.insert(widget);
+            tree.encode(&mut Archive::new(ArchiveCompression::Whole))
+        };
+        let mut widget = Widget::new(
+            0,
+            WidgetKind::Tooltip {
+                text: String::new(),
+            },
+        );
+        widget.script_conditions = vec![(0, 0); 256];
+        assert!(matches!(
+            encode(widget.clone()),
+            Err(FileSystemError::WidgetTooLarge {
+                count: 256,
+                max: 255,
+                ..
+            })
+        ));
+        widget.script_conditions = Vec::new();
+        widget.scripts = vec![vec![0; 65536]];
+        assert!(matches!(
+            encode(widget.clone()),
+            Err(FileSystemError::WidgetTooLarge { count: 65536, .. })
+        ));
+        widget.scripts = vec![Vec::new(); 255];
+        assert!(encode(widget).is_ok());
+
+        let children = vec![WidgetChild { id: 1, x: 0, y: 0 }; 65536];
+        let container = Widget::new(
+            7,
+            WidgetKind::Container {
+                scroll_height: 0,
+                hidden_until_hovered: false,
+                children,
+            },
+        );
+        assert!(matches!(
+            encode(container),
+            Err(FileSystemError::WidgetTooLarge {
+                widget: 7,
+                element: "children",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_cs1_instructions() {
+        let script = [1, 5, 15, 13, 300, 4, 20, 7, 99, 0];
+        let instructions = Cs1Instruction::decode(&script).unwrap();
+        assert_eq!(
+            instructions,
+            vec![
+                Cs1Instruction::SkillLevel(5),
+                Cs1Instruction::Subtract,
+                Cs1Instruction::VarpBit {
+                    varp_id: 300,
+                    bit: 4
+                },
+                Cs1Instruction::Constant(7),
+                Cs1Instruction::Unknown(99),
+                Cs1Instruction::Return,
+            ]
+        );
+        assert_eq!(Cs1Instruction::encode(&instructions), script);
+        assert_eq!(Cs1Instruction::decode(&[4, 1]), None);
+    }
+
+    #[test]
+    fn test_sprite_references() {
+        let reference = SpriteReference::parse("mod,icons,2").unwrap();
+        assert_eq!(reference, SpriteReference::new("mod,icons", 2));
+        assert_eq!(reference.entry_name(), "mod,icons.dat");
+        assert!(SpriteReference::parse("miscgraphics").is_err());
+    }
+
+    #[test]
+    fn test_sprite_reference_resolving() {
+        let frame = Sprite {
+            max_width: 1,
+            max_height: 1,
+            offset_x: 0,
+            offset_y: 0,
+            width: 1,
+            height: 1,
+            pixel_order: PixelOrder::Horizontal,
+            pixels: vec![0xFF0000],
+        };
+        let mut media = Archive::new(ArchiveCompression::Whole);
+        media.insert(INDEX_ENTRY_NAME, Vec::new());
+        media
+            .write_sprites("mod,icons", std::slice::from_ref(&frame))
+            .unwrap();
+        assert_eq!(
+            SpriteReference::new("mod,icons", 0)
+                .resolve(&media)
+                .unwrap(),
+            frame
+        );
+        assert!(SpriteReference::new("mod,icons", 1)
+            .resolve(&media)
+            .is_err());
+        assert!(SpriteReference::new("redstone", 0).resolve(&media).is_err());
+    }
+}